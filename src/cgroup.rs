@@ -0,0 +1,370 @@
+//! Cgroup v2 hierarchies for workers, so that a collector attributing events
+//! to containers via cgroup paths sees the workload coming from a number of
+//! distinct containers. Every workload group gets its own set of cgroups,
+//! workers are spread over them by process number and move themselves in
+//! right after they're forked. Optionally cgroups are named the same way as
+//! kubelet names pods and containers.
+//!
+//! Directories missing in the hierarchy are created together with enabling
+//! the controllers needed for limits, and removed when the run is over.
+//! Existing directories are joined and left as they are.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How many times to try removing a cgroup, while processes in it are dying.
+const REMOVE_ATTEMPTS: usize = 100;
+
+const REMOVE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How cgroups of a workload group are named.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Naming {
+    /// Named after the workload group, numbered if there are many of them,
+    /// it's the default.
+    #[default]
+    Plain,
+
+    /// As with cgroupfs driver of kubelet, e.g.
+    /// `kubepods/besteffort/pod<uid>/<container id>`.
+    Kubepods,
+
+    /// As with systemd driver of kubelet, e.g.
+    /// `kubepods.slice/kubepods-besteffort.slice/
+    /// kubepods-besteffort-pod<uid>.slice/cri-containerd-<container id>.scope`.
+    KubepodsSystemd,
+}
+
+/// Cgroups to run workers of a group in.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CgroupConfig {
+    /// Directory in the cgroup v2 hierarchy to create cgroups under.
+    #[serde(default = "default_parent")]
+    pub parent: String,
+
+    #[serde(default)]
+    pub naming: Naming,
+
+    /// How many cgroups to spread workers of the group over.
+    #[serde(default = "default_count")]
+    pub count: usize,
+
+    /// Value for `cpu.max`, e.g. "50000 100000" for half of a CPU.
+    #[serde(default)]
+    pub cpu_max: Option<String>,
+
+    /// Value for `memory.max` in bytes, optionally with K, M or G suffix.
+    #[serde(default)]
+    pub memory_max: Option<String>,
+
+    #[serde(default)]
+    pub pids_max: Option<u64>,
+}
+
+fn default_parent() -> String {
+    String::from("/sys/fs/cgroup/berserker")
+}
+
+fn default_count() -> usize {
+    1
+}
+
+impl CgroupConfig {
+    /// Interface files of the limits and their values.
+    fn limits(&self) -> Vec<(&'static str, String)> {
+        [
+            ("cpu.max", self.cpu_max.clone()),
+            ("memory.max", self.memory_max.clone()),
+            ("pids.max", self.pids_max.map(|pids| pids.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(file, value)| Some((file, value?)))
+        .collect()
+    }
+
+    /// Controllers needed for the limits.
+    fn controllers(&self) -> Vec<&'static str> {
+        self.limits()
+            .iter()
+            .filter_map(|(file, _)| file.split('.').next())
+            .collect()
+    }
+
+    /// Paths of cgroups for a workload group, random ids are taken from
+    /// `rng`.
+    pub fn paths(&self, group: &str, rng: &mut impl Rng) -> Vec<PathBuf> {
+        let parent = Path::new(&self.parent);
+
+        // Pods with limits are not best effort anymore
+        let qos = if self.limits().is_empty() {
+            "besteffort"
+        } else {
+            "burstable"
+        };
+
+        (0..self.count)
+            .map(|i| match self.naming {
+                Naming::Plain if self.count == 1 => parent.join(group),
+                Naming::Plain => parent.join(format!("{group}-{i}")),
+                Naming::Kubepods => parent
+                    .join("kubepods")
+                    .join(qos)
+                    .join(format!("pod{}", uid(rng)))
+                    .join(container_id(rng)),
+                Naming::KubepodsSystemd => parent
+                    .join("kubepods.slice")
+                    .join(format!("kubepods-{qos}.slice"))
+                    .join(format!(
+                        "kubepods-{qos}-pod{}.slice",
+                        uid(rng).replace('-', "_")
+                    ))
+                    .join(format!(
+                        "cri-containerd-{}.scope",
+                        container_id(rng)
+                    )),
+            })
+            .collect()
+    }
+}
+
+/// Pod UID in the UUID format.
+fn uid(rng: &mut impl Rng) -> String {
+    let hex = hex(rng, 16);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Container ID, 64 hex digits as with containerd and docker.
+fn container_id(rng: &mut impl Rng) -> String {
+    hex(rng, 32)
+}
+
+fn hex(rng: &mut impl Rng, bytes: usize) -> String {
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.r#gen::<u8>()))
+        .collect()
+}
+
+/// Cgroups of the run, those created by it are removed when it's dropped.
+#[derive(Debug, Default)]
+pub struct Cgroups {
+    /// Created directories, parents go first.
+    created: Vec<PathBuf>,
+
+    /// Configuration and cgroups of every workload group by name.
+    groups: HashMap<String, (CgroupConfig, Vec<PathBuf>)>,
+}
+
+impl Cgroups {
+    /// Cgroups for workers of a workload group, prepared the first time
+    /// they're needed, or when the configuration has changed.
+    pub fn prepare(
+        &mut self,
+        group: &str,
+        config: &CgroupConfig,
+        rng: &mut impl Rng,
+    ) -> Result<Vec<PathBuf>, String> {
+        if let Some((prepared, paths)) = self.groups.get(group)
+            && prepared == config
+        {
+            return Ok(paths.clone());
+        }
+
+        let paths = config.paths(group, rng);
+        for path in paths.iter() {
+            self.create(path, config)
+                .map_err(|e| format!("Cannot prepare cgroup {path:?}: {e}"))?;
+        }
+
+        info!("Group {group} in cgroups {paths:?}");
+        self.groups
+            .insert(group.to_string(), (config.clone(), paths.clone()));
+        Ok(paths)
+    }
+
+    /// Create the cgroup together with missing parents, enable controllers
+    /// on the way down from the root of the hierarchy and set the limits.
+    fn create(&mut self, path: &Path, config: &CgroupConfig) -> io::Result<()> {
+        let mut ancestors: Vec<_> = path.ancestors().skip(1).collect();
+        ancestors.reverse();
+
+        let controllers = config.controllers();
+
+        for dir in ancestors {
+            if !dir.exists() {
+                fs::create_dir(dir)?;
+                self.created.push(dir.to_path_buf());
+            }
+
+            // Not a cgroup yet, e.g. a directory above the mount point
+            if !dir.join("cgroup.controllers").exists() {
+                continue;
+            }
+
+            enable(dir, &controllers)?;
+        }
+
+        if !path.exists() {
+            fs::create_dir(path)?;
+            self.created.push(path.to_path_buf());
+        }
+
+        for (file, value) in config.limits() {
+            fs::write(path.join(file), &value).map_err(|e| {
+                io::Error::new(e.kind(), format!("cannot set {file}: {e}"))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove created cgroups, killing whatever is left in them, e.g.
+    /// processes spawned by workers.
+    pub fn remove(&mut self) {
+        for path in self.created.drain(..).rev() {
+            let _ = fs::write(path.join("cgroup.kill"), "1");
+
+            let mut attempts = 0;
+            while let Err(e) = fs::remove_dir(&path) {
+                attempts += 1;
+
+                if e.kind() == io::ErrorKind::NotFound {
+                    break;
+                }
+
+                if attempts == REMOVE_ATTEMPTS {
+                    warn!("Cannot remove cgroup {path:?}: {e}");
+                    break;
+                }
+
+                thread::sleep(REMOVE_INTERVAL);
+            }
+        }
+
+        self.groups.clear();
+    }
+}
+
+impl Drop for Cgroups {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Enable controllers for children of the cgroup, if not enabled yet.
+fn enable(dir: &Path, controllers: &[&str]) -> io::Result<()> {
+    let enabled = fs::read_to_string(dir.join("cgroup.subtree_control"))?;
+    let missing: Vec<_> = controllers
+        .iter()
+        .filter(|c| !enabled.split_whitespace().any(|e| e == **c))
+        .map(|c| format!("+{c}"))
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    fs::write(dir.join("cgroup.subtree_control"), missing.join(" ")).map_err(
+        |e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot enable {} in {dir:?}: {e}", missing.join(" ")),
+            )
+        },
+    )
+}
+
+/// Move the current process into the cgroup. Zero stands for the writer,
+/// which works the same way inside of a pid namespace.
+pub fn join(path: &Path) -> io::Result<()> {
+    fs::write(path.join("cgroup.procs"), "0")
+}
+
+/// Whether the value is fine for `cpu.max`: a quota in microseconds or
+/// "max", optionally followed by a period.
+pub fn valid_cpu_max(value: &str) -> bool {
+    match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [quota] => *quota == "max" || quota.parse::<u64>().is_ok(),
+        [quota, period] => {
+            (*quota == "max" || quota.parse::<u64>().is_ok())
+                && period.parse::<u64>().is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// Whether the value is fine for `memory.max`: "max" or a number of bytes,
+/// optionally with K, M or G suffix.
+pub fn valid_memory_max(value: &str) -> bool {
+    let number = value.trim_end_matches(['K', 'M', 'G', 'k', 'm', 'g']);
+    value == "max" || (!number.is_empty() && number.parse::<u64>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_cgroup_paths() {
+        let mut config = CgroupConfig {
+            parent: String::from("/sys/fs/cgroup/test"),
+            naming: Naming::Plain,
+            count: 2,
+            cpu_max: None,
+            memory_max: None,
+            pids_max: Some(100),
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+
+        assert_eq!(
+            config.paths("syscalls", &mut rng),
+            vec![
+                PathBuf::from("/sys/fs/cgroup/test/syscalls-0"),
+                PathBuf::from("/sys/fs/cgroup/test/syscalls-1")
+            ]
+        );
+        assert_eq!(config.controllers(), vec!["pids"]);
+
+        config.naming = Naming::KubepodsSystemd;
+        let path = config.paths("syscalls", &mut rng)[0]
+            .to_string_lossy()
+            .into_owned();
+        let parts: Vec<_> = path.split('/').collect();
+
+        assert_eq!(parts[5], "kubepods.slice");
+        assert_eq!(parts[6], "kubepods-burstable.slice");
+        assert!(parts[7].starts_with("kubepods-burstable-pod"), "{path}");
+        assert_eq!(parts[7].len(), "kubepods-burstable-pod.slice".len() + 36);
+        assert!(parts[8].starts_with("cri-containerd-"), "{path}");
+        assert_eq!(parts[8].len(), "cri-containerd-.scope".len() + 64);
+
+        // The same seed gives the same containers
+        let mut again = StdRng::seed_from_u64(1);
+        assert_eq!(
+            config.paths("syscalls", &mut again)[0].to_string_lossy(),
+            path
+        );
+
+        assert!(valid_cpu_max("50000 100000"));
+        assert!(valid_cpu_max("max"));
+        assert!(!valid_cpu_max("half"));
+        assert!(valid_memory_max("512M"));
+        assert!(!valid_memory_max("M"));
+    }
+}
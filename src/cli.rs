@@ -0,0 +1,450 @@
+//! Command line interface. Arguments are parsed by hand into a `Command`,
+//! which is then executed by the main binary.
+
+use std::fmt::Display;
+
+/// Exit code for failures happened while running the workload.
+pub const EXIT_RUNTIME_ERROR: i32 = 1;
+
+/// Exit code for invalid command line arguments.
+pub const EXIT_USAGE_ERROR: i32 = 2;
+
+/// Exit code for invalid or missing configuration.
+pub const EXIT_CONFIG_ERROR: i32 = 3;
+
+/// Configuration file to use if nothing is specified, it's fine if it
+/// doesn't exist.
+pub const DEFAULT_CONFIG: &str = "workload.toml";
+
+pub const USAGE: &str = "\
+Usage: berserker [COMMAND] [OPTIONS] [CONFIG]
+
+Commands:
+  run <CONFIG>        Run the workload (default)
+  compare <CONFIG>    Compare runs without and with a monitor
+  validate <CONFIG>   Parse and check the configuration without running it
+  verify <EVENTS> <OBSERVED>
+                      Match the event log of a run with events a monitor has
+                      reported, in JSON lines
+  list <WHAT>         List supported syscalls, iouring or workloads
+  help                Print this message
+
+Options:
+  -c, --config <PATH>       Configuration file, the same as CONFIG
+  -d, --duration <SECONDS>  Override the run duration
+  -w, --workers <NUMBER>    Override the number of workers for all groups
+  -l, --log-level <LEVEL>   Log level, e.g. info or debug, overrides RUST_LOG
+  -s, --seed <NUMBER>       Seed for random decisions, to reproduce a run
+  -m, --metrics <ADDRESS>   Serve live metrics on the address, e.g. 127.0.0.1:9100
+  -t, --target <PID|COMM>   Sample resource usage of the process during the run
+  --control <PATH>          Accept commands on a Unix socket during the run
+  -h, --help                Print this message
+
+Compare options:
+  --monitor-cmd <COMMAND>   Shell command started for every monitored run
+  --monitor-pid <PID>       Running monitor, stopped during baseline runs
+  --rounds <NUMBER>         Number of baseline and monitored run pairs (default 1)
+  --warmup <SECONDS>        Wait for the monitor to start up (default 5)
+
+Verify options:
+  --mapping <PATH>          How to read the monitor events, TOML (default: the
+                            format of the event log)
+  --window <MILLISECONDS>   Override how late an event could be reported";
+
+/// What to list via `list` command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListKind {
+    Syscalls,
+    IOUring,
+    Workloads,
+}
+
+/// Options shared between commands that need a configuration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    /// Path to the configuration file, which has to exist. `DEFAULT_CONFIG`
+    /// is used if not specified.
+    pub config: Option<String>,
+
+    /// Overrides `duration` from the configuration.
+    pub duration: Option<u64>,
+
+    /// Overrides `workers` from the configuration, for all groups.
+    pub workers: Option<usize>,
+
+    /// Log level filter in `RUST_LOG` format.
+    pub log_level: Option<String>,
+
+    /// Overrides `seed` from the configuration.
+    pub seed: Option<u64>,
+
+    /// Address to serve metrics on, overrides `metrics.address`.
+    pub metrics: Option<String>,
+
+    /// Pid or command name of the process to sample, overrides `target`.
+    pub target: Option<String>,
+
+    /// Path of the control socket, overrides `control.socket`.
+    pub control: Option<String>,
+}
+
+/// The process, which overhead is measured by `compare` command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorSpec {
+    Command(String),
+    Pid(i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareOptions {
+    pub monitor: MonitorSpec,
+
+    /// How many times to repeat both baseline and monitored runs.
+    pub rounds: usize,
+
+    /// Seconds to wait after resuming the monitor before a monitored run.
+    pub warmup: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyOptions {
+    /// Event log written by a run.
+    pub events: String,
+
+    /// Events reported by the monitor.
+    pub observed: String,
+
+    /// Field mapping of the monitor events.
+    pub mapping: Option<String>,
+
+    /// Overrides `window` of the mapping, in milliseconds.
+    pub window: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run(Options),
+    Compare(Options, CompareOptions),
+    Validate(Options),
+    Verify(VerifyOptions),
+    List(ListKind),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UsageError(String);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parse command line arguments, without the program name. For backward
+/// compatibility a configuration path without any command means `run`.
+pub fn parse<I>(args: I) -> Result<Command, UsageError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+
+    let command = match args.peek().map(|s| s.as_str()) {
+        Some("run") | Some("compare") | Some("validate") | Some("verify")
+        | Some("list") | Some("help") => args.next().unwrap(),
+        _ => String::from("run"),
+    };
+
+    match command.as_str() {
+        "help" => Ok(Command::Help),
+        "list" => {
+            let kind = match args.next().as_deref() {
+                Some("syscalls") => ListKind::Syscalls,
+                Some("iouring") => ListKind::IOUring,
+                Some("workloads") => ListKind::Workloads,
+                Some(other) => {
+                    return Err(UsageError(format!(
+                        "unknown list kind '{other}'"
+                    )));
+                }
+                None => {
+                    return Err(UsageError(String::from(
+                        "list requires one of: syscalls, iouring, workloads",
+                    )));
+                }
+            };
+
+            if let Some(arg) = args.next() {
+                return Err(UsageError(format!("unexpected argument '{arg}'")));
+            }
+
+            Ok(Command::List(kind))
+        }
+        "verify" => {
+            let mut files = vec![];
+            let mut mapping = None;
+            let mut window = None;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-h" | "--help" => return Ok(Command::Help),
+                    "--mapping" => mapping = Some(value(&arg, args.next())?),
+                    "--window" => {
+                        window = Some(number(&arg, value(&arg, args.next())?)?);
+                    }
+                    _ if arg.starts_with('-') => {
+                        return Err(UsageError(format!(
+                            "unknown option '{arg}'"
+                        )));
+                    }
+                    _ if files.len() == 2 => {
+                        return Err(UsageError(format!(
+                            "unexpected argument '{arg}'"
+                        )));
+                    }
+                    _ => files.push(arg),
+                }
+            }
+
+            let [events, observed]: [String; 2] =
+                files.try_into().map_err(|_| {
+                    UsageError(String::from(
+                        "verify requires the event log and monitor events",
+                    ))
+                })?;
+
+            Ok(Command::Verify(VerifyOptions {
+                events,
+                observed,
+                mapping,
+                window,
+            }))
+        }
+        _ => {
+            let mut options = Options::default();
+            let mut config = None;
+
+            // Options of compare command
+            let mut monitor = None;
+            let mut rounds = 1;
+            let mut warmup = 5;
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-h" | "--help" => return Ok(Command::Help),
+                    "-c" | "--config" => {
+                        config = Some(value(&arg, args.next())?);
+                    }
+                    "-d" | "--duration" => {
+                        options.duration =
+                            Some(number(&arg, value(&arg, args.next())?)?);
+                    }
+                    "-w" | "--workers" => {
+                        options.workers =
+                            Some(number(&arg, value(&arg, args.next())?)?);
+                    }
+                    "-l" | "--log-level" => {
+                        options.log_level = Some(value(&arg, args.next())?);
+                    }
+                    "-s" | "--seed" => {
+                        options.seed =
+                            Some(number(&arg, value(&arg, args.next())?)?);
+                    }
+                    "-m" | "--metrics" => {
+                        options.metrics = Some(value(&arg, args.next())?);
+                    }
+                    "-t" | "--target" => {
+                        options.target = Some(value(&arg, args.next())?);
+                    }
+                    "--control" => {
+                        options.control = Some(value(&arg, args.next())?);
+                    }
+                    "--monitor-cmd" if command == "compare" => {
+                        monitor = Some(MonitorSpec::Command(value(
+                            &arg,
+                            args.next(),
+                        )?));
+                    }
+                    "--monitor-pid" if command == "compare" => {
+                        monitor = Some(MonitorSpec::Pid(number(
+                            &arg,
+                            value(&arg, args.next())?,
+                        )?));
+                    }
+                    "--rounds" if command == "compare" => {
+                        rounds = number(&arg, value(&arg, args.next())?)?;
+                        if rounds == 0 {
+                            return Err(UsageError(String::from(
+                                "at least one round needed",
+                            )));
+                        }
+                    }
+                    "--warmup" if command == "compare" => {
+                        warmup = number(&arg, value(&arg, args.next())?)?;
+                    }
+                    _ if arg.starts_with('-') => {
+                        return Err(UsageError(format!(
+                            "unknown option '{arg}'"
+                        )));
+                    }
+                    _ => {
+                        if config.is_some() {
+                            return Err(UsageError(format!(
+                                "unexpected argument '{arg}'"
+                            )));
+                        }
+                        config = Some(arg);
+                    }
+                }
+            }
+
+            options.config = config;
+
+            match command.as_str() {
+                "validate" => Ok(Command::Validate(options)),
+                "compare" => {
+                    let monitor = monitor.ok_or_else(|| {
+                        UsageError(String::from(
+                            "compare requires --monitor-cmd or --monitor-pid",
+                        ))
+                    })?;
+
+                    Ok(Command::Compare(
+                        options,
+                        CompareOptions {
+                            monitor,
+                            rounds,
+                            warmup,
+                        },
+                    ))
+                }
+                _ => Ok(Command::Run(options)),
+            }
+        }
+    }
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, UsageError> {
+    value.ok_or_else(|| UsageError(format!("option '{option}' needs a value")))
+}
+
+fn number<T: std::str::FromStr>(
+    option: &str,
+    value: String,
+) -> Result<T, UsageError> {
+    value.parse().map_err(|_| {
+        UsageError(format!("option '{option}' expects a number, got '{value}'"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(args("")), Ok(Command::Run(Options::default())));
+
+        // Bare configuration path is still supported
+        assert_eq!(
+            parse(args("workload.server.toml")),
+            Ok(Command::Run(Options {
+                config: Some("workload.server.toml".to_string()),
+                ..Options::default()
+            }))
+        );
+
+        assert_eq!(
+            parse(args("run -c /etc/berserker/network.toml -d 60 -w 4 -s 7")),
+            Ok(Command::Run(Options {
+                config: Some("/etc/berserker/network.toml".to_string()),
+                duration: Some(60),
+                workers: Some(4),
+                log_level: None,
+                seed: Some(7),
+                metrics: None,
+                target: None,
+                control: None,
+            }))
+        );
+
+        assert_eq!(
+            parse(args("validate --log-level debug x.toml")),
+            Ok(Command::Validate(Options {
+                config: Some("x.toml".to_string()),
+                log_level: Some("debug".to_string()),
+                ..Options::default()
+            }))
+        );
+
+        assert_eq!(
+            parse(args("run -m 127.0.0.1:9100")),
+            Ok(Command::Run(Options {
+                metrics: Some("127.0.0.1:9100".to_string()),
+                ..Options::default()
+            }))
+        );
+
+        assert_eq!(
+            parse(args("run --target falco")),
+            Ok(Command::Run(Options {
+                target: Some("falco".to_string()),
+                ..Options::default()
+            }))
+        );
+
+        assert_eq!(
+            parse(args("run --control /tmp/berserker.sock")),
+            Ok(Command::Run(Options {
+                control: Some("/tmp/berserker.sock".to_string()),
+                ..Options::default()
+            }))
+        );
+
+        assert_eq!(
+            parse(args("compare -d 30 --monitor-pid 42 --rounds 5 x.toml")),
+            Ok(Command::Compare(
+                Options {
+                    config: Some("x.toml".to_string()),
+                    duration: Some(30),
+                    ..Options::default()
+                },
+                CompareOptions {
+                    monitor: MonitorSpec::Pid(42),
+                    rounds: 5,
+                    warmup: 5,
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse(args("verify events.jsonl falco.jsonl --mapping falco.toml")),
+            Ok(Command::Verify(VerifyOptions {
+                events: "events.jsonl".to_string(),
+                observed: "falco.jsonl".to_string(),
+                mapping: Some("falco.toml".to_string()),
+                window: None,
+            }))
+        );
+
+        assert_eq!(
+            parse(args("list syscalls")),
+            Ok(Command::List(ListKind::Syscalls))
+        );
+        assert_eq!(parse(args("run --help")), Ok(Command::Help));
+
+        assert!(parse(args("list")).is_err());
+        assert!(parse(args("run -d soon")).is_err());
+        assert!(parse(args("run -w")).is_err());
+        assert!(parse(args("run --verbose")).is_err());
+        assert!(parse(args("run a.toml b.toml")).is_err());
+        assert!(parse(args("run --rounds 3")).is_err());
+        assert!(parse(args("compare x.toml")).is_err());
+        assert!(parse(args("verify events.jsonl")).is_err());
+        assert!(parse(args("verify a b c")).is_err());
+        assert!(parse(args("compare --monitor-cmd falco --rounds 0")).is_err());
+    }
+}
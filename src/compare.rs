@@ -0,0 +1,298 @@
+//! A/B comparison of the same workload without and with a monitor, to find
+//! out how much overhead the monitor adds. Baseline and monitored runs are
+//! interleaved in rounds, so that a slow drift of the machine state affects
+//! both of them. Differences between the two are reported with a 95%
+//! confidence interval of Welch's t-test over the rounds.
+
+use std::fmt::Display;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+
+use crate::histogram::format_nanos;
+use crate::stats::{GroupSummary, Summary};
+
+/// The process, which overhead is measured.
+#[derive(Debug)]
+pub enum Monitor {
+    /// Shell command started for every monitored run and terminated after
+    /// it.
+    Command {
+        command: String,
+        child: Option<Child>,
+    },
+
+    /// Already running process, which is stopped with SIGSTOP for baseline
+    /// runs. Anything the monitor has attached in the kernel stays in place,
+    /// only processing of the events is paused.
+    Pid(Pid),
+}
+
+impl Monitor {
+    pub fn command(command: &str) -> Self {
+        Monitor::Command {
+            command: command.to_string(),
+            child: None,
+        }
+    }
+
+    pub fn pid(pid: i32) -> Self {
+        Monitor::Pid(Pid::from_raw(pid))
+    }
+
+    /// Let the monitor run.
+    pub fn resume(&mut self) -> Result<(), String> {
+        match self {
+            Monitor::Command { command, child } => {
+                // A separate process group to terminate whatever the shell
+                // has started
+                let spawned = Command::new("sh")
+                    .arg("-c")
+                    .arg(command.as_str())
+                    .process_group(0)
+                    .spawn()
+                    .map_err(|e| format!("Cannot start '{command}': {e}"))?;
+
+                *child = Some(spawned);
+                Ok(())
+            }
+            Monitor::Pid(pid) => kill(*pid, Signal::SIGCONT)
+                .map_err(|e| format!("Cannot resume {pid}: {e}")),
+        }
+    }
+
+    /// Take the monitor out of the way for a baseline run. Fails if the
+    /// monitor has exited on its own in the meantime.
+    pub fn pause(&mut self) -> Result<(), String> {
+        match self {
+            Monitor::Command { command, child } => {
+                let Some(mut child) = child.take() else {
+                    return Ok(());
+                };
+
+                // Workers supervisor might have reaped the process already
+                if !matches!(child.try_wait(), Ok(None)) {
+                    return Err(format!(
+                        "'{command}' has exited during the run"
+                    ));
+                }
+
+                let group = Pid::from_raw(-(child.id() as i32));
+                kill(group, Signal::SIGTERM)
+                    .map_err(|e| format!("Cannot stop '{command}': {e}"))?;
+                let _ = child.wait();
+                Ok(())
+            }
+            Monitor::Pid(pid) => kill(*pid, Signal::SIGSTOP)
+                .map_err(|e| format!("Cannot stop {pid}: {e}")),
+        }
+    }
+
+    /// Leave the monitor in the state it was found in: a started command is
+    /// terminated, an existing process keeps running.
+    pub fn finish(&mut self) -> Result<(), String> {
+        match self {
+            Monitor::Command { .. } => self.pause(),
+            Monitor::Pid(_) => self.resume(),
+        }
+    }
+}
+
+impl Display for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Monitor::Command { command, .. } => write!(f, "'{command}'"),
+            Monitor::Pid(pid) => write!(f, "pid {pid}"),
+        }
+    }
+}
+
+/// Value of a group compared between runs, with a function to get it from a
+/// run summary, and one to format it.
+type Metric = (
+    &'static str,
+    fn(&Summary, &GroupSummary) -> Option<f64>,
+    fn(f64) -> String,
+);
+
+const METRICS: &[Metric] = &[
+    (
+        "rate",
+        |s, g| Some(s.rate(&g.stats)),
+        |v| format!("{v:.1}/s"),
+    ),
+    ("p50", |_, g| latency(g, 0.5), nanos),
+    ("p90", |_, g| latency(g, 0.9), nanos),
+    ("p99", |_, g| latency(g, 0.99), nanos),
+    ("p99.9", |_, g| latency(g, 0.999), nanos),
+];
+
+fn latency(group: &GroupSummary, quantile: f64) -> Option<f64> {
+    (group.latency.count() > 0).then(|| group.latency.value_at(quantile) as f64)
+}
+
+fn nanos(value: f64) -> String {
+    let formatted = format_nanos(value.abs().round() as u64);
+    if value < 0.0 {
+        format!("-{formatted}")
+    } else {
+        formatted
+    }
+}
+
+/// Summaries of all the baseline and monitored runs.
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    pub baseline: Vec<Summary>,
+    pub monitored: Vec<Summary>,
+}
+
+impl Comparison {
+    /// Values of a metric for a group in each of the runs.
+    fn values(
+        runs: &[Summary],
+        group: &str,
+        metric: fn(&Summary, &GroupSummary) -> Option<f64>,
+    ) -> Vec<f64> {
+        runs.iter()
+            .filter_map(|run| {
+                let summary = run.groups.iter().find(|g| g.name == group)?;
+                metric(run, summary)
+            })
+            .collect()
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Rounds: {}, confidence intervals: 95%",
+            self.baseline.len().min(self.monitored.len())
+        )?;
+        writeln!(
+            f,
+            "{:<16} {:<6} {:>12} {:>12} {:>12} {:>9} {:>12}",
+            "Group",
+            "Metric",
+            "Baseline",
+            "Monitored",
+            "Delta",
+            "Delta %",
+            "CI"
+        )?;
+
+        let groups = self.baseline.first().map(|s| s.groups.as_slice());
+
+        for group in groups.unwrap_or_default() {
+            for (name, metric, format) in METRICS {
+                let baseline =
+                    Self::values(&self.baseline, &group.name, *metric);
+                let monitored =
+                    Self::values(&self.monitored, &group.name, *metric);
+
+                if baseline.is_empty() || monitored.is_empty() {
+                    continue;
+                }
+
+                let (before, after) = (mean(&baseline), mean(&monitored));
+                let delta = after - before;
+                let relative = if before != 0.0 {
+                    format!("{:+.1}%", delta / before * 100.0)
+                } else {
+                    String::from("-")
+                };
+                let interval = welch_interval(&baseline, &monitored)
+                    .map(|ci| format!("±{}", format(ci)))
+                    .unwrap_or(String::from("-"));
+
+                writeln!(
+                    f,
+                    "{:<16} {:<6} {:>12} {:>12} {:>12} {:>9} {:>12}",
+                    group.name,
+                    name,
+                    format(before),
+                    format(after),
+                    format(delta),
+                    relative,
+                    interval
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Unbiased sample variance.
+fn variance(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>()
+        / (values.len() - 1) as f64
+}
+
+/// Half-width of 95% confidence interval for the difference of means of two
+/// samples with possibly different variances. At least two values in each
+/// sample are needed.
+pub fn welch_interval(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let va = variance(a) / a.len() as f64;
+    let vb = variance(b) / b.len() as f64;
+
+    if va + vb == 0.0 {
+        return Some(0.0);
+    }
+
+    // Welch–Satterthwaite degrees of freedom
+    let df = (va + vb).powi(2)
+        / (va.powi(2) / (a.len() - 1) as f64
+            + vb.powi(2) / (b.len() - 1) as f64);
+
+    Some(t_critical(df) * (va + vb).sqrt())
+}
+
+/// Two-sided 95% critical value of Student's t-distribution. Fractional
+/// degrees of freedom are rounded down, which makes the interval slightly
+/// wider.
+fn t_critical(df: f64) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+        2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+        2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+    ];
+
+    match df.floor() as usize {
+        0 => TABLE[0],
+        df @ 1..=30 => TABLE[df - 1],
+        31..=60 => 2.021,
+        61..=120 => 2.000,
+        _ => 1.960,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_welch_interval() {
+        assert_eq!(welch_interval(&[1.0], &[2.0, 3.0]), None);
+        assert_eq!(welch_interval(&[1.0, 1.0], &[2.0, 2.0]), Some(0.0));
+
+        // Equal variances of 1 and sizes of 5, df = 8
+        let a = [1.0, 2.0, 3.0, 2.5, 1.5];
+        let b = [11.0, 12.0, 13.0, 12.5, 11.5];
+        let expected = 2.306 * (0.625f64 / 5.0 * 2.0).sqrt();
+        let ci = welch_interval(&a, &b).unwrap();
+        assert!((ci - expected).abs() < 1e-9, "{ci}");
+    }
+}
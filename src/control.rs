@@ -0,0 +1,359 @@
+//! Control of a run in progress via a Unix socket, e.g. to double the rate
+//! of syscalls while watching a monitor, without restarting anything. Every
+//! line sent to the socket is a command, answered with a single line:
+//!
+//! * `pause` stops all workers with SIGSTOP, and `resume` continues them.
+//! * `set-rate <group> <rate>` replaces the arrival rate of a group.
+//! * `status` reports the state of the run as JSON.
+//! * `stop` finishes the run as if its duration was over.
+//!
+//! Commands are relayed to the supervisor, which owns the worker processes.
+//! Workers are retuned via a control block in memory shared with them, the
+//! same way as their statistics are collected.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+use log::{info, warn};
+use serde_json::json;
+
+use crate::WorkloadConfig;
+use crate::stats::{Registry, StatsSnapshot, shared_zeroed, target_rate};
+
+/// Runtime controls of a workload group, shared between the parent and the
+/// workers of the group.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Control {
+    /// Arrival rate set at runtime as `f64` bits, zero if not set.
+    rate: AtomicU64,
+
+    /// Arrival rate of the workload from the reloaded configuration, the
+    /// same way as `rate`.
+    base: AtomicU64,
+
+    /// How many times workers were resumed after a pause.
+    resumes: AtomicU64,
+}
+
+impl Control {
+    /// Allocate controls in a memory region, that will be shared with forked
+    /// processes. Nothing is overridden initially.
+    pub fn shared() -> &'static Control {
+        // Zeros stand for no rates and no resumes
+        unsafe { shared_zeroed() }
+    }
+
+    /// Arrival rate set at runtime, if any.
+    pub fn rate(&self) -> Option<f64> {
+        rate(&self.rate)
+    }
+
+    /// Override the arrival rate, it has to be positive.
+    pub fn set_rate(&self, rate: f64) {
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Arrival rate of the workload changed by reloading the configuration,
+    /// if any.
+    pub fn base(&self) -> Option<f64> {
+        rate(&self.base)
+    }
+
+    pub fn set_base(&self, rate: Option<f64>) {
+        let bits = rate.map(f64::to_bits).unwrap_or_default();
+        self.base.store(bits, Ordering::Relaxed);
+    }
+
+    pub fn resumes(&self) -> u64 {
+        self.resumes.load(Ordering::Relaxed)
+    }
+
+    /// Let workers know they were paused, before they continue.
+    pub fn resumed(&self) {
+        self.resumes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Rate stored as `f64` bits, zero stands for no rate.
+fn rate(bits: &AtomicU64) -> Option<f64> {
+    let bits = bits.load(Ordering::Relaxed);
+    (bits != 0).then(|| f64::from_bits(bits))
+}
+
+/// A command sent to the control socket.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Pause,
+    Resume,
+    SetRate { group: String, rate: f64 },
+    Status,
+    Stop,
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<_> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["pause"] => Ok(Request::Pause),
+            ["resume"] => Ok(Request::Resume),
+            ["status"] => Ok(Request::Status),
+            ["stop"] => Ok(Request::Stop),
+            ["set-rate", group, rate] => {
+                let rate = rate
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|rate| rate.is_finite() && *rate > 0.0)
+                    .ok_or_else(|| {
+                        format!("rate must be a positive number, got '{rate}'")
+                    })?;
+
+                Ok(Request::SetRate {
+                    group: group.to_string(),
+                    rate,
+                })
+            }
+            ["set-rate", ..] => {
+                Err(String::from("usage: set-rate <group> <rate>"))
+            }
+            [] => Err(String::from("empty command")),
+            [command, ..] => Err(format!("unknown command '{command}'")),
+        }
+    }
+}
+
+/// Outcome of a request, sent back as `ok [...]` or `error: ...`.
+pub type Reply = Result<String, String>;
+
+/// A request waiting to be handled, with where to send the reply.
+pub struct Pending {
+    pub request: Request,
+    reply: Sender<Reply>,
+}
+
+impl Pending {
+    pub fn reply(self, reply: Reply) {
+        // The client might have gone already
+        let _ = self.reply.send(reply);
+    }
+}
+
+/// Workload group as seen by the controller.
+pub struct ControlledGroup {
+    pub name: String,
+
+    /// Configuration of the group workers, with the shared controls.
+    pub config: WorkloadConfig,
+}
+
+/// Receiving end of the control socket, held by the supervisor.
+pub struct Controller {
+    path: String,
+    requests: Receiver<Pending>,
+    groups: Vec<ControlledGroup>,
+    registry: Registry,
+    start: Instant,
+}
+
+impl Controller {
+    /// Start accepting commands on the socket at `path` in a separate
+    /// thread. An existing file at the path is replaced.
+    pub fn serve(
+        path: &str,
+        groups: Vec<ControlledGroup>,
+        registry: Registry,
+    ) -> io::Result<Controller> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        info!("Accepting commands on {path}");
+
+        let (sender, requests) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sender = sender.clone();
+
+                match stream {
+                    Ok(stream) => {
+                        thread::spawn(move || {
+                            if let Err(e) = session(stream, sender) {
+                                warn!("Control connection failed: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Cannot accept control connection: {e}"),
+                }
+            }
+        });
+
+        Ok(Controller {
+            path: path.to_string(),
+            requests,
+            groups,
+            registry,
+            start: Instant::now(),
+        })
+    }
+
+    /// Next request waiting to be handled, if any.
+    pub fn next(&self) -> Option<Pending> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Override the arrival rate of a group.
+    pub fn set_rate(&self, name: &str, rate: f64) -> Reply {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.name == name)
+            .ok_or_else(|| format!("unknown group '{name}'"))?;
+
+        let paced = group
+            .config
+            .workload
+            .as_ref()
+            .and_then(target_rate)
+            .is_some();
+
+        match group.config.runtime {
+            Some(control) if paced => {
+                control.set_rate(rate);
+                info!("Arrival rate of {name} is set to {rate}");
+                Ok(String::new())
+            }
+            _ => Err(format!("group '{name}' has no arrival rate")),
+        }
+    }
+
+    /// Replace the groups after the configuration was reloaded.
+    pub fn set_groups(&mut self, groups: Vec<ControlledGroup>) {
+        self.groups = groups;
+    }
+
+    /// Tell workers of all groups that they were paused.
+    pub fn resumed(&self) {
+        for control in self.groups.iter().filter_map(|g| g.config.runtime) {
+            control.resumed();
+        }
+    }
+
+    /// State of the run as a JSON object: `state` of the supervisor, number
+    /// of running `workers`, and the rate in effect and counters of every
+    /// group.
+    pub fn status(&self, state: &str, workers: usize) -> String {
+        let sources = self.registry.sources();
+
+        let groups: Vec<_> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let mut stats = StatsSnapshot::default();
+                for source in sources.iter().filter(|s| s.group == group.name) {
+                    stats.merge(&source.stats.snapshot());
+                }
+
+                let rate = group
+                    .config
+                    .workload
+                    .as_ref()
+                    .and_then(target_rate)
+                    .map(|base| group.config.arrival_rate(base));
+
+                json!({
+                    "name": group.name,
+                    "rate": rate,
+                    "ops": stats.ops,
+                    "errors": stats.errors,
+                    "missed": stats.missed,
+                })
+            })
+            .collect();
+
+        json!({
+            "state": state,
+            "elapsed": self.start.elapsed().as_secs_f64(),
+            "workers": workers,
+            "groups": groups,
+        })
+        .to_string()
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Relay commands of one client until it disconnects.
+fn session(stream: UnixStream, requests: Sender<Pending>) -> io::Result<()> {
+    let mut writer = &stream;
+
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+
+        let reply = line.parse::<Request>().and_then(|request| {
+            let (reply, receiver) = mpsc::channel();
+            requests
+                .send(Pending { request, reply })
+                .map_err(|_| String::from("the run is over"))?;
+
+            receiver
+                .recv()
+                .unwrap_or_else(|_| Err(String::from("the run is over")))
+        });
+
+        match reply {
+            Ok(text) if text.is_empty() => writeln!(writer, "ok")?,
+            Ok(text) => writeln!(writer, "ok {text}")?,
+            Err(e) => writeln!(writer, "error: {e}")?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests() {
+        assert_eq!("pause".parse(), Ok(Request::Pause));
+        assert_eq!(" status \n".parse(), Ok(Request::Status));
+        assert_eq!(
+            "set-rate syscalls 2000".parse(),
+            Ok(Request::SetRate {
+                group: String::from("syscalls"),
+                rate: 2000.0
+            })
+        );
+        assert!("set-rate syscalls".parse::<Request>().is_err());
+        assert!("set-rate syscalls 0".parse::<Request>().is_err());
+        assert!("set-rate syscalls fast".parse::<Request>().is_err());
+        assert!("restart".parse::<Request>().is_err());
+
+        let control = Control::shared();
+        assert_eq!(control.rate(), None);
+        control.set_rate(0.5);
+        assert_eq!(control.rate(), Some(0.5));
+
+        control.set_base(Some(10.0));
+        assert_eq!(control.base(), Some(10.0));
+        control.set_base(None);
+        assert_eq!(control.base(), None);
+    }
+}
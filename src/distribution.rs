@@ -0,0 +1,302 @@
+//! Probability distributions for numeric workload parameters, e.g. number
+//! of ports to listen on, process and connection lifetimes or payload sizes.
+//! A distribution is described in the configuration by the `distribution`
+//! key naming the family, plus the family parameters next to it.
+
+use std::fs;
+use std::io;
+
+use rand::Rng;
+use rand::distributions::WeightedIndex;
+use rand_distr::{Exp, LogNormal, Normal, Pareto, Uniform, Zipf};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "distribution")]
+pub enum Distribution {
+    /// Always the same value.
+    Constant {
+        value: f64,
+    },
+
+    /// Every value in `[lower, upper)` is equally likely.
+    #[serde(alias = "Uniform")]
+    Uniform {
+        lower: f64,
+        upper: f64,
+    },
+
+    /// Exponential distribution with the mean of `1 / rate`.
+    #[serde(rename = "exp", alias = "exponential")]
+    Exponential {
+        rate: f64,
+    },
+
+    Normal {
+        mean: f64,
+        std_dev: f64,
+    },
+
+    /// Distribution of `exp(X)`, where `X` is normally distributed with mean
+    /// `mu` and standard deviation `sigma`.
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+    },
+
+    /// Heavy tailed distribution, values start at `scale`.
+    Pareto {
+        scale: f64,
+        shape: f64,
+    },
+
+    /// Values in `[1, n]`, where few values are very frequent and the rest
+    /// are rare.
+    #[serde(rename = "zipf", alias = "Zipfian")]
+    Zipfian {
+        #[serde(alias = "n_ports")]
+        n: u64,
+        exponent: f64,
+    },
+
+    /// Values with weights read from a file, see `Empirical::load`.
+    Empirical {
+        path: String,
+    },
+}
+
+impl Distribution {
+    /// Prepare for sampling, checking the parameters and loading the
+    /// empirical histogram if needed.
+    pub fn sampler(&self) -> io::Result<Sampler> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+        };
+
+        let sampler = match *self {
+            Distribution::Constant { value } => Sampler::Constant(value),
+            Distribution::Uniform { lower, upper } => {
+                if !(lower < upper && lower.is_finite() && upper.is_finite()) {
+                    return Err(invalid(&format!(
+                        "lower bound {lower} must be less than upper bound {upper}"
+                    )));
+                }
+                Sampler::Uniform(Uniform::new(lower, upper))
+            }
+            Distribution::Exponential { rate } => {
+                Sampler::Exponential(Exp::new(rate).map_err(|e| invalid(&e))?)
+            }
+            Distribution::Normal { mean, std_dev } => Sampler::Normal(
+                Normal::new(mean, std_dev).map_err(|e| invalid(&e))?,
+            ),
+            Distribution::LogNormal { mu, sigma } => Sampler::LogNormal(
+                LogNormal::new(mu, sigma).map_err(|e| invalid(&e))?,
+            ),
+            Distribution::Pareto { scale, shape } => Sampler::Pareto(
+                Pareto::new(scale, shape).map_err(|e| invalid(&e))?,
+            ),
+            Distribution::Zipfian { n, exponent } => Sampler::Zipfian(
+                Zipf::new(n, exponent).map_err(|e| invalid(&e))?,
+            ),
+            Distribution::Empirical { ref path } => {
+                Sampler::Empirical(Empirical::load(path)?)
+            }
+        };
+
+        Ok(sampler)
+    }
+}
+
+/// Histogram of values with their weights.
+#[derive(Debug, Clone)]
+pub struct Empirical {
+    values: Vec<f64>,
+    index: WeightedIndex<f64>,
+}
+
+impl Empirical {
+    /// Read a histogram from a file with one value per line, optionally
+    /// followed by its weight (1 by default), separated by whitespace or
+    /// comma. Empty lines and lines starting with `#` are skipped.
+    pub fn load(path: &str) -> io::Result<Empirical> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot read {path}: {e}"))
+        })?;
+
+        Empirical::parse(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {e}"))
+        })
+    }
+
+    fn parse(content: &str) -> Result<Empirical, String> {
+        let mut values = vec![];
+        let mut weights = vec![];
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let number = |s: &str| {
+                s.parse::<f64>().ok().filter(|n| n.is_finite()).ok_or_else(
+                    || format!("line {}: invalid number {s}", i + 1),
+                )
+            };
+
+            let mut fields = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|f| !f.is_empty());
+
+            let value = number(fields.next().unwrap_or_default())?;
+            let weight = fields.next().map(number).transpose()?.unwrap_or(1.0);
+
+            if fields.next().is_some() {
+                return Err(format!("line {}: too many fields", i + 1));
+            }
+
+            if weight < 0.0 {
+                return Err(format!("line {}: negative weight", i + 1));
+            }
+
+            values.push(value);
+            weights.push(weight);
+        }
+
+        let index = WeightedIndex::new(&weights)
+            .map_err(|_| String::from("no values with positive weight"))?;
+
+        Ok(Empirical { values, index })
+    }
+}
+
+/// Distribution ready for sampling.
+#[derive(Debug, Clone)]
+pub enum Sampler {
+    Constant(f64),
+    Uniform(Uniform<f64>),
+    Exponential(Exp<f64>),
+    Normal(Normal<f64>),
+    LogNormal(LogNormal<f64>),
+    Pareto(Pareto<f64>),
+    Zipfian(Zipf<f64>),
+    Empirical(Empirical),
+}
+
+impl rand::distributions::Distribution<f64> for Sampler {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Sampler::Constant(value) => *value,
+            Sampler::Uniform(d) => d.sample(rng),
+            Sampler::Exponential(d) => d.sample(rng),
+            Sampler::Normal(d) => d.sample(rng),
+            Sampler::LogNormal(d) => d.sample(rng),
+            Sampler::Pareto(d) => d.sample(rng),
+            Sampler::Zipfian(d) => d.sample(rng),
+            Sampler::Empirical(d) => d.values[d.index.sample(rng)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Workload, testing};
+    use rand::{SeedableRng, rngs::StdRng};
+
+    fn mean(distribution: Distribution) -> f64 {
+        let sampler = distribution.sampler().expect("invalid distribution");
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let n = 100_000;
+        (0..n).map(|_| rng.sample(&sampler)).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn test_sampling() {
+        let close = |a: f64, b: f64| (a - b).abs() < b * 0.05;
+
+        assert_eq!(mean(Distribution::Constant { value: 3.0 }), 3.0);
+        assert!(close(
+            mean(Distribution::Uniform {
+                lower: 10.0,
+                upper: 20.0
+            }),
+            15.0
+        ));
+        assert!(close(mean(Distribution::Exponential { rate: 0.5 }), 2.0));
+        assert!(close(
+            mean(Distribution::Normal {
+                mean: 100.0,
+                std_dev: 10.0
+            }),
+            100.0
+        ));
+        // Mean of lognormal is exp(mu + sigma^2 / 2)
+        assert!(close(
+            mean(Distribution::LogNormal {
+                mu: 1.0,
+                sigma: 0.5
+            }),
+            (1.125f64).exp()
+        ));
+        // Mean of pareto is shape * scale / (shape - 1)
+        assert!(close(
+            mean(Distribution::Pareto {
+                scale: 2.0,
+                shape: 3.0
+            }),
+            3.0
+        ));
+
+        let empirical =
+            Empirical::parse("# size, weight\n\n64, 3\n1500 1\n9000 0\n")
+                .unwrap();
+        assert_eq!(empirical.values, vec![64.0, 1500.0, 9000.0]);
+        let sampler = Sampler::Empirical(empirical);
+        let mut rng = StdRng::seed_from_u64(1);
+        let small = (0..1000).filter(|_| rng.sample(&sampler) == 64.0).count();
+        assert!((700..800).contains(&small), "small: {small}");
+
+        assert!(Empirical::parse("1 -1\n").is_err());
+        assert!(Empirical::parse("1 0\n").is_err());
+        assert!(Empirical::parse("x\n").is_err());
+        assert!(
+            Distribution::Uniform {
+                lower: 1.0,
+                upper: 1.0
+            }
+            .sampler()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_lifetime() {
+        let config = testing::config(
+            r#"
+            restart_interval = 10
+
+            [workload]
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = true
+
+            [workload.lifetime]
+            distribution = "lognormal"
+            mu = 0.5
+            sigma = 1
+            "#,
+        );
+
+        let Some(Workload::Processes { lifetime, .. }) = config.workload else {
+            panic!("wrong workload type found");
+        };
+        assert!(matches!(
+            lifetime,
+            Some(Distribution::LogNormal { mu, sigma })
+                if mu == 0.5 && sigma == 1.0
+        ));
+    }
+}
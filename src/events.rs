@@ -0,0 +1,455 @@
+//! Ground truth log of every event generated by workers, to find out how
+//! many of them a monitoring tool has missed. Every event gets a correlation
+//! tag unique within the run, which is embedded into the event itself where
+//! possible, e.g. as an argument of an executed process. Other events are
+//! identified by their content, like the connection 5-tuple.
+//!
+//! Every worker process appends JSON lines to its own part file, the parts
+//! are merged into one file ordered by time when the run is over. Records
+//! are buffered in memory, and workers are terminated with SIGTERM, thus the
+//! buffer is written out from the signal handler. The buffer is guarded by
+//! a flag instead of a mutex: if the signal arrives while the buffer is
+//! being modified, the handler leaves it to whoever holds the flag.
+
+use std::borrow::Cow;
+use std::cell::UnsafeCell;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::fd::{IntoRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::unistd::{Pid, getpid, gettid};
+use serde::{Deserialize, Serialize};
+
+use crate::namespaces;
+
+/// Buffered records are written out once they take more space.
+const FLUSH_SIZE: usize = 64 * 1024;
+
+/// What has happened, with details to match the event with what a monitor
+/// has reported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A syscall was issued, `ret` is its result or a negated errno.
+    Syscall {
+        sysno: u32,
+        name: Cow<'static, str>,
+        ret: i64,
+    },
+
+    /// An io_uring request was completed.
+    #[serde(rename = "io_uring")]
+    IOUring { op: Cow<'static, str>, ret: i64 },
+
+    /// A new process was spawned. Arguments are empty for a forked process.
+    Process { child: i32, argv: Vec<String> },
+
+    /// A new connection was opened, the tag is its 5-tuple.
+    Connection {
+        protocol: Cow<'static, str>,
+        local_addr: String,
+        local_port: u16,
+        remote_addr: String,
+        remote_port: u16,
+    },
+
+    /// A port was bound to listen on, `ret` is zero or a negated errno.
+    Listen { port: u16, ret: i64 },
+
+    /// A BPF program was loaded, `ret` is its fd or a negated errno.
+    BpfProg { name: String, ret: i64 },
+}
+
+/// A single record of the log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// Wall clock time in nanoseconds since Unix epoch, as most monitors
+    /// report it.
+    pub timestamp: u64,
+    pub pid: i32,
+    pub tid: i32,
+
+    /// Workload group, CPU core and process number of the worker.
+    pub group: String,
+    pub cpu: usize,
+    pub process: usize,
+
+    /// Correlation tag unique within the run.
+    pub tag: String,
+
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Where and on behalf of which worker events are logged.
+#[derive(Debug, Clone)]
+pub struct EventSink {
+    /// Part file of the worker, appended to by every restart of it.
+    pub path: String,
+    pub group: String,
+    pub cpu: usize,
+    pub process: usize,
+
+    /// Whether file names of syscalls and io_uring requests get the tag.
+    pub tag_paths: bool,
+}
+
+impl EventSink {
+    /// Part of the log at `path` for the worker with the specified index
+    /// among all workers of the run.
+    pub fn part(path: &str, index: usize) -> String {
+        format!("{path}.part{index}")
+    }
+}
+
+struct EventLog {
+    fd: RawFd,
+
+    /// The process the log belongs to. Processes forked by the worker
+    /// inherit a copy of the buffer, which must not be written twice.
+    owner: Pid,
+    sink: EventSink,
+    buffer: Vec<u8>,
+}
+
+impl EventLog {
+    /// Write out everything buffered. Only async-signal-safe calls are made,
+    /// so that it could be done from the signal handler.
+    fn flush(&mut self) {
+        let mut written = 0;
+
+        while written < self.buffer.len() {
+            match nix::unistd::write(self.fd, &self.buffer[written..]) {
+                Ok(n) => written += n,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(_) => break,
+            }
+        }
+
+        self.buffer.clear();
+    }
+}
+
+struct Shared {
+    /// Whether the log is in use, either by a worker thread or by the signal
+    /// handler.
+    busy: AtomicBool,
+
+    /// SIGTERM has arrived while the log was in use.
+    terminating: AtomicBool,
+    log: UnsafeCell<Option<EventLog>>,
+}
+
+// Access to the log is serialized via `busy` flag
+unsafe impl Sync for Shared {}
+
+static LOG: Shared = Shared {
+    busy: AtomicBool::new(false),
+    terminating: AtomicBool::new(false),
+    log: UnsafeCell::new(None),
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TAG_PATHS: AtomicBool = AtomicBool::new(false);
+
+/// Number of events logged by the worker so far.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Pid of the worker as seen by the supervisor and monitors, it differs from
+/// `getpid` when the worker runs in its own pid namespace.
+static PID: AtomicI32 = AtomicI32::new(0);
+
+/// Start logging events of the current worker process, which has `pid` as
+/// seen from outside.
+pub fn open(sink: &EventSink, pid: Pid) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open(&sink.path)?;
+
+    with_log(|log| {
+        *log = Some(EventLog {
+            fd: file.into_raw_fd(),
+            owner: getpid(),
+            sink: sink.clone(),
+            buffer: Vec::with_capacity(FLUSH_SIZE * 2),
+        })
+    });
+
+    unsafe { signal::signal(Signal::SIGTERM, SigHandler::Handler(terminate)) }
+        .map_err(io::Error::from)?;
+
+    PID.store(pid.as_raw(), Ordering::Relaxed);
+    TAG_PATHS.store(sink.tag_paths, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether events are logged.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Correlation tag for the next event, made of the worker pid and sequence
+/// number, if events are logged. It has to be passed to `record_tagged`.
+pub fn tag() -> Option<String> {
+    enabled().then(|| {
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{sequence}", PID.load(Ordering::Relaxed))
+    })
+}
+
+/// Whether the tag has to be embedded into file names. It's off by default,
+/// as every file operation then gets a different path than configured.
+pub fn tag_paths() -> bool {
+    enabled() && TAG_PATHS.load(Ordering::Relaxed)
+}
+
+/// Path named by the correlation `tag` inside of `base`, to embed the tag
+/// into a file operation, e.g. `/tmp/1234-5`.
+pub fn tagged_path(base: &CStr, tag: &str) -> CString {
+    let mut path = base.to_bytes().to_vec();
+    path.push(b'/');
+    path.extend_from_slice(tag.as_bytes());
+
+    CString::new(path).expect("tag contains a nul byte")
+}
+
+/// Remove the file created at `path`, if it's a tagged one. Tagged names are
+/// never reused, so that such files would only pile up.
+pub fn remove_tagged(base: &CStr, path: &CStr) {
+    if path != base {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+}
+
+/// Log an event, if enabled.
+pub fn record(kind: EventKind) {
+    if let Some(tag) = tag() {
+        record_tagged(tag, kind);
+    }
+}
+
+/// Log an event with the tag obtained from `tag` in advance.
+pub fn record_tagged(tag: String, kind: EventKind) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    with_log(|log| {
+        let Some(log) = log else {
+            return;
+        };
+
+        // Only the main thread id is known from outside of a pid namespace
+        let pid = PID.load(Ordering::Relaxed);
+        let tid = match gettid() {
+            tid if tid == log.owner => pid,
+            tid => tid.as_raw(),
+        };
+
+        let event = Event {
+            timestamp,
+            pid,
+            tid,
+            group: log.sink.group.clone(),
+            cpu: log.sink.cpu,
+            process: log.sink.process,
+            tag,
+            kind,
+        };
+
+        if serde_json::to_writer(&mut log.buffer, &event).is_ok() {
+            log.buffer.push(b'\n');
+        }
+
+        if log.buffer.len() >= FLUSH_SIZE {
+            log.flush();
+        }
+    });
+}
+
+/// Write out everything buffered, e.g. before the worker exits.
+pub fn flush() {
+    with_log(|log| log.iter_mut().for_each(EventLog::flush));
+}
+
+fn with_log<R>(f: impl FnOnce(&mut Option<EventLog>) -> R) -> R {
+    while LOG
+        .busy
+        .compare_exchange_weak(
+            false,
+            true,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .is_err()
+    {
+        std::hint::spin_loop();
+    }
+
+    let log = unsafe { &mut *LOG.log.get() };
+    let result = f(log);
+
+    // The signal handler has left it to us, the log stays busy until the
+    // process is gone
+    if LOG.terminating.load(Ordering::Relaxed) {
+        terminate_with(log);
+    }
+
+    LOG.busy.store(false, Ordering::Release);
+    result
+}
+
+extern "C" fn terminate(_: libc::c_int) {
+    if LOG
+        .busy
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        LOG.terminating.store(true, Ordering::Relaxed);
+        return;
+    }
+
+    terminate_with(unsafe { &mut *LOG.log.get() });
+}
+
+/// Write out the log and terminate the process by SIGTERM as if nothing was
+/// logged, so that the exit status is the same. Inside of the handler the
+/// signal is delivered once the handler returns.
+fn terminate_with(log: &mut Option<EventLog>) {
+    if let Some(log) = log
+        && log.owner == getpid()
+    {
+        log.flush();
+    }
+
+    unsafe {
+        let _ = signal::signal(Signal::SIGTERM, SigHandler::SigDfl);
+    }
+    let _ = signal::raise(Signal::SIGTERM);
+
+    // The first process of a pid namespace ignores the signal
+    if getpid().as_raw() == 1 {
+        unsafe { libc::_exit(namespaces::EXIT_TERMINATED) }
+    }
+}
+
+/// Merge the part files into `path` ordered by time, and remove them.
+pub fn merge(path: &str, parts: &[String]) -> io::Result<usize> {
+    #[derive(Deserialize)]
+    struct Timestamp {
+        timestamp: u64,
+    }
+
+    let mut lines = vec![];
+
+    for part in parts {
+        let file = match File::open(part) {
+            Ok(file) => file,
+            // A worker might have not started at all
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            // The last record could be cut if a worker was killed
+            if let Ok(Timestamp { timestamp }) = serde_json::from_str(&line) {
+                lines.push((timestamp, line));
+            }
+        }
+    }
+
+    // Records of the same worker are already mostly in order
+    lines.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    for (_, line) in lines.iter() {
+        writeln!(file, "{line}")?;
+    }
+    file.flush()?;
+
+    for part in parts {
+        let _ = fs::remove_file(part);
+    }
+
+    Ok(lines.len())
+}
+
+/// Read a merged log.
+pub fn load(path: &str) -> io::Result<Vec<Event>> {
+    let file = BufReader::new(File::open(path)?);
+
+    file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{path}:{}: {e}", i + 1),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let dir = std::env::temp_dir();
+        let path = dir
+            .join(format!("berserker-events-{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let parts: Vec<_> = (0..3).map(|i| EventSink::part(&path, i)).collect();
+
+        let event = |timestamp, tag: &str| Event {
+            timestamp,
+            pid: 1,
+            tid: 1,
+            group: String::from("syscalls"),
+            cpu: 0,
+            process: 0,
+            tag: tag.to_string(),
+            kind: EventKind::Syscall {
+                sysno: 39,
+                name: Cow::Borrowed("getpid"),
+                ret: 1,
+            },
+        };
+
+        let line = |e: &Event| serde_json::to_string(e).unwrap() + "\n";
+        fs::write(&parts[0], line(&event(3, "1-1")) + &line(&event(1, "1-0")))
+            .unwrap();
+        // Cut by termination
+        fs::write(&parts[1], line(&event(2, "2-0")) + "{\"timestamp\": 5")
+            .unwrap();
+
+        assert_eq!(merge(&path, &parts).unwrap(), 3);
+        assert!(!parts.iter().any(|part| fs::exists(part).unwrap()));
+
+        let events = load(&path).unwrap();
+        let tags: Vec<_> = events.iter().map(|e| e.tag.as_str()).collect();
+        assert_eq!(tags, vec!["1-0", "2-0", "1-1"]);
+        assert_eq!(events[0], event(1, "1-0"));
+
+        let json = line(&event(1, "1-0"));
+        assert!(json.contains("\"kind\":\"syscall\""), "{json}");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! Latency histograms in the spirit of HdrHistogram. Values in nanoseconds
+//! are counted in buckets, which are exact below 128 ns and then grow with
+//! the value, so that every bucket is less than 1% wide relative to its
+//! values. The histogram has a fixed size and consists only of atomic
+//! counters, which allows to keep it in the shared memory of a worker.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Number of bits for buckets within one power of two.
+const SUB_BITS: u32 = 7;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Values are tracked up to 2^40 ns, about 18 minutes, anything above is
+/// counted as the largest value.
+const MAX_BITS: u32 = 40;
+const MAX_VALUE: u64 = (1 << MAX_BITS) - 1;
+
+const BUCKETS: usize = (MAX_BITS - SUB_BITS + 1) as usize * SUB_BUCKETS;
+
+/// Index of the bucket counting the value.
+fn index(value: u64) -> usize {
+    let value = value.min(MAX_VALUE);
+
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    // Values in [2^exp, 2^(exp + 1)) are split into SUB_BUCKETS buckets
+    let exp = u64::BITS - 1 - value.leading_zeros();
+    let group = (exp - SUB_BITS + 1) as usize;
+    let sub = (value >> (exp - SUB_BITS)) as usize - SUB_BUCKETS;
+
+    group * SUB_BUCKETS + sub
+}
+
+/// Largest value counted by the bucket.
+fn highest_value(index: usize) -> u64 {
+    let (group, sub) = (index / SUB_BUCKETS, index % SUB_BUCKETS);
+
+    match group {
+        0 => sub as u64,
+        _ => (((SUB_BUCKETS + sub + 1) as u64) << (group - 1)) - 1,
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS],
+    max: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let value = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+
+        self.counts[index(value)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Plain copy of a histogram, which could be merged with others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    counts: Vec<u64>,
+    max: u64,
+}
+
+impl Default for HistogramSnapshot {
+    fn default() -> Self {
+        HistogramSnapshot {
+            counts: vec![0; BUCKETS],
+            max: 0,
+        }
+    }
+}
+
+impl HistogramSnapshot {
+    /// Count a value in nanoseconds, for histograms built outside of the
+    /// shared memory.
+    pub fn record(&mut self, value: u64) {
+        self.counts[index(value)] += 1;
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &HistogramSnapshot) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Value in nanoseconds, which `quantile` of all values do not exceed.
+    /// The value is precise up to the bucket width, but never exceeds the
+    /// largest recorded value.
+    pub fn value_at(&self, quantile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+
+        let rank = ((quantile * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+
+        for (index, bucket) in self.counts.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return highest_value(index).min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    /// Summary of the distribution, if anything was recorded.
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        let count = self.count();
+
+        (count > 0).then(|| Percentiles {
+            count,
+            p50: self.value_at(0.5),
+            p90: self.value_at(0.9),
+            p99: self.value_at(0.99),
+            p999: self.value_at(0.999),
+            max: self.max,
+        })
+    }
+}
+
+/// Latency percentiles in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+/// Human readable latency, e.g. "12.3us".
+pub fn format_nanos(value: u64) -> String {
+    match value {
+        0..1_000 => format!("{value}ns"),
+        1_000..1_000_000 => format!("{:.1}us", value as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.2}ms", value as f64 / 1e6),
+        _ => format!("{:.2}s", value as f64 / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::WorkerStats;
+
+    #[test]
+    fn test_histogram() {
+        // Every bucket contains only values with the same index, and its
+        // width is within 1% of its values
+        for value in (0..100_000).chain([MAX_VALUE - 1, MAX_VALUE]) {
+            let i = index(value);
+            assert!(highest_value(i) >= value, "{value}");
+            assert!(i == 0 || highest_value(i - 1) < value, "{value}");
+            assert!(highest_value(i) - value <= value / 100, "{value}");
+        }
+        assert_eq!(index(u64::MAX), BUCKETS - 1);
+
+        let stats = WorkerStats::shared();
+        for micros in 1..=1000 {
+            stats.latency(Duration::from_micros(micros));
+        }
+        stats.latency(Duration::from_secs(1));
+
+        let mut total = HistogramSnapshot::default();
+        total.merge(&stats.latencies());
+        total.merge(&stats.latencies());
+
+        let percentiles = total.percentiles().unwrap();
+        let close = |value: u64, expected: u64| {
+            value >= expected && value - expected <= expected / 100
+        };
+        assert_eq!(percentiles.count, 2002);
+        assert!(close(percentiles.p50, 501_000), "{percentiles:?}");
+        assert!(close(percentiles.p90, 901_000), "{percentiles:?}");
+        assert!(close(percentiles.p999, 1_000_000), "{percentiles:?}");
+        assert_eq!(percentiles.max, 1_000_000_000);
+
+        assert_eq!(HistogramSnapshot::default().percentiles(), None);
+        assert_eq!(format_nanos(12_345), "12.3us");
+    }
+}
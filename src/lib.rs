@@ -1,14 +1,60 @@
+use cgroup::CgroupConfig;
+use config::Config;
+use control::Control;
 use core_affinity::CoreId;
-use serde::{Deserialize, Deserializer};
-use std::{collections::HashMap, fmt::Display, net::Ipv4Addr};
+use namespaces::NamespaceConfig;
+use pacing::Pacing;
+use placement::Placement;
+use rand::{SeedableRng, rngs::StdRng};
+use rate::Schedule;
+use scheduling::Scheduling;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use stats::WorkerStats;
+use std::{
+    collections::HashMap, fmt::Display, fs, net::Ipv4Addr, time::Instant,
+};
 use syscalls::Sysno;
-
+use validate::ValidationError;
+
+pub use distribution::Distribution;
+
+pub mod cgroup;
+pub mod cli;
+pub mod compare;
+pub mod control;
+pub mod distribution;
+pub mod events;
+pub mod histogram;
+pub mod metrics;
+pub mod namespaces;
+pub mod pacing;
+pub mod placement;
+pub mod probes;
+pub mod rate;
+pub mod reload;
+pub mod report;
+pub mod scheduling;
+pub mod stats;
+pub mod supervisor;
+pub mod usage;
+pub mod validate;
+pub mod verify;
 pub mod worker;
 
+/// Current version of the configuration format. Version 2 changed
+/// `arrival_rate` of syscalls and io_uring workloads from events per
+/// millisecond to events per second.
+pub const CONFIG_VERSION: u32 = 2;
+
 /// Main workload configuration, contains general bits for all types of
 /// workloads plus workload specific data.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WorkloadConfig {
+    /// Version of the configuration format, see `CONFIG_VERSION`. Could be
+    /// omitted, but then rates written for an older version are taken as is.
+    #[serde(default)]
+    pub version: Option<u32>,
+
     /// An amount of time for workload payload to run before restarting.
     pub restart_interval: u64,
 
@@ -23,12 +69,542 @@ pub struct WorkloadConfig {
     #[serde(default = "default_workers")]
     pub workers: usize,
 
-    /// Custom workload configuration.
-    pub workload: Workload,
+    /// CPU cores to run workers on, inherited by groups without their own.
+    /// Without it workers run on every core in per-core mode, or are not
+    /// pinned at all.
+    #[serde(default)]
+    pub placement: Option<Placement>,
+
+    /// Nice level, scheduling policy and I/O priority of workers, inherited
+    /// by groups without their own. Workers are scheduled as the parent if
+    /// not specified.
+    #[serde(default)]
+    pub scheduling: Option<Scheduling>,
+
+    /// Cgroup v2 hierarchy to run workers in, inherited by groups without
+    /// their own. Workers stay in the cgroup of the parent if not specified.
+    #[serde(default)]
+    pub cgroup: Option<CgroupConfig>,
+
+    /// Namespaces every worker gets on its own, inherited by groups without
+    /// their own. Workers stay in the namespaces of the parent if not
+    /// specified.
+    #[serde(default)]
+    pub namespaces: Option<NamespaceConfig>,
+
+    /// Custom workload configuration. Could be omitted if the workload
+    /// groups are specified instead.
+    #[serde(default)]
+    pub workload: Option<Workload>,
 
     /// For how long to run the worker. Default value is zero, meaning no limit.
     #[serde(default = "default_duration")]
     pub duration: u64,
+
+    /// Set of workload groups to run at the same time, every one of them
+    /// with its own workload type and number of workers. All the groups are
+    /// started and stopped together.
+    #[serde(default)]
+    pub groups: Vec<WorkloadGroup>,
+
+    /// Timeline of phases, every one overriding workload rates for a
+    /// specified amount of time. After the last phase is over, its overrides
+    /// stay in effect.
+    #[serde(default)]
+    pub scenario: Vec<Phase>,
+
+    /// How the arrival rate of the top level workload changes over time.
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+
+    /// What to do with workers that have finished or failed.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// Expose live counters over HTTP, disabled if not specified.
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+
+    /// Where to write machine-readable results of the run.
+    #[serde(default)]
+    pub results: ResultsConfig,
+
+    /// Process to sample resource usage of during the run, e.g. the
+    /// monitoring tool under test. Nothing is sampled if not specified.
+    #[serde(default)]
+    pub target: Option<TargetConfig>,
+
+    /// Unix socket to control the run while it's in progress, disabled if
+    /// not specified.
+    #[serde(default)]
+    pub control: Option<ControlConfig>,
+
+    /// Seed for all random decisions. Every worker gets its own generator
+    /// derived from the seed, CPU core and process number, so that two runs
+    /// of the same configuration produce the same schedule of events.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// When the run was started, used to find the current scenario phase.
+    #[serde(skip, default = "Instant::now")]
+    pub start: Instant,
+
+    /// Runtime controls of a workload group, shared between the parent and
+    /// the workers of the group.
+    #[serde(skip)]
+    pub runtime: Option<&'static Control>,
+}
+
+impl WorkloadConfig {
+    /// Read the configuration from all the sources in order: the system wide
+    /// file, the specified file and `BERSERKER__` environment variables. The
+    /// specified file has to exist, otherwise `cli::DEFAULT_CONFIG` is read
+    /// if it's there.
+    pub fn load(
+        path: Option<&str>,
+    ) -> Result<WorkloadConfig, Vec<ValidationError>> {
+        let file = match path {
+            Some(path) => {
+                // Otherwise a typo in the path is reported as missing options
+                fs::File::open(path).map_err(|e| {
+                    vec![ValidationError::new(
+                        "",
+                        format!("cannot read {path}: {e}"),
+                    )]
+                })?;
+                config::File::with_name(path)
+            }
+            None => {
+                config::File::with_name(cli::DEFAULT_CONFIG).required(false)
+            }
+        };
+
+        let config = Config::builder()
+            .add_source(
+                config::File::with_name("/etc/berserker/workload.toml")
+                    .required(false),
+            )
+            .add_source(file)
+            // Eg.. `BERSERKER__WORKLOAD__ARRIVAL_RATE=1` would set the
+            // `arrival_rate` key
+            .add_source(
+                config::Environment::with_prefix("BERSERKER")
+                    .try_parsing(true)
+                    .separator("__"),
+            )
+            .build()
+            .map_err(|e| vec![ValidationError::from(e)])?;
+
+        WorkloadConfig::from_config(config)
+    }
+
+    /// Deserialize and validate the configuration, collecting all the
+    /// problems found.
+    pub fn from_config(
+        config: Config,
+    ) -> Result<WorkloadConfig, Vec<ValidationError>> {
+        let raw = config
+            .clone()
+            .try_deserialize::<HashMap<String, config::Value>>()
+            .map_err(|e| vec![ValidationError::from(e)])?;
+
+        let mut errors = validate::unknown_keys(&raw);
+
+        match config.try_deserialize::<WorkloadConfig>() {
+            Ok(workload) => {
+                errors.extend(validate::validate(&workload));
+
+                if errors.is_empty() {
+                    return Ok(workload);
+                }
+            }
+            Err(e) => errors.push(ValidationError::from(e)),
+        }
+
+        Err(errors)
+    }
+
+    /// Return all workload groups to run. The top level workload, if
+    /// specified, is treated as a separate group using top level `per_core`
+    /// and `workers` values.
+    pub fn groups(&self) -> Vec<WorkloadGroup> {
+        let mut groups = vec![];
+
+        if let Some(workload) = &self.workload {
+            groups.push(WorkloadGroup {
+                name: None,
+                per_core: self.per_core,
+                workers: self.workers,
+                placement: self.placement.clone(),
+                scheduling: self.scheduling.clone(),
+                cgroup: self.cgroup.clone(),
+                namespaces: self.namespaces.clone(),
+                workload: workload.clone(),
+                schedule: self.schedule.clone(),
+            });
+        }
+
+        groups.extend(self.groups.iter().map(|group| WorkloadGroup {
+            placement: group.placement.clone().or(self.placement.clone()),
+            scheduling: group.scheduling.clone().or(self.scheduling.clone()),
+            cgroup: group.cgroup.clone().or(self.cgroup.clone()),
+            namespaces: group.namespaces.clone().or(self.namespaces.clone()),
+            ..group.clone()
+        }));
+        groups
+    }
+
+    /// Configuration for workers of a particular group. It contains only the
+    /// group workload, general bits are inherited from the main
+    /// configuration.
+    pub fn for_group(&self, group: &WorkloadGroup) -> WorkloadConfig {
+        WorkloadConfig {
+            per_core: group.per_core,
+            workers: group.workers,
+            placement: group.placement.clone(),
+            scheduling: group.scheduling.clone(),
+            cgroup: group.cgroup.clone(),
+            namespaces: group.namespaces.clone(),
+            workload: Some(group.workload.clone()),
+            groups: vec![],
+            schedule: group.schedule.clone(),
+            // Workers of different groups should not follow the same schedule
+            seed: self.seed.map(|seed| mix(seed, name_hash(group.name()))),
+            ..self.clone()
+        }
+    }
+
+    /// Random generator for a worker running on the specified CPU core with
+    /// the specified process number. Without `seed` it's seeded from the
+    /// system entropy source.
+    pub fn rng(&self, cpu: usize, process: usize) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(mix(
+                mix(seed, cpu as u64),
+                process as u64,
+            )),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Scenario phase active at the moment, if any.
+    pub fn phase(&self) -> Option<&Phase> {
+        self.phase_at(self.start.elapsed().as_secs_f64())
+    }
+
+    /// Scenario phase active at the specified number of seconds since the
+    /// start of the run.
+    pub fn phase_at(&self, elapsed: f64) -> Option<&Phase> {
+        let mut phase_end = 0.0;
+
+        for phase in self.scenario.iter() {
+            phase_end += phase.duration as f64;
+
+            if elapsed < phase_end {
+                return Some(phase);
+            }
+        }
+
+        self.scenario.last()
+    }
+
+    /// Arrival rate in effect at the moment, `base` is the rate from the
+    /// workload configuration.
+    pub fn arrival_rate(&self, base: f64) -> f64 {
+        self.arrival_rate_at(base, self.start.elapsed().as_secs_f64())
+    }
+
+    /// Arrival rate in effect at the specified number of seconds since the
+    /// start of the run.
+    pub fn arrival_rate_at(&self, base: f64, elapsed: f64) -> f64 {
+        if let Some(rate) = self.rate_override() {
+            return rate;
+        }
+
+        let base = self.base_rate(base);
+        match self.phase_at(elapsed) {
+            Some(phase) => phase.arrival_rate.unwrap_or(base) * phase.scale,
+            None => base,
+        }
+    }
+
+    /// Maximum arrival rate over all scenario phases.
+    pub fn max_arrival_rate(&self, base: f64) -> f64 {
+        if let Some(rate) = self.rate_override() {
+            return rate;
+        }
+
+        let base = self.base_rate(base);
+        self.scenario
+            .iter()
+            .map(|phase| phase.arrival_rate.unwrap_or(base) * phase.scale)
+            .reduce(f64::max)
+            .unwrap_or(base)
+    }
+
+    /// Arrival rate set at runtime via the control socket, it takes place of
+    /// the workload and scenario rates.
+    fn rate_override(&self) -> Option<f64> {
+        self.runtime.and_then(Control::rate)
+    }
+
+    /// Arrival rate of the workload, which might have been changed by
+    /// reloading the configuration since the worker has started.
+    fn base_rate(&self, base: f64) -> f64 {
+        self.runtime.and_then(Control::base).unwrap_or(base)
+    }
+
+    /// Departure rate in effect at the moment, `base` is the rate from the
+    /// workload configuration.
+    pub fn departure_rate(&self, base: f64) -> f64 {
+        self.phase()
+            .and_then(|phase| phase.departure_rate)
+            .unwrap_or(base)
+    }
+
+    /// Maximum number of dynamic connections in effect at the moment, `base`
+    /// is the value from the workload configuration.
+    pub fn connections_dyn_max(&self, base: u32) -> u32 {
+        self.phase()
+            .and_then(|phase| phase.connections_dyn_max)
+            .unwrap_or(base)
+    }
+}
+
+/// Combine a seed with a value into a new seed, using SplitMix64 finalizer
+/// to make seeds of neighbouring workers unrelated.
+fn mix(seed: u64, value: u64) -> u64 {
+    let mut z = seed ^ value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// FNV-1a hash of a name, stable between runs and compiler versions.
+fn name_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// When to restart a worker.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    /// Never restart workers, it's the default.
+    Never,
+
+    /// Restart workers whenever they finish.
+    Always,
+
+    /// Restart only failed workers.
+    OnFailure,
+}
+
+/// Restart policy for worker processes. A restarted worker keeps the same CPU
+/// core and process number.
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+pub struct RestartPolicy {
+    /// When to restart a worker.
+    #[serde(default = "default_restart_policy")]
+    pub policy: RestartMode,
+
+    /// How many times the same worker could be restarted, zero means no
+    /// limit.
+    #[serde(default)]
+    pub max_restarts: u32,
+
+    /// Delay before restarting a worker in milliseconds. The delay is doubled
+    /// with every restart of the same worker.
+    #[serde(default = "default_restart_backoff")]
+    pub backoff: u64,
+
+    /// Maximum delay before restarting a worker in milliseconds.
+    #[serde(default = "default_restart_max_backoff")]
+    pub max_backoff: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            policy: default_restart_policy(),
+            max_restarts: 0,
+            backoff: default_restart_backoff(),
+            max_backoff: default_restart_max_backoff(),
+        }
+    }
+}
+
+fn default_restart_policy() -> RestartMode {
+    RestartMode::Never
+}
+
+fn default_restart_backoff() -> u64 {
+    1000
+}
+
+fn default_restart_max_backoff() -> u64 {
+    60000
+}
+
+/// Local HTTP listener serving live counters in OpenMetrics text format.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    /// Address to listen on, e.g. `127.0.0.1:9100`.
+    #[serde(default = "default_metrics_address")]
+    pub address: String,
+}
+
+fn default_metrics_address() -> String {
+    String::from("127.0.0.1:9100")
+}
+
+/// Files with results of the run, nothing is written if not specified.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResultsConfig {
+    /// JSON document with the effective configuration, timestamps, host
+    /// information and totals per worker, written at the end of the run.
+    #[serde(default)]
+    pub json: Option<String>,
+
+    /// CSV time series of operations per workload group, sampled every
+    /// second.
+    #[serde(default)]
+    pub csv: Option<String>,
+
+    /// JSON lines log of every generated event, the ground truth to measure
+    /// event loss of a monitor.
+    #[serde(default)]
+    pub events: Option<String>,
+
+    /// Embed the tag of logged events into file names of syscalls and
+    /// io_uring requests, which changes the workload. Disabled by default.
+    #[serde(default)]
+    pub tag_paths: bool,
+}
+
+/// Unix socket accepting commands to pause, resume or retune the workers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ControlConfig {
+    /// Path of the socket, an existing file is replaced.
+    pub socket: String,
+}
+
+/// Process to sample resource usage of, specified either by `pid` or by
+/// `comm`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TargetConfig {
+    #[serde(default)]
+    pub pid: Option<i32>,
+
+    /// Command name as in `/proc/<pid>/comm`. The process is looked up again
+    /// if it exits, e.g. after being restarted.
+    #[serde(default)]
+    pub comm: Option<String>,
+
+    /// Sampling interval in milliseconds.
+    #[serde(default = "default_target_interval")]
+    pub interval: u64,
+}
+
+fn default_target_interval() -> u64 {
+    1000
+}
+
+impl Default for TargetConfig {
+    fn default() -> Self {
+        TargetConfig {
+            pid: None,
+            comm: None,
+            interval: default_target_interval(),
+        }
+    }
+}
+
+impl Display for TargetConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.pid, &self.comm) {
+            (Some(pid), _) => write!(f, "pid {pid}"),
+            (None, Some(comm)) => write!(f, "'{comm}'"),
+            (None, None) => write!(f, "<none>"),
+        }
+    }
+}
+
+/// One phase of a scenario. Every parameter that is not specified keeps the
+/// value from the workload configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Phase {
+    /// Phase duration in seconds.
+    pub duration: u64,
+
+    /// Multiplier for the arrival rate, e.g. 0.1 to run at 10% of the rate.
+    #[serde(default = "default_phase_scale")]
+    pub scale: f64,
+
+    /// Overrides `arrival_rate` of the workload.
+    #[serde(default)]
+    pub arrival_rate: Option<f64>,
+
+    /// Overrides `departure_rate` of the workload.
+    #[serde(default)]
+    pub departure_rate: Option<f64>,
+
+    /// Overrides `connections_dyn_max` of the network workload.
+    #[serde(default)]
+    pub connections_dyn_max: Option<u32>,
+}
+
+fn default_phase_scale() -> f64 {
+    1.0
+}
+
+/// A group of workers running the same workload.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WorkloadGroup {
+    /// Group name to use in logs, by default it's the workload type.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Same as `per_core` in the main configuration, but only for this group.
+    #[serde(default = "default_per_core")]
+    pub per_core: bool,
+
+    /// Same as `workers` in the main configuration, but only for this group.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    /// Same as `placement` in the main configuration, but only for this
+    /// group.
+    #[serde(default)]
+    pub placement: Option<Placement>,
+
+    /// Same as `scheduling` in the main configuration, but only for this
+    /// group.
+    #[serde(default)]
+    pub scheduling: Option<Scheduling>,
+
+    /// Same as `cgroup` in the main configuration, but only for this group.
+    #[serde(default)]
+    pub cgroup: Option<CgroupConfig>,
+
+    /// Same as `namespaces` in the main configuration, but only for this
+    /// group.
+    #[serde(default)]
+    pub namespaces: Option<NamespaceConfig>,
+
+    /// Workload configuration for this group.
+    pub workload: Workload,
+
+    /// How the arrival rate of this group changes over time.
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+}
+
+impl WorkloadGroup {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.workload.kind())
+    }
 }
 
 fn default_workers() -> usize {
@@ -79,14 +655,31 @@ where
     Ok(map)
 }
 
+/// Serialize arguments back into "arg1=value1,arg2=value2" format, sorted by
+/// name.
+fn serialize_args<S>(
+    args: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut args: Vec<_> = args
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    args.sort();
+    serializer.serialize_str(&args.join(","))
+}
+
 /// Workload specific configuration, contains one enum value for each
 /// workload type.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase", tag = "type")]
 pub enum Workload {
     /// How to listen on ports.
     Endpoints {
-        /// Governing the number of ports open.
+        /// Governing the number of ports open per worker.
         #[serde(flatten)]
         distribution: Distribution,
     },
@@ -96,16 +689,22 @@ pub enum Workload {
         /// How often a new process will be spawn.
         arrival_rate: f64,
 
-        /// How long processes are going to live.
+        /// How long processes are going to live, exponentially distributed
+        /// lifetime with this rate. Not used if `lifetime` is specified.
+        #[serde(default)]
         departure_rate: f64,
 
+        /// Distribution of process lifetimes in seconds.
+        #[serde(default)]
+        lifetime: Option<Distribution>,
+
         /// Spawn a new process with random arguments.
         random_process: bool,
     },
 
     /// How to invoke syscalls
     Syscalls {
-        /// How often to invoke a syscall.
+        /// How many syscalls to invoke per second.
         #[serde(default = "default_syscalls_arrival_rate")]
         arrival_rate: f64,
 
@@ -113,6 +712,10 @@ pub enum Workload {
         #[serde(default = "default_syscalls_tight_loop")]
         tight_loop: bool,
 
+        /// How to keep up with the arrival rate, not used in a tight loop.
+        #[serde(default)]
+        pacing: Pacing,
+
         /// Which syscall to trigger
         #[serde(default = "default_syscalls_syscall_nr")]
         syscall_nr: u32,
@@ -120,6 +723,7 @@ pub enum Workload {
         /// Arguments for syscall in format "arg1=value1,arg2=value2"
         #[serde(
             deserialize_with = "deserialize_args",
+            serialize_with = "serialize_args",
             default = "HashMap::new"
         )]
         syscall_args: HashMap<String, String>,
@@ -127,10 +731,14 @@ pub enum Workload {
 
     /// How to invoke syscalls
     IOUring {
-        /// How often to invoke a io_uring events.
+        /// How many io_uring requests to submit per second.
         #[serde(default = "default_syscalls_arrival_rate")]
         arrival_rate: f64,
 
+        /// How to keep up with the arrival rate.
+        #[serde(default)]
+        pacing: Pacing,
+
         /// Number of io uring event to trigger
         /// List of io_uring events can be found at https://github.com/tokio-rs/io-uring/blob/master/src/sys/sys_x86_64.rs
         /// or at https://github.com/torvalds/linux/blob/b320789d6883cc00ac78ce83bccbfe7ed58afcf0/include/uapi/linux/io_uring.h
@@ -140,6 +748,7 @@ pub enum Workload {
         /// Arguments for io_uring in format "arg1=value1,arg2=value2"
         #[serde(
             deserialize_with = "deserialize_args",
+            serialize_with = "serialize_args",
             default = "HashMap::new"
         )]
         iouring_args: HashMap<String, String>,
@@ -158,12 +767,20 @@ pub enum Workload {
         /// to connect to.
         target_port: u16,
 
-        /// Rate of opening new connections
+        /// Rate of opening new connections, used only by the client
+        #[serde(default)]
         arrival_rate: f64,
 
-        /// Rate of closing connections
+        /// Rate of closing connections, used only by the client. Not used
+        /// if `lifetime` is specified.
+        #[serde(default)]
         departure_rate: f64,
 
+        /// Distribution of dynamic connection lifetimes in seconds, used
+        /// only by the client.
+        #[serde(default)]
+        lifetime: Option<Distribution>,
+
         /// Number of static connections
         connections_static: u32,
 
@@ -184,6 +801,12 @@ pub enum Workload {
         #[serde(default = "default_network_send_interval")]
         send_interval: u64,
 
+        /// Distribution of sizes of the data sent via connections in bytes,
+        /// limited by the socket buffer size. By default a short greeting
+        /// is sent.
+        #[serde(default)]
+        payload_size: Option<Distribution>,
+
         /// Whether or not to wait for a connection to be removed before adding
         /// a new one, when the dynamic connection limit is reached.
         /// if true: an old connection will be forcibly removed
@@ -205,6 +828,30 @@ pub enum Workload {
     },
 }
 
+impl Workload {
+    /// All known workload types.
+    pub const KINDS: &[&str] = &[
+        "endpoints",
+        "processes",
+        "syscalls",
+        "iouring",
+        "network",
+        "bpf",
+    ];
+
+    /// Workload type, the same as used in the configuration.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Workload::Endpoints { .. } => "endpoints",
+            Workload::Processes { .. } => "processes",
+            Workload::Syscalls { .. } => "syscalls",
+            Workload::IOUring { .. } => "iouring",
+            Workload::Network { .. } => "network",
+            Workload::Bpf { .. } => "bpf",
+        }
+    }
+}
+
 fn default_bpf_tracepoint() -> u64 {
     306
 }
@@ -221,19 +868,6 @@ fn default_network_send_interval() -> u64 {
     100
 }
 
-/// Distribution for number of ports to listen on
-#[derive(Debug, Copy, Clone, Deserialize)]
-#[serde(tag = "distribution")]
-pub enum Distribution {
-    /// Few processes are opening large number of ports, the rest are only few.
-    #[serde(alias = "zipf")]
-    Zipfian { n_ports: u64, exponent: f64 },
-
-    /// Every process opens more or less the same number of ports.
-    #[serde(alias = "uniform")]
-    Uniform { lower: u64, upper: u64 },
-}
-
 #[derive(Debug)]
 pub enum WorkerError {
     Internal,
@@ -262,6 +896,9 @@ pub trait Worker {
 struct BaseConfig {
     cpu: CoreId,
     process: usize,
+
+    /// Worker counters, shared with the parent process.
+    stats: &'static WorkerStats,
 }
 
 impl Display for BaseConfig {
@@ -270,10 +907,33 @@ impl Display for BaseConfig {
     }
 }
 
+/// Configuration fixtures shared by tests of all modules.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use config::{File, FileFormat};
+
+    /// Configuration source parsed from TOML.
+    pub fn source(input: &str) -> Config {
+        Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+    }
+
+    /// Configuration deserialized from TOML, without validation.
+    pub fn config(input: &str) -> WorkloadConfig {
+        source(input)
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use config::{Config, File, FileFormat};
+    use rand::Rng;
+    use std::time::Duration;
 
     #[test]
     fn test_processes() {
@@ -287,12 +947,7 @@ mod tests {
             random_process = true
         "#;
 
-        let config = Config::builder()
-            .add_source(File::from_str(input, FileFormat::Toml))
-            .build()
-            .expect("failed to parse configuration")
-            .try_deserialize::<WorkloadConfig>()
-            .expect("failed to deserialize into WorkloadConfig");
+        let config = testing::config(input);
 
         let WorkloadConfig {
             restart_interval,
@@ -300,11 +955,12 @@ mod tests {
             ..
         } = config;
         assert_eq!(restart_interval, 10);
-        if let Workload::Processes {
+        if let Some(Workload::Processes {
             arrival_rate,
             departure_rate,
             random_process,
-        } = workload
+            ..
+        }) = workload
         {
             assert_eq!(arrival_rate, 10.0);
             assert_eq!(departure_rate, 200.0);
@@ -326,12 +982,7 @@ mod tests {
             exponent = 1.4
         "#;
 
-        let config = Config::builder()
-            .add_source(File::from_str(input, FileFormat::Toml))
-            .build()
-            .expect("failed to parse configuration")
-            .try_deserialize::<WorkloadConfig>()
-            .expect("failed to deserialize into WorkloadConfig");
+        let config = testing::config(input);
 
         let WorkloadConfig {
             restart_interval,
@@ -340,9 +991,9 @@ mod tests {
         } = config;
         assert_eq!(restart_interval, 10);
 
-        if let Workload::Endpoints { distribution, .. } = workload {
-            if let Distribution::Zipfian { n_ports, exponent } = distribution {
-                assert_eq!(n_ports, 200);
+        if let Some(Workload::Endpoints { distribution, .. }) = workload {
+            if let Distribution::Zipfian { n, exponent } = distribution {
+                assert_eq!(n, 200);
                 assert_eq!(exponent, 1.4);
             } else {
                 panic!("wrong distribution type found");
@@ -364,12 +1015,7 @@ mod tests {
             lower = 1
         "#;
 
-        let config = Config::builder()
-            .add_source(File::from_str(input, FileFormat::Toml))
-            .build()
-            .expect("failed to parse configuration")
-            .try_deserialize::<WorkloadConfig>()
-            .expect("failed to deserialize into WorkloadConfig");
+        let config = testing::config(input);
 
         let WorkloadConfig {
             restart_interval,
@@ -378,10 +1024,10 @@ mod tests {
         } = config;
         assert_eq!(restart_interval, 10);
 
-        if let Workload::Endpoints { distribution } = workload {
+        if let Some(Workload::Endpoints { distribution }) = workload {
             if let Distribution::Uniform { lower, upper } = distribution {
-                assert_eq!(lower, 1);
-                assert_eq!(upper, 100);
+                assert_eq!(lower, 1.0);
+                assert_eq!(upper, 100.0);
             } else {
                 panic!("wrong distribution type found");
             }
@@ -400,12 +1046,7 @@ mod tests {
             arrival_rate = 10.0
         "#;
 
-        let config = Config::builder()
-            .add_source(File::from_str(input, FileFormat::Toml))
-            .build()
-            .expect("failed to parse configuration")
-            .try_deserialize::<WorkloadConfig>()
-            .expect("failed to deserialize into WorkloadConfig");
+        let config = testing::config(input);
 
         let WorkloadConfig {
             restart_interval,
@@ -413,10 +1054,142 @@ mod tests {
             ..
         } = config;
         assert_eq!(restart_interval, 10);
-        if let Workload::Syscalls { arrival_rate, .. } = workload {
+        if let Some(Workload::Syscalls { arrival_rate, .. }) = workload {
+            assert_eq!(arrival_rate, 10.0);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let input = r#"
+            restart_interval = 10
+            duration = 60
+
+            [[groups]]
+            workers = 2
+            per_core = false
+
+            [groups.workload]
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = false
+
+            [[groups]]
+            name = "getpid"
+
+            [groups.workload]
+            type = "syscalls"
+            arrival_rate = 10.0
+        "#;
+
+        let config = testing::config(input);
+
+        assert!(config.workload.is_none());
+
+        let groups = config.groups();
+        assert_eq!(groups.len(), 2);
+
+        assert_eq!(groups[0].name(), "processes");
+        assert_eq!(groups[0].workers, 2);
+        assert!(!groups[0].per_core);
+
+        assert_eq!(groups[1].name(), "getpid");
+        assert_eq!(groups[1].workers, 1);
+        assert!(groups[1].per_core);
+
+        let group_config = config.for_group(&groups[1]);
+        assert_eq!(group_config.duration, 60);
+        assert!(group_config.groups.is_empty());
+        if let Some(Workload::Syscalls { arrival_rate, .. }) =
+            group_config.workload
+        {
             assert_eq!(arrival_rate, 10.0);
         } else {
             panic!("wrong workload type found");
         }
     }
+
+    #[test]
+    fn test_scenario() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100.0
+
+            [[scenario]]
+            duration = 3
+            scale = 0.1
+
+            [[scenario]]
+            duration = 10
+
+            [[scenario]]
+            duration = 2
+            arrival_rate = 50.0
+            scale = 3.0
+        "#;
+
+        let mut config = testing::config(input);
+
+        assert_eq!(config.scenario.len(), 3);
+        assert_eq!(config.arrival_rate(100.0), 10.0);
+        assert_eq!(config.departure_rate(1.0), 1.0);
+
+        config.start -= Duration::from_secs(5);
+        assert_eq!(config.arrival_rate(100.0), 100.0);
+
+        // The last phase stays in effect after the scenario is over
+        config.start -= Duration::from_secs(20);
+        assert_eq!(config.arrival_rate(100.0), 150.0);
+    }
+
+    #[test]
+    fn test_seed() {
+        let input = r#"
+            restart_interval = 10
+            seed = 42
+
+            [workload]
+            type = "syscalls"
+
+            [[groups]]
+            name = "other"
+            workload = { type = "syscalls" }
+        "#;
+
+        let config = testing::config(input);
+
+        let sample = |config: &WorkloadConfig, cpu, process| -> Vec<u64> {
+            let mut rng = config.rng(cpu, process);
+            (0..4).map(|_| rng.r#gen()).collect()
+        };
+
+        let groups = config.groups();
+        let first = config.for_group(&groups[0]);
+        let second = config.for_group(&groups[1]);
+
+        // The same worker gets the same sequence every time
+        assert_eq!(sample(&first, 0, 0), sample(&first, 0, 0));
+        assert_ne!(sample(&first, 0, 0), sample(&first, 0, 1));
+        assert_ne!(sample(&first, 0, 0), sample(&first, 1, 0));
+        assert_ne!(sample(&first, 0, 0), sample(&second, 0, 0));
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let errors = WorkloadConfig::load(Some("/nonexistent/workload.toml"))
+            .expect_err("missing file has to be reported");
+
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0]
+                .to_string()
+                .starts_with("cannot read /nonexistent/workload.toml: ")
+        );
+    }
 }
@@ -9,127 +9,712 @@
 //!
 //! The execution contains following steps:
 //! * Consume provided configuration
-//! * For each available CPU core spawn specified number of worker processes
+//! * For each workload group and each available CPU core spawn specified number
+//!   of worker processes
 //! * Invoke a workload-specific logic via run_payload
-//! * Wait for all the workers to finish
+//! * Wait for all the workers to finish, reporting and restarting those that
+//!   have failed, and applying the configuration reloaded on SIGHUP
 
 #[macro_use]
 extern crate log;
 extern crate core_affinity;
 
-use config::Config;
-use core_affinity::CoreId;
-use fork::{Fork, fork};
-use itertools::iproduct;
-use nix::sys::signal::{Signal, kill};
-use nix::sys::wait::waitpid;
-use nix::unistd::Pid;
-use std::time::SystemTime;
-use std::{env, thread, time};
+use std::{
+    collections::HashMap,
+    env, fs,
+    ops::Range,
+    process, thread,
+    time::{Duration, Instant, SystemTime},
+};
+use syscalls::Sysno;
 
-use berserker::{WorkloadConfig, worker::new_worker};
+use berserker::cgroup::Cgroups;
+use berserker::cli::{
+    self, Command, CompareOptions, ListKind, MonitorSpec, Options,
+    VerifyOptions,
+};
+use berserker::compare::{Comparison, Monitor};
+use berserker::control::{Control, ControlledGroup, Controller};
+use berserker::events::{self, EventSink};
+use berserker::metrics;
+use berserker::placement::Layout;
+use berserker::reload;
+use berserker::report::{Report, TimeSeries};
+use berserker::stats::{Registry, Source, Summary, WorkerStats, target_rate};
+use berserker::supervisor::{Supervisor, WorkerHandle, WorkerSetup};
+use berserker::usage::{UsageSampler, UsageSummary};
+use berserker::validate::{self, ValidationError};
+use berserker::verify::{self, Mapping};
+use berserker::worker;
+use berserker::{
+    ControlConfig, MetricsConfig, ResultsConfig, TargetConfig, Workload,
+    WorkloadConfig, WorkloadGroup, worker::new_worker,
+};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let default_config = String::from("workload.toml");
-    let config_path = &args.get(1).unwrap_or(&default_config);
-    let duration_timer = SystemTime::now();
-
-    let config = Config::builder()
-        // Add in `./Settings.toml`
-        .add_source(
-            config::File::with_name("/etc/berserker/workload.toml")
-                .required(false),
-        )
-        .add_source(config::File::with_name(config_path).required(false))
-        // Add in settings from the environment (with a prefix of APP)
-        // Eg.. `BERSERKER__WORKLOAD__ARRIVAL_RATE=1` would set the `arrival_rate` key
-        .add_source(
-            config::Environment::with_prefix("BERSERKER")
-                .try_parsing(true)
-                .separator("__"),
-        )
-        .build()
-        .unwrap()
-        .try_deserialize::<WorkloadConfig>()
-        .unwrap();
-
-    let mut lower = 1024;
-    let mut upper = 1024;
-
-    env_logger::init();
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            process::exit(cli::EXIT_USAGE_ERROR);
+        }
+    };
 
-    info!("Config: {:?}", config);
+    match command {
+        Command::Help => println!("{}", cli::USAGE),
+        Command::List(kind) => list(kind),
+        Command::Validate(options) => {
+            init_logger(&options);
+            let config = load_config(&options);
+            println!("Configuration is valid");
+            for group in config.groups() {
+                println!(
+                    "  {}: {} worker(s){}",
+                    group.name(),
+                    group.workers,
+                    if group.per_core { " per core" } else { "" }
+                );
+            }
+        }
+        Command::Verify(options) => {
+            env_logger::init();
+            verify(options);
+        }
+        Command::Run(options) => {
+            init_logger(&options);
+            let config = load_config(&options);
+
+            if let Err(e) = run(config, &options) {
+                error!("{e}");
+                process::exit(cli::EXIT_RUNTIME_ERROR);
+            }
+        }
+        Command::Compare(options, compare_options) => {
+            init_logger(&options);
+            let config = load_config(&options);
+
+            if let Err(e) = compare(config, compare_options) {
+                error!("{e}");
+                process::exit(cli::EXIT_RUNTIME_ERROR);
+            }
+        }
+    }
+}
+
+fn init_logger(options: &Options) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if let Some(level) = &options.log_level {
+        builder.parse_filters(level);
+    }
+
+    builder.init();
+}
+
+/// Read the configuration and apply command line overrides. Exit with a
+/// configuration error if it's not usable.
+fn load_config(options: &Options) -> WorkloadConfig {
+    configure(options).unwrap_or_else(|errors| config_error(errors))
+}
+
+/// Read the configuration and apply command line overrides.
+fn configure(
+    options: &Options,
+) -> Result<WorkloadConfig, Vec<ValidationError>> {
+    let mut config = WorkloadConfig::load(options.config.as_deref())?;
+
+    if let Some(duration) = options.duration {
+        config.duration = duration;
+    }
+
+    if let Some(workers) = options.workers {
+        config.workers = workers;
+        for group in config.groups.iter_mut() {
+            group.workers = workers;
+        }
+    }
+
+    if let Some(seed) = options.seed {
+        config.seed = Some(seed);
+    }
+
+    if let Some(address) = &options.metrics {
+        config.metrics = Some(MetricsConfig {
+            address: address.clone(),
+        });
+    }
+
+    if let Some(target) = &options.target {
+        let config = config.target.get_or_insert_with(TargetConfig::default);
+        config.pid = target.parse().ok();
+        config.comm = config.pid.is_none().then(|| target.clone());
+    }
+
+    if let Some(socket) = &options.control {
+        config.control = Some(ControlConfig {
+            socket: socket.clone(),
+        });
+    }
+
+    // Overrides have to be verified as well
+    let errors = validate::validate(&config);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Printed regardless of the log level, as the workload is still run
+    let warnings = validate::warnings(&config);
+    if !warnings.is_empty() {
+        eprintln!("Configuration warnings:");
+        for w in warnings {
+            eprintln!("  {w}");
+        }
+    }
+
+    Ok(config)
+}
+
+fn config_error(errors: Vec<ValidationError>) -> ! {
+    eprintln!("Invalid configuration:");
+    for e in errors {
+        eprintln!("  {e}");
+    }
+    process::exit(cli::EXIT_CONFIG_ERROR);
+}
+
+fn list(kind: ListKind) {
+    match kind {
+        ListKind::Syscalls => {
+            for syscall in worker::syscalls::SUPPORTED {
+                println!("{:>4} {}", syscall.id(), syscall);
+            }
+            println!(
+                "Any other syscall from {} to {} is invoked without arguments",
+                Sysno::first().id(),
+                Sysno::last().id()
+            );
+        }
+        ListKind::IOUring => {
+            for (code, name) in worker::io_uring::SUPPORTED {
+                println!("{:>4} {}", code, name);
+            }
+        }
+        ListKind::Workloads => {
+            for kind in Workload::KINDS {
+                println!("{kind}");
+            }
+        }
+    }
+}
+
+/// Match the event log with what the monitor has reported, and print how
+/// much was detected.
+fn verify(options: VerifyOptions) {
+    let fail = |path: &str, e: std::io::Error| -> ! {
+        eprintln!("Cannot read {path}: {e}");
+        process::exit(cli::EXIT_CONFIG_ERROR);
+    };
+
+    let mut mapping = match &options.mapping {
+        Some(path) => Mapping::load(path).unwrap_or_else(|e| fail(path, e)),
+        None => Mapping::default(),
+    };
+
+    if let Some(window) = options.window {
+        mapping.window = window;
+    }
+
+    let truth = events::load(&options.events)
+        .unwrap_or_else(|e| fail(&options.events, e));
+    let observed = verify::load_observed(&options.observed)
+        .unwrap_or_else(|e| fail(&options.observed, e));
+
+    info!(
+        "Matching {} generated events with {} reported",
+        truth.len(),
+        observed.len()
+    );
+
+    println!("{}", verify::verify(&truth, &observed, &mapping));
+}
+
+fn run(mut config: WorkloadConfig, options: &Options) -> Result<(), String> {
+    // Pick a seed if none is specified, to be able to reproduce the run
+    let seed = *config.seed.get_or_insert_with(rand::random);
+    info!("Seed: {seed}");
+
+    reload::install()
+        .map_err(|e| format!("Cannot handle SIGHUP to reload: {e}"))?;
+
+    let (summary, failures) = execute(&config, Some(options))?;
+    println!("{summary}");
 
-    let core_ids: Vec<CoreId> = if config.per_core {
-        // Retrieve the IDs of all active CPU cores.
-        core_affinity::get_core_ids().unwrap()
-    } else {
-        vec![CoreId { id: 0 }]
+    if failures > 0 {
+        return Err(format!("Workers have failed {failures} time(s)"));
+    }
+
+    Ok(())
+}
+
+/// Run the workload without and with the monitor in interleaved rounds, and
+/// print the difference. Both use the same seed, so that they generate the
+/// same sequence of events.
+fn compare(
+    mut config: WorkloadConfig,
+    options: CompareOptions,
+) -> Result<(), String> {
+    if config.duration == 0 {
+        return Err(String::from("Comparison needs a limited duration"));
+    }
+
+    let seed = *config.seed.get_or_insert_with(rand::random);
+    info!("Seed: {seed}");
+
+    // Listeners and result files of consecutive runs would clash, and
+    // pausing workers would skew the comparison
+    config.metrics = None;
+    config.results = ResultsConfig::default();
+    config.control = None;
+
+    let mut monitor = match options.monitor {
+        MonitorSpec::Command(command) => Monitor::command(&command),
+        MonitorSpec::Pid(pid) => Monitor::pid(pid),
     };
 
-    let handles: Vec<_> = iproduct!(core_ids.into_iter(), 0..config.workers)
-        .map(|(cpu, process)| {
+    let mut comparison = Comparison::default();
+    let result = (1..=options.rounds).try_for_each(|round| {
+        monitor.pause()?;
+        let baseline = execute(&config, None)?.0;
+        println!(
+            "Round {round}/{}: baseline done in {:.1}s",
+            options.rounds, baseline.elapsed
+        );
+        comparison.baseline.push(baseline);
+
+        monitor.resume()?;
+        thread::sleep(Duration::from_secs(options.warmup));
+        let monitored = execute(&config, None)?.0;
+        println!(
+            "Round {round}/{}: monitored by {monitor} done in {:.1}s",
+            options.rounds, monitored.elapsed
+        );
+        comparison.monitored.push(monitored);
+
+        Ok::<(), String>(())
+    });
+
+    // Whatever has happened, do not leave the monitor stopped
+    let finished = monitor.finish();
+    result.and(finished)?;
+
+    println!("{comparison}");
+    Ok(())
+}
+
+/// Workers of a run, with everything needed to start more of them while
+/// it's in progress.
+struct Run {
+    config: WorkloadConfig,
+    supervisor: Supervisor,
+
+    /// Counters of every worker, to aggregate them at the end of the run.
+    registry: Registry,
+
+    /// Shared controls of every group by name.
+    controls: HashMap<String, &'static Control>,
+
+    /// Workload of every group ever started by name, to summarize results.
+    workloads: HashMap<String, Workload>,
+
+    /// Part files of the event log.
+    parts: Vec<String>,
+
+    /// Range of ports taken by endpoints workers.
+    lower: usize,
+    upper: usize,
+
+    /// Cgroups of workers, removed once the run is over.
+    cgroups: Cgroups,
+}
+
+impl Run {
+    fn new(config: WorkloadConfig) -> Self {
+        Run {
+            supervisor: Supervisor::new(config.restart),
+            config,
+            registry: Registry::default(),
+            controls: HashMap::new(),
+            workloads: HashMap::new(),
+            parts: vec![],
+            lower: 1024,
+            upper: 1024,
+            cgroups: Cgroups::default(),
+        }
+    }
+
+    /// Configuration for workers of a group, sharing controls with them.
+    fn group_config(&mut self, group: &WorkloadGroup) -> WorkloadConfig {
+        let control = *self
+            .controls
+            .entry(group.name().to_string())
+            .or_insert_with(Control::shared);
+
+        let mut config = self.config.for_group(group);
+        config.runtime = Some(control);
+        config
+    }
+
+    /// Spawn workers of a group with process numbers from the range, on CPU
+    /// cores according to the group placement.
+    fn spawn(
+        &mut self,
+        group: &WorkloadGroup,
+        processes: Range<usize>,
+    ) -> Result<(), String> {
+        let group_config = self.group_config(group);
+        self.workloads
+            .insert(group.name().to_string(), group.workload.clone());
+
+        let layout = Layout::new(group)?;
+        let cgroups = match &group.cgroup {
+            Some(config) => self.cgroups.prepare(
+                group.name(),
+                config,
+                &mut group_config.rng(0, 0),
+            )?,
+            None => vec![],
+        };
+
+        info!("Group {}: {:?}", group.name(), group.workload);
+        if layout.pin {
+            info!("Group {} on CPUs {}", group.name(), layout.cpus());
+        }
+
+        for (cpu, process) in layout.workers(processes) {
+            let stats = WorkerStats::shared();
             let worker = new_worker(
-                config.clone(),
+                group_config.clone(),
                 cpu,
                 process,
-                &mut lower,
-                &mut upper,
+                stats,
+                &mut self.lower,
+                &mut self.upper,
             );
 
-            match fork() {
-                Ok(Fork::Parent(child)) => {
-                    info!("Child {}", child);
-                    Some(child)
+            // Every worker logs events to its own part of the log
+            let events = self.config.results.events.as_ref().map(|path| {
+                let part = EventSink::part(path, self.parts.len());
+                let _ = fs::remove_file(&part);
+                self.parts.push(part.clone());
+
+                EventSink {
+                    path: part,
+                    group: group.name().to_string(),
+                    cpu: cpu.id,
+                    process,
+                    tag_paths: self.config.results.tag_paths,
                 }
-                Ok(Fork::Child) => {
-                    if config.per_core {
-                        core_affinity::set_for_current(cpu);
-                    }
-
-                    loop {
-                        worker.run_payload().unwrap();
-                    }
+            });
+
+            // Workers take turns on cgroups the same way as on CPU cores
+            let setup = WorkerSetup {
+                pin: layout.pin,
+                scheduling: group.scheduling.clone(),
+                cgroup: cgroups.get(process % cgroups.len().max(1)).cloned(),
+                namespaces: group.namespaces.as_ref().map(|config| {
+                    config.for_worker(group.name(), cpu.id, process)
+                }),
+            };
+
+            match WorkerHandle::spawn(
+                worker,
+                group.name(),
+                cpu,
+                process,
+                setup,
+                events,
+            ) {
+                Ok(handle) => {
+                    self.supervisor.add(handle);
+                    self.registry.add(Source {
+                        group: group.name().to_string(),
+                        workload: group.workload.kind(),
+                        cpu: cpu.id,
+                        process,
+                        stats,
+                        retired: false,
+                    });
+                }
+                Err(e) => warn!("Failed: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Groups as seen via the control socket.
+    fn controlled(&mut self) -> Vec<ControlledGroup> {
+        self.config
+            .groups()
+            .iter()
+            .map(|group| ControlledGroup {
+                name: group.name().to_string(),
+                config: self.group_config(group),
+            })
+            .collect()
+    }
+
+    /// Apply a new configuration to the run in progress: start and retire
+    /// workers to match the new numbers, change arrival rates via the shared
+    /// controls, and fork again workers of groups changed otherwise.
+    fn reload(&mut self, mut config: WorkloadConfig) {
+        // The same timeline and sequence of events goes on
+        config.seed = config.seed.or(self.config.seed);
+        config.start = self.config.start;
+
+        for section in reload::fixed_changes(&self.config, &config) {
+            warn!("Changes of {section} are applied only to a new run");
+        }
+
+        config.metrics = self.config.metrics.clone();
+        config.results = self.config.results.clone();
+        config.target = self.config.target.clone();
+        config.control = self.config.control.clone();
+
+        let old = std::mem::replace(&mut self.config, config);
+        self.supervisor.set_policy(self.config.restart);
+
+        let groups = self.config.groups();
+        let old_groups = old.groups();
+
+        for removed in old_groups
+            .iter()
+            .filter(|o| !groups.iter().any(|g| g.name() == o.name()))
+        {
+            info!("Removing group {}", removed.name());
+            self.supervisor.retire(removed.name(), 0);
+            self.registry.retire(removed.name(), 0);
+            self.controls.remove(removed.name());
+        }
+
+        for group in groups.iter() {
+            let name = group.name();
+            let previous = old_groups.iter().find(|o| o.name() == name);
+
+            let Some(previous) =
+                previous.filter(|p| !reload::needs_restart(p, group))
+            else {
+                // A new group, or all the workers are laid out differently
+                if previous.is_some() {
+                    self.supervisor.retire(name, 0);
+                    self.registry.retire(name, 0);
+                    self.controls.remove(name);
                 }
-                Err(e) => {
-                    warn!("Failed: {e:?}");
-                    None
+
+                info!("Starting group {name}");
+                if let Err(e) = self.spawn(group, 0..group.workers) {
+                    error!("Cannot start group {name}: {e}");
                 }
+                continue;
+            };
+
+            let control = self.controls[name];
+            let group_config = self.group_config(group);
+
+            // Retired workers are not replaced
+            if group.workers < previous.workers {
+                info!("Retiring workers of group {name}");
+                self.supervisor.retire(name, group.workers);
+                self.registry.retire(name, group.workers);
+            }
+
+            if reload::needs_replace(&old.for_group(previous), &group_config) {
+                info!("Replacing workers of group {name}");
+                control.set_base(None);
+                self.workloads
+                    .insert(name.to_string(), group.workload.clone());
+
+                let (lower, upper) = (&mut self.lower, &mut self.upper);
+                self.supervisor.replace(name, |handle| {
+                    let stats = self
+                        .registry
+                        .sources()
+                        .into_iter()
+                        .rev()
+                        .find(|s| {
+                            !s.retired
+                                && s.group == handle.group
+                                && s.cpu == handle.cpu.id
+                                && s.process == handle.process
+                        })
+                        .map(|s| s.stats)
+                        .unwrap_or_else(WorkerStats::shared);
+
+                    new_worker(
+                        group_config.clone(),
+                        handle.cpu,
+                        handle.process,
+                        stats,
+                        lower,
+                        upper,
+                    )
+                });
+            } else if let Some(rate) = target_rate(&group.workload)
+                && target_rate(&previous.workload) != Some(rate)
+            {
+                info!("Arrival rate of group {name} is set to {rate}");
+                control.set_base(Some(rate));
+                self.workloads
+                    .insert(name.to_string(), group.workload.clone());
             }
-        })
-        .collect();
-
-    info!("In total: {}", upper);
-
-    let processes = &handles.clone();
-
-    thread::scope(|s| {
-        if config.duration != 0 {
-            // Spin a watcher thread
-            s.spawn(move || {
-                loop {
-                    thread::sleep(time::Duration::from_secs(1));
-                    let elapsed = duration_timer.elapsed().unwrap().as_secs();
-
-                    if elapsed > config.duration {
-                        for handle in processes.iter().flatten() {
-                            info!("Terminating: {}", *handle);
-                            let _ =
-                                kill(Pid::from_raw(*handle), Signal::SIGTERM);
-                        }
-
-                        break;
-                    }
+
+            if group.workers > previous.workers {
+                info!("Adding workers to group {name}");
+                if let Err(e) =
+                    self.spawn(group, previous.workers..group.workers)
+                {
+                    error!("Cannot add workers to group {name}: {e}");
                 }
-            });
+            }
+        }
+
+        let controlled = self.controlled();
+        if let Some(controller) = self.supervisor.controller_mut() {
+            controller.set_groups(controlled);
         }
+    }
+}
+
+/// Run the workload once, returning the summary and how many times workers
+/// have failed. With `reload` options the configuration is read again on
+/// SIGHUP.
+fn execute(
+    config: &WorkloadConfig,
+    reload: Option<&Options>,
+) -> Result<(Summary, usize), String> {
+    // Phases and schedules start over with every run
+    let mut config = config.clone();
+    config.start = Instant::now();
+
+    info!("Config: {:?}", config);
+
+    let mut run = Run::new(config);
 
-        s.spawn(move || {
-            for handle in processes.iter().flatten() {
-                info!("waitpid: {}", *handle);
-                waitpid(Pid::from_raw(*handle), None).unwrap();
+    for group in run.config.groups().iter() {
+        run.spawn(group, 0..group.workers)?;
+    }
+
+    info!("In total: {}", run.upper);
+
+    let Run {
+        config,
+        supervisor,
+        registry,
+        ..
+    } = &mut run;
+
+    if supervisor.is_empty() {
+        return Err(String::from("No workers were started"));
+    }
+
+    if let Some(config) = &config.metrics
+        && let Err(e) = metrics::serve(&config.address, registry.clone())
+    {
+        supervisor.stop();
+        supervisor.run(0);
+        return Err(format!("Cannot serve metrics on {}: {e}", config.address));
+    }
+
+    let series = match &config.results.csv {
+        Some(path) => match TimeSeries::start(path, registry.clone()) {
+            Ok(series) => Some(series),
+            Err(e) => {
+                supervisor.stop();
+                supervisor.run(0);
+                return Err(format!("Cannot write time series to {path}: {e}"));
             }
-        });
-    });
+        },
+        None => None,
+    };
+
+    if let Some(control) = run.config.control.clone() {
+        let groups = run.controlled();
+
+        match Controller::serve(&control.socket, groups, run.registry.clone()) {
+            Ok(controller) => run.supervisor.control(controller),
+            Err(e) => {
+                run.supervisor.stop();
+                run.supervisor.run(0);
+                return Err(format!(
+                    "Cannot accept commands on {}: {e}",
+                    control.socket
+                ));
+            }
+        }
+    }
+
+    let sampler = run
+        .config
+        .target
+        .as_ref()
+        .map(|target| UsageSampler::start(target, run.registry.clone()));
+
+    let started = SystemTime::now();
+    let start = Instant::now();
+
+    // The duration might be changed by reloading as well
+    while run.supervisor.run_until(run.config.duration, || {
+        reload.is_some() && reload::requested()
+    }) {
+        let Some(options) = reload else {
+            continue;
+        };
+
+        info!("Reloading configuration");
+        match configure(options) {
+            Ok(config) => run.reload(config),
+            Err(errors) => {
+                for e in errors {
+                    error!("Cannot reload configuration: {e}");
+                }
+            }
+        }
+    }
+
+    let failures = run.supervisor.failures();
+    let finished = SystemTime::now();
+
+    let usage = sampler.map(UsageSampler::stop).unwrap_or_default();
+
+    if let Some(series) = series
+        && let Err(e) = series.stop()
+    {
+        error!("Cannot write time series: {e}");
+    }
+
+    let mut summary = Summary {
+        elapsed: start.elapsed().as_secs_f64(),
+        seed: run.config.seed,
+        target: UsageSummary::new(&usage),
+        ..Summary::default()
+    };
+
+    let sources = run.registry.sources();
+    for source in sources.iter() {
+        summary.add(source, &run.workloads[&source.group]);
+    }
+
+    if let Some(path) = &run.config.results.json {
+        Report::new(&run.config, &sources, usage, started, finished)
+            .write(path)
+            .map_err(|e| format!("Cannot write results to {path}: {e}"))?;
+    }
+
+    if let Some(path) = &run.config.results.events {
+        let count = events::merge(path, &run.parts)
+            .map_err(|e| format!("Cannot write events to {path}: {e}"))?;
+        info!("Logged {count} events to {path}");
+    }
+
+    Ok((summary, failures))
 }
@@ -0,0 +1,258 @@
+//! Live counters exposed over HTTP in OpenMetrics text format, to graph the
+//! generated load next to metrics of the system under test. The listener
+//! runs in a thread of the parent process and reads worker counters from
+//! the shared memory on every scrape.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use log::{info, warn};
+
+use crate::stats::{Registry, Source, StatsSnapshot};
+
+const CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Copy, PartialEq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+/// Exposed metric family, which is reported only for workers of the
+/// specified workload types.
+struct Family {
+    name: &'static str,
+    kind: MetricType,
+    help: &'static str,
+    workloads: &'static [&'static str],
+    value: fn(&StatsSnapshot) -> u64,
+}
+
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "berserker_syscalls",
+        kind: MetricType::Counter,
+        help: "Syscalls and io_uring requests issued, including failed ones",
+        workloads: &["syscalls", "iouring"],
+        value: |s| s.ops,
+    },
+    Family {
+        name: "berserker_processes_spawned",
+        kind: MetricType::Counter,
+        help: "Processes spawned",
+        workloads: &["processes"],
+        value: StatsSnapshot::succeeded,
+    },
+    Family {
+        name: "berserker_processes_exited",
+        kind: MetricType::Counter,
+        help: "Spawned processes that have exited",
+        workloads: &["processes"],
+        value: |s| s.closed,
+    },
+    Family {
+        name: "berserker_connections_opened",
+        kind: MetricType::Counter,
+        help: "Network connections opened",
+        workloads: &["network"],
+        value: StatsSnapshot::succeeded,
+    },
+    Family {
+        name: "berserker_connections_closed",
+        kind: MetricType::Counter,
+        help: "Network connections closed",
+        workloads: &["network"],
+        value: |s| s.closed,
+    },
+    Family {
+        name: "berserker_connections_active",
+        kind: MetricType::Gauge,
+        help: "Network connections currently open",
+        workloads: &["network"],
+        value: StatsSnapshot::active,
+    },
+    Family {
+        name: "berserker_bpf_programs_loaded",
+        kind: MetricType::Counter,
+        help: "BPF programs loaded",
+        workloads: &["bpf"],
+        value: StatsSnapshot::succeeded,
+    },
+    Family {
+        name: "berserker_endpoints_listening",
+        kind: MetricType::Gauge,
+        help: "Ports currently listening",
+        workloads: &["endpoints"],
+        value: StatsSnapshot::active,
+    },
+    Family {
+        name: "berserker_missed",
+        kind: MetricType::Counter,
+        help: "Operations dropped, because the target rate was not sustained",
+        workloads: &["syscalls", "iouring"],
+        value: |s| s.missed,
+    },
+    Family {
+        name: "berserker_errors",
+        kind: MetricType::Counter,
+        help: "Failed operations of all workloads",
+        workloads: &[
+            "processes",
+            "endpoints",
+            "syscalls",
+            "iouring",
+            "network",
+            "bpf",
+        ],
+        value: |s| s.errors,
+    },
+];
+
+/// Render current values of all counters. Workers of the same workload type
+/// on the same CPU are reported together.
+pub fn render(sources: &[Source]) -> String {
+    let mut snapshots: BTreeMap<(&str, usize), StatsSnapshot> = BTreeMap::new();
+
+    for source in sources {
+        snapshots
+            .entry((source.workload, source.cpu))
+            .or_default()
+            .merge(&source.stats.snapshot());
+    }
+
+    let mut out = String::new();
+
+    for family in FAMILIES {
+        let series: Vec<_> = snapshots
+            .iter()
+            .filter(|((workload, _), _)| family.workloads.contains(workload))
+            .collect();
+
+        if series.is_empty() {
+            continue;
+        }
+
+        let (kind, suffix) = match family.kind {
+            MetricType::Counter => ("counter", "_total"),
+            MetricType::Gauge => ("gauge", ""),
+        };
+
+        let _ = writeln!(out, "# TYPE {} {kind}", family.name);
+        let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+
+        for ((workload, cpu), snapshot) in series {
+            let _ = writeln!(
+                out,
+                "{}{suffix}{{workload=\"{workload}\",cpu=\"{cpu}\"}} {}",
+                family.name,
+                (family.value)(snapshot)
+            );
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Start serving metrics on the specified address in a separate thread.
+pub fn serve(address: &str, registry: Registry) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving metrics on {}", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result =
+                stream.and_then(|stream| respond(stream, &registry.sources()));
+
+            if let Err(e) = result {
+                warn!("Metrics request failed: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(stream: TcpStream, sources: &[Source]) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // Skip the headers, nothing interesting there
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, content_type, body) = match path {
+        "/metrics" => ("200 OK", CONTENT_TYPE, render(sources)),
+        _ => ("404 Not Found", "text/plain", String::from("Not found\n")),
+    };
+
+    write!(
+        &stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::WorkerStats;
+
+    fn source(
+        workload: &'static str,
+        cpu: usize,
+        stats: &'static WorkerStats,
+    ) -> Source {
+        Source {
+            group: workload.to_string(),
+            workload,
+            cpu,
+            process: 0,
+            stats,
+            retired: false,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let syscalls = WorkerStats::shared();
+        syscalls.success();
+        syscalls.failure(2);
+
+        let network = WorkerStats::shared();
+        network.success();
+        network.success();
+        network.success();
+        network.close();
+
+        let sources = [
+            source("syscalls", 0, syscalls),
+            source("syscalls", 0, syscalls),
+            source("network", 1, network),
+        ];
+
+        let out = render(&sources);
+
+        assert!(out.contains("# TYPE berserker_syscalls counter\n"));
+        assert!(out.contains(
+            "berserker_syscalls_total{workload=\"syscalls\",cpu=\"0\"} 4\n"
+        ));
+        assert!(out.contains(
+            "berserker_connections_active{workload=\"network\",cpu=\"1\"} 2\n"
+        ));
+        assert!(out.contains(
+            "berserker_errors_total{workload=\"network\",cpu=\"1\"} 0\n"
+        ));
+        assert!(!out.contains("berserker_processes_spawned"));
+        assert!(out.ends_with("# EOF\n"));
+    }
+}
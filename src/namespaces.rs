@@ -0,0 +1,432 @@
+//! Namespaces for workers, so that a collector attributing events to
+//! containers sees the workload coming from a number of distinct ones. Every
+//! worker gets its own set of namespaces, optionally with a hostname and a
+//! root directory of its own, the same way as a container runtime would do.
+//!
+//! A pid namespace applies only to children of the process creating it, thus
+//! workers are started in two steps. A forked helper process unshares the
+//! namespaces and starts the worker as a sibling with `CLONE_PARENT`, so that
+//! the worker is still a child of the supervisor, and the first process of
+//! its pid namespace. Then the helper reports the worker pid and exits.
+//!
+//! The supervisor has other threads, which might hold locks at the moment of
+//! forking, e.g. of the allocator. Thus the helper does not allocate or take
+//! locks, it makes only raw system calls with everything prepared before the
+//! fork, including contents of the user namespace id maps.
+//!
+//! Scheduling settings are applied by the worker itself, when it's in the
+//! namespaces already. Raising priorities, like the fifo policy or a negative
+//! nice, is checked against the initial user namespace and fails in a user
+//! namespace of its own.
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::mount::{MsFlags, mount};
+use nix::sched::CloneFlags;
+use nix::sys::signal::{
+    SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction,
+};
+use nix::sys::wait::waitpid;
+use nix::unistd::{
+    Pid, chdir, chroot, getegid, geteuid, getpid, pipe2, sethostname,
+};
+use serde::{Deserialize, Serialize};
+
+/// Steps of the helper process, it reports the one that failed by number.
+const HELPER_STEPS: [&str; 3] = [
+    "Cannot unshare namespaces",
+    "Cannot map user namespace ids",
+    "Cannot start a worker",
+];
+
+/// Exit code of a worker terminated by SIGTERM, when it's the first process
+/// of a pid namespace. Such a process can't be killed by a signal it doesn't
+/// handle, it exits on its own instead.
+pub const EXIT_TERMINATED: i32 = 128 + libc::SIGTERM;
+
+/// Kind of a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Namespace {
+    Pid,
+    Mnt,
+    Net,
+    Uts,
+    Ipc,
+    User,
+}
+
+impl Namespace {
+    fn flag(&self) -> CloneFlags {
+        match self {
+            Namespace::Pid => CloneFlags::CLONE_NEWPID,
+            Namespace::Mnt => CloneFlags::CLONE_NEWNS,
+            Namespace::Net => CloneFlags::CLONE_NEWNET,
+            Namespace::Uts => CloneFlags::CLONE_NEWUTS,
+            Namespace::Ipc => CloneFlags::CLONE_NEWIPC,
+            Namespace::User => CloneFlags::CLONE_NEWUSER,
+        }
+    }
+}
+
+/// Namespaces to run workers of a group in.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct NamespaceConfig {
+    /// Namespaces every worker gets on its own.
+    pub unshare: Vec<Namespace>,
+
+    /// Hostname of workers in their uts namespace, where `{group}`, `{cpu}`
+    /// and `{process}` are replaced with those of the worker.
+    #[serde(default = "default_hostname")]
+    pub hostname: String,
+
+    /// Directory to use as the root of the worker file system, needs a mnt
+    /// namespace.
+    #[serde(default)]
+    pub rootfs: Option<String>,
+}
+
+fn default_hostname() -> String {
+    String::from("{group}-{cpu}-{process}")
+}
+
+impl NamespaceConfig {
+    pub fn contains(&self, namespace: Namespace) -> bool {
+        self.unshare.contains(&namespace)
+    }
+
+    /// Namespaces of a particular worker.
+    pub fn for_worker(
+        &self,
+        group: &str,
+        cpu: usize,
+        process: usize,
+    ) -> Namespaces {
+        let hostname = self.contains(Namespace::Uts).then(|| {
+            self.hostname
+                .replace("{group}", group)
+                .replace("{cpu}", &cpu.to_string())
+                .replace("{process}", &process.to_string())
+        });
+
+        Namespaces {
+            flags: self
+                .unshare
+                .iter()
+                .fold(CloneFlags::empty(), |flags, ns| flags | ns.flag()),
+            hostname,
+            rootfs: self.rootfs.as_ref().map(PathBuf::from),
+        }
+    }
+}
+
+/// Namespaces of a worker, ready to be created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespaces {
+    flags: CloneFlags,
+    hostname: Option<String>,
+    rootfs: Option<PathBuf>,
+}
+
+impl Namespaces {
+    /// Start `child` in new namespaces via a helper process, returns pid of
+    /// the child. The child is set up further by calling `enter`, and is not
+    /// supposed to return.
+    pub fn spawn(&self, child: impl FnOnce()) -> Result<Pid, String> {
+        let maps = self.id_maps()?;
+        let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC)
+            .map_err(|e| format!("Cannot create a pipe: {e}"))?;
+        let (mut reply, helper) = unsafe {
+            (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd))
+        };
+
+        match unsafe { libc::fork() } {
+            -1 => Err(format!(
+                "Cannot fork a worker: {}",
+                io::Error::last_os_error()
+            )),
+            0 => {
+                drop(reply);
+
+                // Either zero and the worker pid, or the failed step and
+                // errno
+                let (step, value) = match self.unshare(&maps) {
+                    Ok(()) => match start_sibling() {
+                        Ok(0) => {
+                            drop(helper);
+                            child();
+                            unsafe { libc::_exit(0) }
+                        }
+                        Ok(pid) => (0, pid),
+                        Err(e) => (3, e as i32),
+                    },
+                    Err((step, e)) => (step, e as i32),
+                };
+
+                let mut message = [0; 8];
+                message[..4].copy_from_slice(&step.to_ne_bytes());
+                message[4..].copy_from_slice(&value.to_ne_bytes());
+
+                unsafe {
+                    libc::write(
+                        helper.as_raw_fd(),
+                        message.as_ptr().cast(),
+                        message.len(),
+                    );
+                    libc::_exit(0)
+                }
+            }
+            pid => {
+                drop(helper);
+
+                let mut message = vec![];
+                let read = reply.read_to_end(&mut message);
+                let _ = waitpid(Pid::from_raw(pid), None);
+                read.map_err(|e| format!("Cannot start a worker: {e}"))?;
+
+                let Ok(message) = <[u8; 8]>::try_from(message) else {
+                    return Err(String::from("Cannot start a worker"));
+                };
+                let [step, value] = [&message[..4], &message[4..]]
+                    .map(|bytes| i32::from_ne_bytes(bytes.try_into().unwrap()));
+
+                match step {
+                    0 => Ok(Pid::from_raw(value)),
+                    step => Err(format!(
+                        "{}: {}",
+                        HELPER_STEPS[step as usize - 1],
+                        Errno::from_i32(value)
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Paths and contents of files mapping ids of the user namespace, if
+    /// there is one. Only the own ids could be mapped from inside of the user
+    /// namespace, they become root.
+    fn id_maps(&self) -> Result<Vec<(CString, Vec<u8>)>, String> {
+        if !self.flags.contains(CloneFlags::CLONE_NEWUSER) {
+            return Ok(vec![]);
+        }
+
+        let (uid, gid) = (geteuid(), getegid());
+        let map = |path: &str, content: String| {
+            CString::new(path)
+                .map(|path| (path, content.into_bytes()))
+                .map_err(|e| format!("Cannot map user namespace ids: {e}"))
+        };
+
+        Ok(vec![
+            map("/proc/self/uid_map", format!("0 {uid} 1"))?,
+            map("/proc/self/setgroups", String::from("deny"))?,
+            map("/proc/self/gid_map", format!("0 {gid} 1"))?,
+        ])
+    }
+
+    /// Create the namespaces and write the id `maps`, the next child of the
+    /// current process is the first one to enter the pid namespace. Runs in
+    /// the helper process, thus makes only raw system calls. On error returns
+    /// the failed step, counting from one.
+    fn unshare(&self, maps: &[(CString, Vec<u8>)]) -> Result<(), (i32, Errno)> {
+        if unsafe { libc::unshare(self.flags.bits()) } < 0 {
+            return Err((1, Errno::last()));
+        }
+
+        for (path, content) in maps {
+            write_file(path, content).map_err(|e| (2, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Set up the namespaces from inside, the worker does it right before
+    /// running the payload.
+    pub fn enter(&self) -> Result<(), String> {
+        if let Some(hostname) = &self.hostname {
+            sethostname(hostname)
+                .map_err(|e| format!("Cannot set hostname {hostname}: {e}"))?;
+        }
+
+        if self.flags.contains(CloneFlags::CLONE_NEWNS) {
+            // Do not propagate mounts of the worker back to the host
+            mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&str>,
+            )
+            .map_err(|e| format!("Cannot make mounts private: {e}"))?;
+
+            if let Some(rootfs) = &self.rootfs {
+                chroot(rootfs).and_then(|_| chdir("/")).map_err(|e| {
+                    format!("Cannot change root to {rootfs:?}: {e}")
+                })?;
+            }
+
+            // Processes of the namespace are visible only with its own proc
+            if self.flags.contains(CloneFlags::CLONE_NEWPID) {
+                mount(
+                    Some("proc"),
+                    "/proc",
+                    Some("proc"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                    None::<&str>,
+                )
+                .map_err(|e| format!("Cannot mount /proc: {e}"))?;
+            }
+        }
+
+        if self.flags.contains(CloneFlags::CLONE_NEWNET) {
+            loopback_up()
+                .map_err(|e| format!("Cannot bring up loopback: {e}"))?;
+        }
+
+        if self.flags.contains(CloneFlags::CLONE_NEWPID) {
+            exit_on_sigterm()
+                .map_err(|e| format!("Cannot handle SIGTERM: {e}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fork a process sharing the parent with the current one, returns zero in
+/// the new process as `fork` does.
+fn start_sibling() -> Result<libc::pid_t, Errno> {
+    let flags = libc::CLONE_PARENT | libc::SIGCHLD;
+    let pid = unsafe { libc::syscall(libc::SYS_clone, flags, 0, 0, 0, 0) };
+
+    if pid < 0 {
+        return Err(Errno::last());
+    }
+
+    Ok(pid as libc::pid_t)
+}
+
+/// Write `content` to the file at `path`, with raw system calls only.
+fn write_file(path: &CString, content: &[u8]) -> Result<(), Errno> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY) };
+    if fd < 0 {
+        return Err(Errno::last());
+    }
+
+    let written =
+        unsafe { libc::write(fd, content.as_ptr().cast(), content.len()) };
+    let error = Errno::last();
+    unsafe { libc::close(fd) };
+
+    match written {
+        n if n < 0 => Err(error),
+        n if n as usize != content.len() => Err(Errno::EIO),
+        _ => Ok(()),
+    }
+}
+
+/// Pid of the current process as seen by the supervisor, it differs from
+/// `getpid` inside of a pid namespace. Has to be taken before /proc is
+/// mounted anew.
+pub fn host_pid() -> Pid {
+    fs::read_link("/proc/self")
+        .ok()
+        .and_then(|link| link.to_str()?.parse().ok())
+        .map(Pid::from_raw)
+        .unwrap_or_else(getpid)
+}
+
+/// New network namespaces have only the loopback interface, and it's down.
+fn loopback_up() -> io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { File::from_raw_fd(socket) };
+    let fd = socket.as_raw_fd();
+
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in request.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+
+    unsafe {
+        if libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut request as *mut libc::ifreq)
+            < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+
+        if libc::ioctl(fd, libc::SIOCSIFFLAGS, &request as *const libc::ifreq)
+            < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+extern "C" fn exit_terminated(_: libc::c_int) {
+    unsafe { libc::_exit(EXIT_TERMINATED) }
+}
+
+/// Exit on SIGTERM, unless it's handled already, e.g. to write out events.
+fn exit_on_sigterm() -> nix::Result<()> {
+    let exit = SigAction::new(
+        SigHandler::Handler(exit_terminated),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+
+    unsafe {
+        let previous = sigaction(Signal::SIGTERM, &exit)?;
+        if previous.handler() != SigHandler::SigDfl {
+            sigaction(Signal::SIGTERM, &previous)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces() {
+        let config: NamespaceConfig = toml::from_str(
+            r#"
+            unshare = ["pid", "uts", "net"]
+            "#,
+        )
+        .unwrap();
+
+        let namespaces = config.for_worker("syscalls", 2, 5);
+        assert_eq!(
+            namespaces.flags,
+            CloneFlags::CLONE_NEWPID
+                | CloneFlags::CLONE_NEWUTS
+                | CloneFlags::CLONE_NEWNET
+        );
+        assert_eq!(namespaces.hostname.as_deref(), Some("syscalls-2-5"));
+        assert_eq!(namespaces.rootfs, None);
+
+        // Without a uts namespace the hostname is left alone
+        let config = NamespaceConfig {
+            unshare: vec![Namespace::Mnt],
+            hostname: String::from("pod-{process}"),
+            rootfs: Some(String::from("/var/lib/rootfs")),
+        };
+        let namespaces = config.for_worker("syscalls", 0, 1);
+        assert_eq!(namespaces.hostname, None);
+        assert_eq!(namespaces.rootfs, Some(PathBuf::from("/var/lib/rootfs")));
+    }
+}
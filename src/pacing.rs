@@ -0,0 +1,313 @@
+//! Pacing of workers issuing many cheap operations, like syscalls or io_uring
+//! requests. Sleeping for every sampled interval accumulates sleep overshoot
+//! and caps the achieved rate well below the target at high rates. Instead
+//! every event gets an absolute deadline, and a worker waits only until the
+//! next deadline, issuing all events that became overdue in the meantime in
+//! one batch. If the worker falls behind too much, the overdue events are
+//! dropped and counted as missed, meaning the target rate is not sustainable.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{
+    ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags,
+};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use crate::WorkloadConfig;
+use crate::rate::Arrivals;
+use crate::stats::WorkerStats;
+
+/// Do not repeat the warning about missed events more often, in seconds.
+const WARNING_INTERVAL: f64 = 10.0;
+
+/// Longest wait at once in seconds, the deadline is infinite when no more
+/// events are going to happen.
+const MAX_WAIT: f64 = 1.0;
+
+/// How to wait for the next deadline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wait {
+    /// Sleep, cheap but the wake up could be late by tens of microseconds.
+    #[default]
+    Sleep,
+
+    /// Busy loop, the most precise, but occupies the whole CPU core.
+    Spin,
+
+    /// Wait for a one-shot timer, which has timer slack of its own but is
+    /// not affected by signals.
+    Timerfd,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Pacing {
+    #[serde(default)]
+    pub wait: Wait,
+
+    /// Maximum number of overdue events issued back to back.
+    #[serde(default = "default_max_batch")]
+    pub max_batch: u32,
+
+    /// How far behind the schedule a worker could fall in seconds, before
+    /// overdue events are dropped.
+    #[serde(default = "default_max_lag")]
+    pub max_lag: f64,
+}
+
+fn default_max_batch() -> u32 {
+    64
+}
+
+fn default_max_lag() -> f64 {
+    1.0
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing {
+            wait: Wait::default(),
+            max_batch: default_max_batch(),
+            max_lag: default_max_lag(),
+        }
+    }
+}
+
+pub struct Pacer<'a> {
+    arrivals: Arrivals<'a>,
+    rng: StdRng,
+    pacing: Pacing,
+    stats: &'static WorkerStats,
+
+    /// Beginning of the run, deadlines are relative to it.
+    start: Instant,
+
+    /// Deadline of the next event in seconds since the start.
+    deadline: f64,
+
+    timer: Option<TimerFd>,
+
+    /// When missed events were reported last time.
+    warned: Option<f64>,
+}
+
+impl<'a> Pacer<'a> {
+    /// Pace events with `base` rate per second of the workload.
+    pub fn new(
+        config: &'a WorkloadConfig,
+        base: f64,
+        pacing: &Pacing,
+        mut rng: StdRng,
+        stats: &'static WorkerStats,
+    ) -> nix::Result<Self> {
+        let timer = match pacing.wait {
+            Wait::Timerfd => Some(TimerFd::new(
+                ClockId::CLOCK_MONOTONIC,
+                TimerFlags::TFD_CLOEXEC,
+            )?),
+            _ => None,
+        };
+
+        let mut arrivals = Arrivals::new(config, base);
+        let deadline =
+            config.start.elapsed().as_secs_f64() + arrivals.next(&mut rng);
+
+        Ok(Pacer {
+            arrivals,
+            rng,
+            pacing: pacing.clone(),
+            stats,
+            start: config.start,
+            deadline,
+            timer,
+            warned: None,
+        })
+    }
+
+    /// Wait until the next event is due, and fill `due` with intended start
+    /// times of all events that have to be issued right away. Latency of an
+    /// event has to be measured from its intended start, otherwise stalls of
+    /// the worker would be hidden.
+    pub fn wait(&mut self, due: &mut Vec<Instant>) -> nix::Result<()> {
+        due.clear();
+
+        loop {
+            let now = self.start.elapsed().as_secs_f64();
+
+            match self.poll(now, due) {
+                Some(seconds) => self.wait_for(seconds.min(MAX_WAIT))?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Fill `due` with events due at `now` in seconds since the start, or
+    /// return how long to wait for the next event if none is due yet.
+    fn poll(&mut self, now: f64, due: &mut Vec<Instant>) -> Option<f64> {
+        // Events scheduled before a pause are not missed, start over
+        if self.arrivals.resume() {
+            self.deadline = now + self.arrivals.next(&mut self.rng);
+        } else if now - self.deadline > self.pacing.max_lag {
+            self.skip(now);
+        }
+
+        if self.deadline > now {
+            return Some(self.deadline - now);
+        }
+
+        while self.deadline <= now && due.len() < self.pacing.max_batch as usize
+        {
+            due.push(self.start + Duration::from_secs_f64(self.deadline));
+            self.deadline += self.arrivals.next(&mut self.rng);
+        }
+
+        None
+    }
+
+    fn wait_for(&self, seconds: f64) -> nix::Result<()> {
+        let duration = Duration::from_secs_f64(seconds);
+
+        match (self.pacing.wait, &self.timer) {
+            (Wait::Timerfd, Some(timer)) => {
+                // Zero disarms the timer, it would never expire then
+                let duration = duration.max(Duration::from_nanos(1));
+                timer.set(
+                    Expiration::OneShot(TimeSpec::from_duration(duration)),
+                    TimerSetTimeFlags::empty(),
+                )?;
+                timer.wait()
+            }
+            (Wait::Spin, _) => {
+                let until = Instant::now() + duration;
+                while Instant::now() < until {
+                    std::hint::spin_loop();
+                }
+                Ok(())
+            }
+            _ => {
+                thread::sleep(duration);
+                Ok(())
+            }
+        }
+    }
+
+    /// Drop all events overdue at `now`, counting them as missed. Sampling
+    /// every dropped event could take longer than issuing it, so the number
+    /// of events is estimated from the current rate instead.
+    fn skip(&mut self, now: f64) {
+        let behind = now - self.deadline;
+
+        // The next event was already sampled and is overdue as well
+        let missed = 1 + self.arrivals.skip(now, &mut self.rng);
+        self.deadline = now + self.arrivals.next(&mut self.rng);
+
+        self.stats.miss(missed);
+
+        if self.warned.is_none_or(|t| now - t >= WARNING_INTERVAL) {
+            warn!(
+                "Target rate is not sustained, {behind:.3}s behind, \
+                 {missed} events missed"
+            );
+            self.warned = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use rand::SeedableRng;
+
+    fn config(rate: f64) -> WorkloadConfig {
+        testing::config(&format!(
+            r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = {rate}
+            "#
+        ))
+    }
+
+    fn pacer<'a>(
+        config: &'a WorkloadConfig,
+        rate: f64,
+        pacing: &Pacing,
+        stats: &'static WorkerStats,
+    ) -> Pacer<'a> {
+        let rng = StdRng::seed_from_u64(1);
+        Pacer::new(config, rate, pacing, rng, stats).unwrap()
+    }
+
+    #[test]
+    fn test_batches() {
+        let config = config(100_000.0);
+        let pacing = Pacing::default();
+        let mut pacer =
+            pacer(&config, 100_000.0, &pacing, WorkerStats::shared());
+        let first = pacer.deadline;
+        let mut due = vec![];
+
+        // Nothing is due before the first deadline
+        let wait = pacer.poll(first - 0.001, &mut due).unwrap();
+        assert!((wait - 0.001).abs() < 1e-9, "{wait}");
+        assert!(due.is_empty());
+
+        // About 1000 events are overdue 10ms later, issued in batches
+        let now = first + 0.01;
+        let mut total = 0;
+        while pacer.poll(now, &mut due).is_none() {
+            assert!(!due.is_empty() && due.len() <= pacing.max_batch as usize);
+            assert!(due.is_sorted());
+            total += due.len();
+            due.clear();
+        }
+
+        assert!((900..1100).contains(&total), "issued: {total}");
+        assert!(pacer.deadline > now);
+        assert_eq!(pacer.stats.snapshot().missed, 0);
+    }
+
+    #[test]
+    fn test_missed() {
+        let config = config(1000.0);
+        let stats = WorkerStats::shared();
+        let pacing = Pacing {
+            max_lag: 0.1,
+            ..Pacing::default()
+        };
+        let mut pacer = pacer(&config, 1000.0, &pacing, stats);
+        let mut due = vec![];
+
+        // Half a second behind, the overdue events are dropped
+        let now = pacer.deadline + 0.5;
+        let wait = pacer.poll(now, &mut due).unwrap();
+        assert!(due.is_empty());
+
+        let missed = stats.snapshot().missed;
+        assert!((450..550).contains(&missed), "missed: {missed}");
+
+        // And the schedule goes on from there
+        assert_eq!(pacer.poll(now + wait, &mut due), None);
+        assert_eq!(due.len(), 1);
+    }
+
+    #[test]
+    fn test_timerfd_short_wait() {
+        let config = config(1000.0);
+        let pacing = Pacing {
+            wait: Wait::Timerfd,
+            ..Pacing::default()
+        };
+        let pacer = pacer(&config, 1000.0, &pacing, WorkerStats::shared());
+
+        // Rounds down to zero nanoseconds
+        pacer.wait_for(1e-12).unwrap();
+    }
+}
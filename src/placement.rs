@@ -0,0 +1,403 @@
+//! Placement of workers on CPU cores. By default workers in per-core mode
+//! run on every available core, and otherwise are not pinned at all. With a
+//! placement configured, the cores are picked from an explicit list minus
+//! the reserved ones, e.g. those left to the monitoring agent, and ordered by
+//! a strategy taking the CPU topology into account. In per-core mode the
+//! strategy decides which cores to use, otherwise workers are pinned to the
+//! cores one by one in that order.
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::fs;
+use std::ops::Range;
+use std::str::FromStr;
+
+use core_affinity::CoreId;
+use itertools::{Itertools, iproduct};
+use serde::{Deserialize, Serialize};
+
+use crate::WorkloadGroup;
+
+/// Set of CPUs in the kernel list format, e.g. "2-7,12".
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CpuSet(BTreeSet<usize>);
+
+impl CpuSet {
+    pub fn contains(&self, cpu: usize) -> bool {
+        self.0.contains(&cpu)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let cpu = |value: &str| {
+            value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid CPU '{value}' in '{list}'"))
+        };
+
+        let mut cpus = BTreeSet::new();
+
+        for item in list.split(',').filter(|item| !item.trim().is_empty()) {
+            match item.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (cpu(first)?, cpu(last)?);
+                    if first > last {
+                        return Err(format!(
+                            "invalid CPU range '{}' in '{list}'",
+                            item.trim()
+                        ));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => {
+                    cpus.insert(cpu(item)?);
+                }
+            }
+        }
+
+        Ok(CpuSet(cpus))
+    }
+}
+
+impl TryFrom<String> for CpuSet {
+    type Error = String;
+
+    fn try_from(list: String) -> Result<Self, Self::Error> {
+        list.parse()
+    }
+}
+
+impl From<CpuSet> for String {
+    fn from(cpus: CpuSet) -> Self {
+        cpus.to_string()
+    }
+}
+
+impl Display for CpuSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Consecutive CPUs are folded into ranges
+        let ranges = self
+            .0
+            .iter()
+            .copied()
+            .enumerate()
+            .group_by(|(i, cpu)| cpu - i)
+            .into_iter()
+            .map(|(_, mut range)| {
+                let (_, first) = range.next().unwrap_or_default();
+                match range.last() {
+                    Some((_, last)) => format!("{first}-{last}"),
+                    None => first.to_string(),
+                }
+            })
+            .join(",");
+
+        write!(f, "{ranges}")
+    }
+}
+
+/// How to order CPU cores for workers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Fill a physical core with its hyperthreads, then the next core of
+    /// the same NUMA node, it's the default.
+    #[default]
+    Compact,
+
+    /// Put consecutive workers on different NUMA nodes and physical cores,
+    /// hyperthreads of the same core are used last.
+    Spread,
+
+    /// Use only one core of every NUMA node.
+    NumaNode,
+
+    /// The same as compact, but without the physical core of CPU 0, which
+    /// usually handles most of the housekeeping.
+    #[serde(rename = "avoid-core-0")]
+    AvoidCore0,
+}
+
+/// Where workers of a group are allowed to run.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Placement {
+    /// CPUs to pick from, all available ones if not specified.
+    #[serde(default)]
+    pub cpus: Option<CpuSet>,
+
+    /// CPUs to keep free from workers, e.g. for the monitoring agent.
+    #[serde(default)]
+    pub reserved: CpuSet,
+
+    #[serde(default)]
+    pub strategy: Strategy,
+}
+
+/// A logical CPU and where it's located.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cpu {
+    pub id: usize,
+    pub node: usize,
+    pub package: usize,
+
+    /// Physical core within the package.
+    pub core: usize,
+}
+
+impl Cpu {
+    /// Find out the location of a CPU from sysfs. Whatever is missing is
+    /// treated as a separate physical core on the first node.
+    fn detect(id: usize, nodes: &[(usize, CpuSet)]) -> Cpu {
+        let read = |name: &str| {
+            fs::read_to_string(format!(
+                "/sys/devices/system/cpu/cpu{id}/topology/{name}"
+            ))
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+        };
+
+        Cpu {
+            id,
+            node: nodes
+                .iter()
+                .find(|(_, cpus)| cpus.contains(id))
+                .map(|(node, _)| *node)
+                .unwrap_or_default(),
+            package: read("physical_package_id").unwrap_or_default(),
+            core: read("core_id").unwrap_or(id),
+        }
+    }
+
+    fn same_core(&self, other: &Cpu) -> bool {
+        (self.node, self.package, self.core)
+            == (other.node, other.package, other.core)
+    }
+}
+
+/// Available CPUs with their locations.
+pub fn topology() -> Option<Vec<Cpu>> {
+    let nodes: Vec<_> = fs::read_dir("/sys/devices/system/node")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let node = name.strip_prefix("node")?.parse().ok()?;
+            let cpus = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            Some((node, cpus.trim().parse().ok()?))
+        })
+        .collect();
+
+    let cpus = core_affinity::get_core_ids()?
+        .iter()
+        .map(|core| Cpu::detect(core.id, &nodes))
+        .collect();
+
+    Some(cpus)
+}
+
+impl Placement {
+    /// CPUs to run workers on in order of preference, picked from the
+    /// available ones.
+    pub fn select(&self, available: &[Cpu]) -> Vec<usize> {
+        let mut cpus: Vec<Cpu> = available
+            .iter()
+            .filter(|cpu| self.cpus.as_ref().is_none_or(|c| c.contains(cpu.id)))
+            .filter(|cpu| !self.reserved.contains(cpu.id))
+            .copied()
+            .collect();
+
+        cpus.sort_by_key(|cpu| (cpu.node, cpu.package, cpu.core, cpu.id));
+
+        match self.strategy {
+            Strategy::Compact => (),
+            Strategy::Spread => {
+                // Rank hyperthreads within their core, and cores within
+                // their node by the first hyperthread
+                let first = |cpu: &Cpu| {
+                    cpus.iter()
+                        .filter(|c| c.same_core(cpu))
+                        .map(|c| c.id)
+                        .min()
+                        .unwrap_or(cpu.id)
+                };
+
+                let ranks: Vec<_> = cpus
+                    .iter()
+                    .map(|cpu| {
+                        let sibling = cpus
+                            .iter()
+                            .filter(|c| c.same_core(cpu) && c.id < cpu.id)
+                            .count();
+                        let core = cpus
+                            .iter()
+                            .filter(|c| c.node == cpu.node && first(c) == c.id)
+                            .filter(|c| c.id < first(cpu))
+                            .count();
+                        (sibling, core, cpu.node, cpu.id)
+                    })
+                    .sorted()
+                    .collect();
+
+                return ranks.into_iter().map(|(.., id)| id).collect();
+            }
+            Strategy::NumaNode => cpus.dedup_by_key(|cpu| cpu.node),
+            Strategy::AvoidCore0 => {
+                if let Some(first) = available.iter().find(|cpu| cpu.id == 0) {
+                    cpus.retain(|cpu| !cpu.same_core(first));
+                }
+            }
+        }
+
+        cpus.into_iter().map(|cpu| cpu.id).collect()
+    }
+}
+
+/// CPU cores workers of a group run on.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    cores: Vec<CoreId>,
+    per_core: bool,
+
+    /// Whether workers are pinned to their cores.
+    pub pin: bool,
+}
+
+impl Layout {
+    pub fn new(group: &WorkloadGroup) -> Result<Layout, String> {
+        let Some(placement) = &group.placement else {
+            let cores = if group.per_core {
+                // Retrieve the IDs of all active CPU cores.
+                core_affinity::get_core_ids()
+                    .ok_or("Cannot retrieve CPU cores")?
+            } else {
+                vec![CoreId { id: 0 }]
+            };
+
+            return Ok(Layout {
+                cores,
+                per_core: group.per_core,
+                pin: group.per_core,
+            });
+        };
+
+        let available = topology().ok_or("Cannot retrieve CPU cores")?;
+        let cores: Vec<_> = placement
+            .select(&available)
+            .into_iter()
+            .map(|id| CoreId { id })
+            .collect();
+
+        if cores.is_empty() {
+            return Err(format!(
+                "No CPU cores left for group {} out of {}",
+                group.name(),
+                available.iter().map(|cpu| cpu.id).join(",")
+            ));
+        }
+
+        Ok(Layout {
+            cores,
+            per_core: group.per_core,
+            pin: true,
+        })
+    }
+
+    /// CPU core of every worker with a process number from the range. In
+    /// per-core mode there are workers with the same process numbers on
+    /// every core, otherwise workers take turns on the cores.
+    pub fn workers(&self, processes: Range<usize>) -> Vec<(CoreId, usize)> {
+        if self.per_core {
+            iproduct!(self.cores.iter().copied(), processes).collect()
+        } else {
+            processes
+                .map(|process| {
+                    (self.cores[process % self.cores.len()], process)
+                })
+                .collect()
+        }
+    }
+
+    /// CPU cores in use, in the kernel list format.
+    pub fn cpus(&self) -> String {
+        self.cores.iter().map(|core| core.id).join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placement() {
+        let cpus: CpuSet = "2-7, 12,0".parse().unwrap();
+        assert_eq!(
+            cpus.iter().collect::<Vec<_>>(),
+            vec![0, 2, 3, 4, 5, 6, 7, 12]
+        );
+        assert_eq!(cpus.to_string(), "0,2-7,12");
+        assert!("3-1".parse::<CpuSet>().is_err());
+        assert!("1,x".parse::<CpuSet>().is_err());
+
+        // Two nodes with two physical cores each, hyperthreads of a core
+        // are numbered by 4
+        let topology: Vec<_> = (0..8)
+            .map(|id| Cpu {
+                id,
+                node: id % 4 / 2,
+                package: id % 4 / 2,
+                core: id % 2,
+            })
+            .collect();
+
+        let select = |strategy, cpus: Option<&str>, reserved: &str| {
+            Placement {
+                cpus: cpus.map(|cpus| cpus.parse().unwrap()),
+                reserved: reserved.parse().unwrap(),
+                strategy,
+            }
+            .select(&topology)
+        };
+
+        assert_eq!(
+            select(Strategy::Compact, None, ""),
+            vec![0, 4, 1, 5, 2, 6, 3, 7]
+        );
+        assert_eq!(
+            select(Strategy::Spread, None, ""),
+            vec![0, 2, 1, 3, 4, 6, 5, 7]
+        );
+        assert_eq!(select(Strategy::NumaNode, None, "0"), vec![4, 2]);
+        assert_eq!(
+            select(Strategy::AvoidCore0, None, ""),
+            vec![1, 5, 2, 6, 3, 7]
+        );
+        assert_eq!(select(Strategy::Compact, Some("0-3"), "1"), vec![0, 2, 3]);
+        assert!(select(Strategy::Spread, Some("1"), "1").is_empty());
+
+        let layout = Layout {
+            cores: vec![CoreId { id: 2 }, CoreId { id: 3 }],
+            per_core: false,
+            pin: true,
+        };
+        let cores: Vec<_> = layout
+            .workers(1..4)
+            .into_iter()
+            .map(|(core, process)| (core.id, process))
+            .collect();
+        assert_eq!(cores, vec![(3, 1), (2, 2), (3, 3)]);
+    }
+}
@@ -0,0 +1,182 @@
+//! USDT probes fired for every generated event, to correlate what berserker
+//! has generated with what the kernel and the monitoring tools have seen,
+//! e.g. `bpftrace -e 'usdt:./berserker:berserker:syscall_issued { ... }'`.
+//!
+//! The `usdt` crate has no probe backend on Linux, its probes are compiled
+//! into no-ops there. Thus probes are described directly as SystemTap SDT
+//! notes in the `.note.stapsdt` section, the format understood by bpftrace,
+//! bcc and perf.
+//! Every probe is a single `nop` instruction, its arguments are described in
+//! the note as 64 bit signed integers in registers. There is no semaphore,
+//! so arguments are always computed, which is cheap enough for integers.
+
+/// Register name prefix in the argument description, x86 registers are
+/// expected in AT&T notation.
+#[cfg(target_arch = "x86_64")]
+macro_rules! reg_prefix {
+    () => {
+        "%"
+    };
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+macro_rules! reg_prefix {
+    () => {
+        ""
+    };
+}
+
+/// Define a probe site with the specified name and arguments, which have to
+/// be variables of `i64` type.
+macro_rules! probe {
+    ($name:literal, $first:ident $(, $arg:ident)*) => {
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        unsafe {
+            std::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt, \"\", %note",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                // Probe address, base address to detect prelinking (not
+                // used) and semaphore address (none)
+                "993: .8byte 990b",
+                ".8byte 0",
+                ".8byte 0",
+                ".asciz \"berserker\"",
+                concat!(".asciz \"", $name, "\""),
+                concat!(
+                    ".asciz \"-8@", reg_prefix!(), "{", stringify!($first), "}",
+                    $(" -8@", reg_prefix!(), "{", stringify!($arg), "}",)*
+                    "\""
+                ),
+                "994: .balign 4",
+                ".popsection",
+                $first = in(reg) $first,
+                $($arg = in(reg) $arg,)*
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        #[cfg(not(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        )))]
+        {
+            let _ = ($first, $($arg,)*);
+        }
+    };
+}
+
+/// A syscall was issued, `ret` is its result or a negated errno.
+pub fn syscall_issued(cpu: usize, process: usize, sysno: u32, ret: i64) {
+    let (cpu, process, sysno) = (cpu as i64, process as i64, sysno as i64);
+    probe!("syscall_issued", cpu, process, sysno, ret);
+}
+
+/// A new process was spawned.
+pub fn process_spawned(cpu: usize, process: usize, pid: i32) {
+    let (cpu, process, pid) = (cpu as i64, process as i64, pid as i64);
+    probe!("process_spawned", cpu, process, pid);
+}
+
+/// A new connection was opened. Addresses are IPv4 addresses in host byte
+/// order.
+pub fn connection_opened(
+    cpu: usize,
+    process: usize,
+    local: (u32, u16),
+    remote: (u32, u16),
+) {
+    let (cpu, process) = (cpu as i64, process as i64);
+    let (laddr, lport) = (local.0 as i64, local.1 as i64);
+    let (raddr, rport) = (remote.0 as i64, remote.1 as i64);
+    probe!(
+        "connection_opened",
+        cpu,
+        process,
+        laddr,
+        lport,
+        raddr,
+        rport
+    );
+}
+
+/// A BPF program was loaded, `fd` is the program file descriptor or a
+/// negated errno.
+pub fn bpf_prog_loaded(cpu: usize, process: usize, fd: i64) {
+    let (cpu, process) = (cpu as i64, process as i64);
+    probe!("bpf_prog_loaded", cpu, process, fd);
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod tests {
+    use super::*;
+    use object::Endianness;
+    use object::elf::{FileHeader64, SHT_NOTE};
+    use object::read::elf::{FileHeader, SectionHeader};
+
+    /// Provider, name and number of arguments of every probe in the notes of
+    /// the current executable.
+    fn notes() -> Vec<(String, String, usize)> {
+        let exe = std::env::current_exe().unwrap();
+        let data = std::fs::read(exe).unwrap();
+
+        let header = FileHeader64::<Endianness>::parse(&*data).unwrap();
+        let endian = header.endian().unwrap();
+        let sections = header.sections(endian, &*data).unwrap();
+
+        let mut probes = vec![];
+        for section in sections.iter() {
+            let name = sections.section_name(endian, section).unwrap();
+            if name != b".note.stapsdt" || section.sh_type(endian) != SHT_NOTE {
+                continue;
+            }
+
+            let mut notes =
+                section.notes(endian, &*data).unwrap().expect("no notes");
+            while let Some(note) = notes.next().unwrap() {
+                assert_eq!(note.name(), b"stapsdt");
+                assert_eq!(note.n_type(endian), 3);
+
+                // Three addresses followed by three strings
+                let strings: Vec<_> = note.desc()[24..]
+                    .split(|b| *b == 0)
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+                    .collect();
+                let args = strings[2].split_whitespace().count();
+                probes.push((strings[0].clone(), strings[1].clone(), args));
+            }
+        }
+
+        probes
+    }
+
+    #[test]
+    fn test_notes() {
+        // Probes are nops, but are not going to be left out
+        syscall_issued(0, 0, 0, 0);
+        process_spawned(0, 0, 0);
+        connection_opened(0, 0, (0, 0), (0, 0));
+        bpf_prog_loaded(0, 0, 0);
+
+        let notes = notes();
+        for (name, args) in [
+            ("syscall_issued", 4),
+            ("process_spawned", 3),
+            ("connection_opened", 6),
+            ("bpf_prog_loaded", 3),
+        ] {
+            let note = (String::from("berserker"), String::from(name), args);
+            assert!(notes.contains(&note), "{note:?} not in {notes:?}");
+        }
+    }
+}
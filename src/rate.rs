@@ -0,0 +1,461 @@
+//! Time-varying arrival rates. A schedule describes how the arrival rate of
+//! a workload is scaled over time, events are then generated as a
+//! non-homogeneous Poisson process via thinning: candidates are sampled from
+//! a homogeneous process with the maximum possible rate, and every candidate
+//! is accepted with probability of the current rate to the maximum rate.
+
+use std::f64::consts::PI;
+
+use rand::Rng;
+use rand_distr::Exp;
+use serde::{Deserialize, Serialize};
+
+use crate::WorkloadConfig;
+use crate::control::Control;
+
+/// One step of a step schedule.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Step {
+    /// Step duration in seconds.
+    pub duration: f64,
+    pub scale: f64,
+}
+
+/// One point of a ramp schedule.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Point {
+    /// Seconds since the start of the run.
+    pub at: f64,
+    pub scale: f64,
+}
+
+/// How the arrival rate is scaled over time. The scale is applied on top of
+/// the rate in effect, including scenario phases.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum Schedule {
+    /// Piecewise constant scale. After the last step its scale stays in
+    /// effect, unless the steps are repeated.
+    Step {
+        steps: Vec<Step>,
+
+        #[serde(default)]
+        repeat: bool,
+    },
+
+    /// Sinusoidal scale `offset + amplitude * sin(2 * pi * t / period)`,
+    /// clamped at zero. Daily cycles have period of 86400 seconds.
+    Sine {
+        /// Period in seconds.
+        period: f64,
+
+        #[serde(default = "default_sine_offset")]
+        offset: f64,
+
+        amplitude: f64,
+    },
+
+    /// On/off bursts, switching between two states after exponentially
+    /// distributed time, i.e. a Markov-modulated Poisson process.
+    Bursts {
+        /// Scale during a burst.
+        on_scale: f64,
+
+        /// Scale between bursts, by default nothing happens.
+        #[serde(default)]
+        off_scale: f64,
+
+        /// Mean burst duration in seconds.
+        mean_on: f64,
+
+        /// Mean duration between bursts in seconds.
+        mean_off: f64,
+    },
+
+    /// Piecewise-linear scale between points, ordered by time. Before the
+    /// first point and after the last one the scale stays constant.
+    Ramp { points: Vec<Point> },
+}
+
+fn default_sine_offset() -> f64 {
+    1.0
+}
+
+impl Schedule {
+    /// Upper bound of the scale over time.
+    pub fn max_scale(&self) -> f64 {
+        let max =
+            |scales: &mut dyn Iterator<Item = f64>| scales.fold(0.0, f64::max);
+
+        match self {
+            Schedule::Step { steps, .. } => {
+                max(&mut steps.iter().map(|s| s.scale))
+            }
+            Schedule::Sine {
+                offset, amplitude, ..
+            } => (offset + amplitude.abs()).max(0.0),
+            Schedule::Bursts {
+                on_scale,
+                off_scale,
+                ..
+            } => on_scale.max(*off_scale),
+            Schedule::Ramp { points } => {
+                max(&mut points.iter().map(|p| p.scale))
+            }
+        }
+    }
+
+    /// Seconds since the start of the run, after which the scale stays zero
+    /// for good, if it ever does.
+    pub fn end(&self) -> Option<f64> {
+        match self {
+            Schedule::Step {
+                steps,
+                repeat: false,
+            } => {
+                let zeros =
+                    steps.iter().rev().take_while(|s| s.scale <= 0.0).count();
+                let active = &steps[..steps.len() - zeros];
+
+                (zeros > 0).then(|| active.iter().map(|s| s.duration).sum())
+            }
+            Schedule::Ramp { points } => {
+                let zeros =
+                    points.iter().rev().take_while(|p| p.scale <= 0.0).count();
+
+                (zeros > 0).then(|| points[points.len() - zeros].at)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// State of bursts schedule, whether a burst is in progress and when it's
+/// going to switch.
+#[derive(Debug, Clone, Copy)]
+struct Bursts {
+    on: bool,
+    until: f64,
+}
+
+/// Generator of arrival times for one worker.
+pub struct Arrivals<'a> {
+    config: &'a WorkloadConfig,
+
+    /// Arrival rate from the workload configuration.
+    base: f64,
+
+    bursts: Option<Bursts>,
+
+    /// Time of the last event in seconds since the start of the run.
+    time: f64,
+
+    /// How many times workers were resumed after a pause, as last seen.
+    resumes: u64,
+}
+
+impl<'a> Arrivals<'a> {
+    /// Arrivals with `base` rate per second of the workload.
+    pub fn new(config: &'a WorkloadConfig, base: f64) -> Self {
+        Arrivals {
+            config,
+            base,
+            bursts: None,
+            time: config.start.elapsed().as_secs_f64(),
+            resumes: config.runtime.map(Control::resumes).unwrap_or_default(),
+        }
+    }
+
+    /// Move on to the current time if the worker was paused since the last
+    /// event, so that the pause is not taken for falling behind. Returns
+    /// whether it was paused.
+    pub fn resume(&mut self) -> bool {
+        let Some(resumes) = self.config.runtime.map(Control::resumes) else {
+            return false;
+        };
+
+        if resumes == self.resumes {
+            return false;
+        }
+
+        self.resumes = resumes;
+        self.time = self.time.max(self.config.start.elapsed().as_secs_f64());
+        true
+    }
+
+    /// Interval in seconds between the last event and the next one. It's
+    /// infinite once the schedule has ended with a zero scale, since no
+    /// events are going to happen anymore.
+    pub fn next<R: Rng>(&mut self, rng: &mut R) -> f64 {
+        let last = self.time;
+
+        // The interval covers the pause, if any
+        self.resume();
+        let max = self.max_rate();
+        let end = self.config.schedule.as_ref().and_then(Schedule::end);
+
+        let candidates = Exp::new(max).unwrap();
+
+        loop {
+            self.time += rng.sample(candidates);
+
+            if end.is_some_and(|end| self.time >= end) {
+                return f64::INFINITY;
+            }
+
+            let rate = self.rate(self.time, rng);
+            if rng.r#gen::<f64>() * max < rate {
+                return self.time - last;
+            }
+        }
+    }
+
+    /// Skip all events until the specified time, returning the expected
+    /// number of skipped events. The next interval starts at that time.
+    pub fn skip<R: Rng>(&mut self, time: f64, rng: &mut R) -> u64 {
+        let skipped = (time - self.time).max(0.0) * self.rate(time, rng);
+        self.time = self.time.max(time);
+        skipped.round() as u64
+    }
+
+    /// Events per second at the specified time.
+    fn rate<R: Rng>(&mut self, time: f64, rng: &mut R) -> f64 {
+        self.config.arrival_rate_at(self.base, time) * self.scale(time, rng)
+    }
+
+    /// Upper bound of events per second over time.
+    fn max_rate(&self) -> f64 {
+        let scale = self
+            .config
+            .schedule
+            .as_ref()
+            .map(Schedule::max_scale)
+            .unwrap_or(1.0);
+
+        self.config.max_arrival_rate(self.base) * scale
+    }
+
+    /// Schedule scale at the specified time. The time is expected to never
+    /// decrease between calls, since bursts state only moves forward.
+    fn scale<R: Rng>(&mut self, time: f64, rng: &mut R) -> f64 {
+        let Some(schedule) = &self.config.schedule else {
+            return 1.0;
+        };
+
+        match schedule {
+            Schedule::Step { steps, repeat } => {
+                let total: f64 = steps.iter().map(|s| s.duration).sum();
+                let mut time = if *repeat { time % total } else { time };
+
+                for step in steps.iter() {
+                    if time < step.duration {
+                        return step.scale;
+                    }
+                    time -= step.duration;
+                }
+
+                steps.last().map(|s| s.scale).unwrap_or(1.0)
+            }
+            Schedule::Sine {
+                period,
+                offset,
+                amplitude,
+            } => {
+                (offset + amplitude * (2.0 * PI * time / period).sin()).max(0.0)
+            }
+            Schedule::Bursts {
+                on_scale,
+                off_scale,
+                mean_on,
+                mean_off,
+            } => {
+                let duration = |mean: f64, rng: &mut R| {
+                    rng.sample(Exp::new(1.0 / mean).unwrap())
+                };
+
+                let state = self.bursts.get_or_insert_with(|| Bursts {
+                    on: true,
+                    until: time + duration(*mean_on, rng),
+                });
+
+                while time >= state.until {
+                    state.on = !state.on;
+                    let mean = if state.on { *mean_on } else { *mean_off };
+                    state.until += duration(mean, rng);
+                }
+
+                if state.on { *on_scale } else { *off_scale }
+            }
+            Schedule::Ramp { points } => {
+                let next = points.iter().position(|p| p.at > time);
+
+                match next {
+                    Some(0) => points[0].scale,
+                    Some(i) => {
+                        let (a, b) = (&points[i - 1], &points[i]);
+                        let fraction = (time - a.at) / (b.at - a.at);
+                        a.scale + (b.scale - a.scale) * fraction
+                    }
+                    None => points.last().map(|p| p.scale).unwrap_or(1.0),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    fn config(schedule: &str) -> WorkloadConfig {
+        testing::config(&format!(
+            r#"
+            restart_interval = 10
+
+            [workload]
+            type = "processes"
+            arrival_rate = 100.0
+            departure_rate = 1.0
+            random_process = false
+
+            [schedule]
+            {schedule}
+            "#
+        ))
+    }
+
+    /// Number of events in every second of the first `seconds`.
+    fn histogram(config: &WorkloadConfig, seconds: usize) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut arrivals = Arrivals::new(config, 100.0);
+        arrivals.time = 0.0;
+
+        let mut counts = vec![0; seconds];
+        let mut time = 0.0;
+
+        loop {
+            time += arrivals.next(&mut rng);
+            if time >= seconds as f64 {
+                return counts;
+            }
+            counts[time as usize] += 1;
+        }
+    }
+
+    #[test]
+    fn test_step_schedule() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let steps = config(
+            r#"type = "step"
+            repeat = true
+            steps = [{ duration = 2, scale = 0.5 }, { duration = 1, scale = 3 }]"#,
+        );
+
+        let mut arrivals = Arrivals::new(&steps, 100.0);
+        assert_eq!(arrivals.scale(1.5, &mut rng), 0.5);
+        assert_eq!(arrivals.scale(2.5, &mut rng), 3.0);
+        assert_eq!(arrivals.scale(3.5, &mut rng), 0.5);
+        assert_eq!(arrivals.max_rate(), 300.0);
+    }
+
+    #[test]
+    fn test_ramp_schedule() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let ramp = config(
+            r#"type = "ramp"
+            points = [{ at = 10, scale = 1 }, { at = 20, scale = 3 }]"#,
+        );
+
+        let mut arrivals = Arrivals::new(&ramp, 100.0);
+        assert_eq!(arrivals.scale(0.0, &mut rng), 1.0);
+        assert_eq!(arrivals.scale(15.0, &mut rng), 2.0);
+        assert_eq!(arrivals.scale(30.0, &mut rng), 3.0);
+    }
+
+    #[test]
+    fn test_schedule_end() {
+        let ramp = config(
+            r#"type = "ramp"
+            points = [{ at = 0, scale = 1 }, { at = 1, scale = 0 }]"#,
+        );
+        assert_eq!(ramp.schedule.as_ref().and_then(Schedule::end), Some(1.0));
+
+        // Events stop coming once the scale drops to zero for good
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut arrivals = Arrivals::new(&ramp, 100.0);
+        arrivals.time = 0.0;
+
+        let mut events = 0;
+        while arrivals.next(&mut rng).is_finite() {
+            events += 1;
+        }
+        assert!((30..70).contains(&events), "events: {events}");
+        assert!(arrivals.next(&mut rng).is_infinite());
+
+        let steps = config(
+            r#"type = "step"
+            steps = [{ duration = 2, scale = 1 }, { duration = 1, scale = 0 }]"#,
+        );
+        assert_eq!(steps.schedule.as_ref().and_then(Schedule::end), Some(2.0));
+
+        // Unless the steps are repeated
+        let repeated = config(
+            r#"type = "step"
+            repeat = true
+            steps = [{ duration = 2, scale = 1 }, { duration = 1, scale = 0 }]"#,
+        );
+        assert_eq!(repeated.schedule.as_ref().and_then(Schedule::end), None);
+    }
+
+    #[test]
+    fn test_sine_schedule() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sine = config(
+            r#"type = "sine"
+            period = 4
+            amplitude = 2"#,
+        );
+
+        let mut arrivals = Arrivals::new(&sine, 100.0);
+        assert!((arrivals.scale(1.0, &mut rng) - 3.0).abs() < 1e-9);
+        assert_eq!(arrivals.scale(3.0, &mut rng), 0.0);
+        assert_eq!(arrivals.max_rate(), 300.0);
+    }
+
+    #[test]
+    fn test_step_thinning() {
+        // Every second step has ten times more events
+        let steps = config(
+            r#"type = "step"
+            repeat = true
+            steps = [{ duration = 1, scale = 0.1 }, { duration = 1, scale = 1 }]"#,
+        );
+
+        let counts = histogram(&steps, 200);
+        let low: usize = counts.iter().step_by(2).sum();
+        let high: usize = counts.iter().skip(1).step_by(2).sum();
+
+        // 100 seconds with 10 and 100 events per second expected
+        assert!((900..1100).contains(&low), "low rate: {low}");
+        assert!((9500..10500).contains(&high), "high rate: {high}");
+    }
+
+    #[test]
+    fn test_bursts_thinning() {
+        let bursts = config(
+            r#"type = "bursts"
+            on_scale = 1
+            mean_on = 1
+            mean_off = 1"#,
+        );
+
+        let counts = histogram(&bursts, 200);
+        let total: usize = counts.iter().sum();
+        assert!((7000..13000).contains(&total), "bursts: {total}");
+
+        // Nothing happens between bursts
+        assert!(counts.contains(&0));
+    }
+}
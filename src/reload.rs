@@ -0,0 +1,196 @@
+//! Configuration reload on SIGHUP, to retune soak runs lasting for days
+//! without losing the state accumulated by workers, like long-lived
+//! connections or loaded BPF programs. The configuration is compared group
+//! by group: the number of workers and the arrival rate are changed live,
+//! while any other change of a group means its workers have to be forked
+//! again. Settings of the run itself, like metrics or results, are fixed
+//! once it has started.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::sys::signal::{self, SigHandler, Signal};
+use serde_json::Value;
+
+use crate::{WorkloadConfig, WorkloadGroup};
+
+/// Top level settings applied only when a run starts.
+const FIXED: &[&str] = &["metrics", "results", "target", "control"];
+
+/// Settings of a group applied live, or not affecting its workers at all.
+const LIVE: &[&str] = &["workers", "duration", "restart"];
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Start handling SIGHUP as a request to reload the configuration.
+pub fn install() -> nix::Result<()> {
+    unsafe { signal::signal(Signal::SIGHUP, SigHandler::Handler(request)) }
+        .map(|_| ())
+}
+
+/// Whether reload was requested since the last check.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Top level settings that differ, but could not be applied to the run in
+/// progress.
+pub fn fixed_changes(
+    old: &WorkloadConfig,
+    new: &WorkloadConfig,
+) -> Vec<&'static str> {
+    let (old, new) = (fingerprint(old), fingerprint(new));
+
+    FIXED
+        .iter()
+        .copied()
+        .filter(|key| old.get(key) != new.get(key))
+        .collect()
+}
+
+/// Whether workers of a group have to be forked again to pick up the new
+/// configuration, both are the configuration for the group workers.
+pub fn needs_replace(old: &WorkloadConfig, new: &WorkloadConfig) -> bool {
+    let worker = |config| {
+        let mut value = fingerprint(config);
+
+        if let Some(object) = value.as_object_mut() {
+            for key in FIXED.iter().chain(LIVE) {
+                object.remove(*key);
+            }
+        }
+
+        if let Some(workload) =
+            value.get_mut("workload").and_then(Value::as_object_mut)
+        {
+            workload.remove("arrival_rate");
+        }
+
+        value
+    };
+
+    worker(old) != worker(new)
+}
+
+/// Whether workers of a group have to be started anew, because they are laid
+/// out on CPU cores or set up differently.
+pub fn needs_restart(old: &WorkloadGroup, new: &WorkloadGroup) -> bool {
+    old.per_core != new.per_core
+        || old.placement != new.placement
+        || old.scheduling != new.scheduling
+        || old.cgroup != new.cgroup
+        || old.namespaces != new.namespaces
+}
+
+fn fingerprint(config: &WorkloadConfig) -> Value {
+    serde_json::to_value(config).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Configuration for workers of the first group.
+    fn config(input: &str) -> WorkloadConfig {
+        let config = testing::config(input);
+        let group = &config.groups()[0];
+        config.for_group(group)
+    }
+
+    /// Configuration running before the reload.
+    fn running() -> WorkloadConfig {
+        config(
+            r#"
+            restart_interval = 10
+            workers = 2
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+            "#,
+        )
+    }
+
+    #[test]
+    fn test_live_changes() {
+        let live = config(
+            r#"
+            restart_interval = 10
+            workers = 4
+            duration = 60
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 500
+
+            [metrics]
+            address = "127.0.0.1:9100"
+            "#,
+        );
+
+        assert!(!needs_replace(&running(), &live));
+        assert_eq!(fixed_changes(&running(), &live), vec!["metrics"]);
+    }
+
+    #[test]
+    fn test_replaced_workload() {
+        let replaced = config(
+            r#"
+            restart_interval = 10
+            workers = 2
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+            syscall_nr = 1
+            "#,
+        );
+
+        let old = running();
+        assert!(needs_replace(&old, &replaced));
+        assert!(fixed_changes(&old, &replaced).is_empty());
+        assert!(!needs_restart(&old.groups()[0], &replaced.groups()[0]));
+    }
+
+    #[test]
+    fn test_restart_on_placement() {
+        let placed = config(
+            r#"
+            restart_interval = 10
+            workers = 2
+
+            [placement]
+            cpus = "0"
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+            "#,
+        );
+
+        assert!(needs_restart(&running().groups()[0], &placed.groups()[0]));
+    }
+
+    #[test]
+    fn test_restart_on_namespaces() {
+        let isolated = config(
+            r#"
+            restart_interval = 10
+            workers = 2
+
+            [namespaces]
+            unshare = ["pid"]
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+            "#,
+        );
+
+        assert!(needs_restart(&running().groups()[0], &isolated.groups()[0]));
+    }
+}
@@ -0,0 +1,295 @@
+//! Machine-readable results of a run: a JSON document written at the end,
+//! and an optional CSV time series of operations sampled every second while
+//! the run is in progress.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use nix::sys::utsname::uname;
+use serde::Serialize;
+
+use crate::WorkloadConfig;
+use crate::histogram::Percentiles;
+use crate::stats::{Registry, Source, StatsSnapshot, errno_name};
+use crate::usage::UsageSample;
+
+const CSV_HEADER: &str = "time,group,workload,ops,errors";
+
+/// Information about the machine the run was performed on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Host {
+    pub hostname: String,
+    pub kernel: String,
+    pub version: String,
+    pub machine: String,
+    pub cpus: usize,
+}
+
+impl Host {
+    pub fn current() -> Host {
+        let cpus = core_affinity::get_core_ids()
+            .map(|ids| ids.len())
+            .unwrap_or_default();
+
+        match uname() {
+            Ok(uts) => Host {
+                hostname: uts.nodename().to_string_lossy().into_owned(),
+                kernel: uts.release().to_string_lossy().into_owned(),
+                version: uts.version().to_string_lossy().into_owned(),
+                machine: uts.machine().to_string_lossy().into_owned(),
+                cpus,
+            },
+            Err(_) => Host {
+                hostname: String::new(),
+                kernel: String::new(),
+                version: String::new(),
+                machine: String::new(),
+                cpus,
+            },
+        }
+    }
+}
+
+/// Final counters of a single worker.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerTotals {
+    pub group: String,
+    pub workload: &'static str,
+    pub cpu: usize,
+    pub process: usize,
+
+    /// Whether the worker was retired by reloading the configuration.
+    pub retired: bool,
+    pub ops: u64,
+    pub errors: u64,
+    pub closed: u64,
+
+    /// Operations dropped, because the target rate was not sustained.
+    pub missed: u64,
+
+    /// Number of errors by errno name.
+    pub errnos: BTreeMap<&'static str, u64>,
+
+    /// Latency percentiles in nanoseconds, if latency is measured.
+    pub latency: Option<Percentiles>,
+}
+
+impl From<&Source> for WorkerTotals {
+    fn from(source: &Source) -> Self {
+        let snapshot = source.stats.snapshot();
+
+        WorkerTotals {
+            group: source.group.clone(),
+            workload: source.workload,
+            cpu: source.cpu,
+            process: source.process,
+            retired: source.retired,
+            ops: snapshot.ops,
+            errors: snapshot.errors,
+            closed: snapshot.closed,
+            missed: snapshot.missed,
+            errnos: snapshot
+                .errnos
+                .iter()
+                .map(|(errno, count)| (errno_name(*errno), *count))
+                .collect(),
+            latency: source.stats.latencies().percentiles(),
+        }
+    }
+}
+
+/// Results of the whole run. Timestamps are seconds since Unix epoch.
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub config: &'a WorkloadConfig,
+    pub started_at: f64,
+    pub finished_at: f64,
+    pub host: Host,
+    pub workers: Vec<WorkerTotals>,
+
+    /// Resource usage of the target process over time, if sampled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub target: Vec<UsageSample>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(
+        config: &'a WorkloadConfig,
+        sources: &[Source],
+        target: Vec<UsageSample>,
+        started: SystemTime,
+        finished: SystemTime,
+    ) -> Self {
+        Report {
+            config,
+            started_at: timestamp(started),
+            finished_at: timestamp(finished),
+            host: Host::current(),
+            workers: sources.iter().map(WorkerTotals::from).collect(),
+            target,
+        }
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+        file.flush()
+    }
+}
+
+fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Background thread writing the number of operations issued by every group
+/// during each second of the run.
+pub struct TimeSeries {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl TimeSeries {
+    pub fn start(path: &str, registry: Registry) -> io::Result<TimeSeries> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{CSV_HEADER}")?;
+        file.flush()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut previous = totals(&registry.sources());
+
+            for second in 1.. {
+                // Sleep until an absolute deadline to not accumulate drift
+                let deadline = start + Duration::from_secs(second);
+                while !stopped.load(Ordering::Relaxed) {
+                    match deadline.checked_duration_since(Instant::now()) {
+                        Some(left) => thread::park_timeout(left),
+                        None => break,
+                    }
+                }
+
+                // The last incomplete second is not reported
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let current = totals(&registry.sources());
+                for row in rows(second, &previous, &current) {
+                    writeln!(file, "{row}")?;
+                }
+                file.flush()?;
+
+                previous = current;
+            }
+
+            Ok(())
+        });
+
+        Ok(TimeSeries { stop, handle })
+    }
+
+    /// Stop sampling and wait until everything is written.
+    pub fn stop(self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.thread().unpark();
+        self.handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("sampler panicked")))
+    }
+}
+
+/// Current counters aggregated by group and workload type.
+fn totals(
+    sources: &[Source],
+) -> BTreeMap<(String, &'static str), StatsSnapshot> {
+    let mut totals: BTreeMap<_, StatsSnapshot> = BTreeMap::new();
+
+    for source in sources {
+        totals
+            .entry((source.group.clone(), source.workload))
+            .or_default()
+            .merge(&source.stats.snapshot());
+    }
+
+    totals
+}
+
+/// CSV rows with the difference between two samples.
+fn rows(
+    time: u64,
+    previous: &BTreeMap<(String, &'static str), StatsSnapshot>,
+    current: &BTreeMap<(String, &'static str), StatsSnapshot>,
+) -> Vec<String> {
+    current
+        .iter()
+        .map(|((group, workload), now)| {
+            let (ops, errors) = previous
+                .get(&(group.clone(), *workload))
+                .map(|before| (before.ops, before.errors))
+                .unwrap_or_default();
+
+            format!(
+                "{time},{group},{workload},{},{}",
+                now.ops - ops,
+                now.errors - errors
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::WorkerStats;
+
+    #[test]
+    fn test_rows() {
+        let stats = WorkerStats::shared();
+        let sources = vec![
+            Source {
+                group: String::from("fast"),
+                workload: "syscalls",
+                cpu: 0,
+                process: 0,
+                stats,
+                retired: false,
+            },
+            Source {
+                group: String::from("fast"),
+                workload: "syscalls",
+                cpu: 1,
+                process: 0,
+                stats: WorkerStats::shared(),
+                retired: true,
+            },
+        ];
+
+        stats.success();
+        let previous = totals(&sources);
+
+        stats.success();
+        stats.failure(2);
+        sources[1].stats.success();
+        let current = totals(&sources);
+
+        assert_eq!(
+            rows(3, &previous, &current),
+            vec![String::from("3,fast,syscalls,3,1")]
+        );
+
+        let totals = WorkerTotals::from(&sources[0]);
+        assert_eq!(totals.ops, 3);
+        assert_eq!(totals.errnos.get("ENOENT"), Some(&1));
+    }
+}
@@ -0,0 +1,186 @@
+//! Scheduling of worker processes, to model latency-sensitive and batch
+//! workloads next to each other. Settings are applied by every worker to
+//! itself right after it's forked and pinned, before running the payload,
+//! so that threads and processes started by the payload inherit them.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/// `ioprio_set` target is a thread, the calling one for id zero.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+/// I/O scheduling class is stored in the top bits of the priority value.
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// CPU scheduling policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    /// The default time-sharing policy.
+    #[default]
+    Other,
+
+    /// Time-sharing for CPU-intensive processes, slightly disfavored when
+    /// it comes to wakeups.
+    Batch,
+
+    /// Runs only when nothing else wants the CPU.
+    Idle,
+
+    /// Real-time first-in first-out policy, requires a `priority`.
+    Fifo,
+}
+
+impl Policy {
+    fn raw(&self) -> libc::c_int {
+        match self {
+            Policy::Other => libc::SCHED_OTHER,
+            Policy::Batch => libc::SCHED_BATCH,
+            Policy::Idle => libc::SCHED_IDLE,
+            Policy::Fifo => libc::SCHED_FIFO,
+        }
+    }
+
+    /// Whether the policy takes a static priority.
+    pub fn realtime(&self) -> bool {
+        *self == Policy::Fifo
+    }
+}
+
+/// I/O scheduling class.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+
+    /// Gets disk time only when nobody else needs it, takes no priority.
+    Idle,
+}
+
+impl IoClass {
+    fn raw(&self) -> libc::c_int {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+}
+
+/// How workers of a group are scheduled. Nothing is changed for settings
+/// that are not specified.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Scheduling {
+    /// Nice level from -20 to 19.
+    #[serde(default)]
+    pub nice: Option<i32>,
+
+    #[serde(default)]
+    pub policy: Option<Policy>,
+
+    /// Static priority of a real-time policy, from 1 to 99.
+    #[serde(default)]
+    pub priority: Option<i32>,
+
+    #[serde(default)]
+    pub io_class: Option<IoClass>,
+
+    /// Priority within the I/O class from 0 (highest) to 7.
+    #[serde(default)]
+    pub io_priority: Option<i32>,
+}
+
+impl Scheduling {
+    /// Apply the settings to the current process. Raising priorities usually
+    /// needs CAP_SYS_NICE.
+    pub fn apply(&self) -> Result<(), String> {
+        if let Some(policy) = self.policy {
+            let param = libc::sched_param {
+                sched_priority: self.priority.unwrap_or_default(),
+            };
+
+            if unsafe { libc::sched_setscheduler(0, policy.raw(), &param) } != 0
+            {
+                return Err(format!(
+                    "Cannot set scheduling policy {policy:?}: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+
+        if let Some(nice) = self.nice
+            && unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0
+        {
+            return Err(format!(
+                "Cannot set nice level {nice}: {}",
+                io::Error::last_os_error()
+            ));
+        }
+
+        if let Some(ioprio) = self.ioprio() {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    ioprio,
+                )
+            };
+
+            if ret != 0 {
+                return Err(format!(
+                    "Cannot set I/O priority: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// I/O priority value for `ioprio_set`, if the class is specified. The
+    /// priority within the class is 4 by default, as for nice level 0.
+    fn ioprio(&self) -> Option<libc::c_int> {
+        let class = self.io_class?;
+        let data = match class {
+            IoClass::Idle => 0,
+            _ => self.io_priority.unwrap_or(4),
+        };
+
+        Some(class.raw() << IOPRIO_CLASS_SHIFT | data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduling() {
+        let scheduling: Scheduling = toml::from_str(
+            r#"
+            nice = 5
+            policy = "batch"
+            io_class = "best-effort"
+            io_priority = 7
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(scheduling.policy, Some(Policy::Batch));
+        assert_eq!(scheduling.ioprio(), Some(2 << 13 | 7));
+
+        let idle = Scheduling {
+            io_class: Some(IoClass::Idle),
+            io_priority: Some(3),
+            ..Scheduling::default()
+        };
+        assert_eq!(idle.ioprio(), Some(3 << 13));
+        assert_eq!(Scheduling::default().ioprio(), None);
+
+        // Lowering priorities of the current thread is always allowed
+        assert!(scheduling.apply().is_ok());
+    }
+}
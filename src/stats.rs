@@ -0,0 +1,487 @@
+//! Workers statistics. Every worker gets its own set of counters in a memory
+//! region shared with the parent process, which allows the parent to
+//! aggregate statistics from all the forked workers without any extra
+//! communication.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use nix::sys::mman::{MapFlags, ProtFlags, mmap};
+use syscalls::Errno;
+
+use crate::Workload;
+use crate::histogram::{Histogram, HistogramSnapshot, format_nanos};
+use crate::usage::UsageSummary;
+
+/// Largest errno value counted separately, anything above is counted
+/// together with unknown errors under zero.
+const MAX_ERRNO: usize = 133;
+
+/// Counters of a single worker.
+#[derive(Debug)]
+#[repr(C)]
+pub struct WorkerStats {
+    /// Operations issued by the worker, including failed ones.
+    ops: AtomicU64,
+
+    /// Failed operations.
+    errors: AtomicU64,
+
+    /// Resources released after a successful operation: exited processes,
+    /// closed connections or endpoints that stopped listening.
+    closed: AtomicU64,
+
+    /// Operations dropped, because the worker could not keep up with the
+    /// target rate.
+    missed: AtomicU64,
+
+    /// Failed operations by errno.
+    errnos: [AtomicU64; MAX_ERRNO + 1],
+
+    /// Latency of operations measured from their intended start.
+    latency: Histogram,
+}
+
+impl WorkerStats {
+    /// Allocate zeroed counters in a memory region, that will be shared with
+    /// forked processes. The region lives until the end of the program.
+    pub fn shared() -> &'static WorkerStats {
+        // Zeros are a valid value for atomic counters
+        unsafe { shared_zeroed() }
+    }
+
+    /// Count a successful operation.
+    pub fn success(&self) {
+        self.ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a failed operation with its errno.
+    pub fn failure(&self, errno: i32) {
+        let index = usize::try_from(errno)
+            .ok()
+            .filter(|errno| *errno <= MAX_ERRNO)
+            .unwrap_or(0);
+
+        self.ops.fetch_add(1, Ordering::Relaxed);
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.errnos[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a resource released after a successful operation.
+    pub fn close(&self) {
+        self.closed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count operations dropped to catch up with the target rate.
+    pub fn miss(&self, count: u64) {
+        self.missed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record latency of an operation.
+    pub fn latency(&self, latency: Duration) {
+        self.latency.record(latency);
+    }
+
+    /// Current latency histogram, which is kept separately from the
+    /// counters snapshot since it's much larger.
+    pub fn latencies(&self) -> HistogramSnapshot {
+        self.latency.snapshot()
+    }
+
+    /// Count an operation depending on its result.
+    pub fn record<T>(&self, result: &Result<T, Errno>) {
+        match result {
+            Ok(_) => self.success(),
+            Err(e) => self.failure(e.into_raw()),
+        }
+    }
+
+    /// Current values of the counters.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let errnos = self
+            .errnos
+            .iter()
+            .enumerate()
+            .map(|(errno, count)| (errno as i32, count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        StatsSnapshot {
+            ops: self.ops.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            closed: self.closed.load(Ordering::Relaxed),
+            missed: self.missed.load(Ordering::Relaxed),
+            errnos,
+        }
+    }
+}
+
+/// Allocate a zeroed value in an anonymous memory region, that will be
+/// shared with forked processes. The region lives until the end of the
+/// program.
+///
+/// # Safety
+///
+/// All zeros has to be a valid value of `T`.
+pub(crate) unsafe fn shared_zeroed<T>() -> &'static T {
+    let length = NonZeroUsize::new(mem::size_of::<T>()).unwrap();
+
+    // An anonymous mapping is filled with zeros
+    let region = unsafe {
+        mmap(
+            None,
+            length,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_SHARED | MapFlags::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    }
+    .expect("cannot allocate shared memory");
+
+    unsafe { &*(region as *const T) }
+}
+
+/// Counters of a single worker together with its identity.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub group: String,
+
+    /// Workload type, e.g. `syscalls`.
+    pub workload: &'static str,
+    pub cpu: usize,
+    pub process: usize,
+    pub stats: &'static WorkerStats,
+
+    /// Whether the worker was retired by reloading the configuration. Its
+    /// counters are still part of the totals.
+    pub retired: bool,
+}
+
+/// Sources of all the workers started during the run. Workers could be added
+/// while the run is in progress, so whoever reports on them has to take a
+/// fresh list every time.
+#[derive(Debug, Clone, Default)]
+pub struct Registry(Arc<RwLock<Vec<Source>>>);
+
+impl Registry {
+    pub fn add(&self, source: Source) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(source);
+    }
+
+    /// Mark workers of a group starting from the specified process number
+    /// as retired, the same way as the supervisor terminates them.
+    pub fn retire(&self, group: &str, from: usize) {
+        let mut sources =
+            self.0.write().unwrap_or_else(PoisonError::into_inner);
+
+        for source in sources
+            .iter_mut()
+            .filter(|s| s.group == group && s.process >= from)
+        {
+            source.retired = true;
+        }
+    }
+
+    pub fn sources(&self) -> Vec<Source> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Plain copy of worker counters, which could be merged together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsSnapshot {
+    pub ops: u64,
+    pub errors: u64,
+    pub closed: u64,
+    pub missed: u64,
+
+    /// Number of errors by errno, zero stands for unknown errors.
+    pub errnos: BTreeMap<i32, u64>,
+}
+
+impl StatsSnapshot {
+    pub fn merge(&mut self, other: &StatsSnapshot) {
+        self.ops += other.ops;
+        self.errors += other.errors;
+        self.closed += other.closed;
+        self.missed += other.missed;
+
+        for (errno, count) in other.errnos.iter() {
+            *self.errnos.entry(*errno).or_default() += count;
+        }
+    }
+
+    /// Successful operations.
+    pub fn succeeded(&self) -> u64 {
+        self.ops - self.errors
+    }
+
+    /// Resources currently held, e.g. active connections.
+    pub fn active(&self) -> u64 {
+        self.succeeded().saturating_sub(self.closed)
+    }
+
+    /// Errors breakdown in human readable form, e.g. "ENOENT 5, EPERM 1".
+    pub fn errors_breakdown(&self) -> String {
+        self.errnos
+            .iter()
+            .map(|(errno, count)| format!("{} {count}", errno_name(*errno)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Symbolic name of an errno, e.g. "ENOENT".
+pub fn errno_name(errno: i32) -> &'static str {
+    Errno::new(errno)
+        .name()
+        .filter(|_| errno != 0)
+        .unwrap_or("unknown")
+}
+
+/// Arrival rate of a single worker from the workload configuration, if the
+/// workload has a target rate at all. Scenario phases, schedules and rates set
+/// at runtime are applied on top of it.
+pub fn target_rate(workload: &Workload) -> Option<f64> {
+    match workload {
+        Workload::Processes { arrival_rate, .. }
+        | Workload::IOUring { arrival_rate, .. } => Some(*arrival_rate),
+        Workload::Syscalls {
+            arrival_rate,
+            tight_loop,
+            ..
+        } => (!tight_loop).then_some(*arrival_rate),
+        Workload::Network {
+            server,
+            arrival_rate,
+            ..
+        } => (!server).then_some(*arrival_rate),
+        Workload::Endpoints { .. } | Workload::Bpf { .. } => None,
+    }
+}
+
+/// Statistics of one workload group.
+#[derive(Debug, Clone)]
+pub struct GroupSummary {
+    pub name: String,
+    pub kind: &'static str,
+    pub workers: usize,
+
+    /// Base arrival rate for all the group workers together, as configured.
+    /// The rate in effect could differ over time, by scenario phases,
+    /// schedules or changes at runtime.
+    pub base_rate: Option<f64>,
+    pub stats: StatsSnapshot,
+    pub latency: HistogramSnapshot,
+}
+
+/// Final statistics of the run, aggregated over all workers.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    /// How long the run took in seconds.
+    pub elapsed: f64,
+
+    /// Seed used for random decisions, to reproduce the run.
+    pub seed: Option<u64>,
+    pub groups: Vec<GroupSummary>,
+
+    /// Resource usage of the target process, if sampled.
+    pub target: Option<UsageSummary>,
+}
+
+impl Summary {
+    /// Add statistics of a worker to its group summary. Retired workers
+    /// count only towards the operations.
+    pub fn add(&mut self, source: &Source, workload: &Workload) {
+        let snapshot = source.stats.snapshot();
+        let latency = source.stats.latencies();
+
+        let workers = usize::from(!source.retired);
+        let rate = target_rate(workload)
+            .map(|rate| if source.retired { 0.0 } else { rate });
+
+        match self.groups.iter_mut().find(|g| g.name == source.group) {
+            Some(summary) => {
+                summary.workers += workers;
+                summary.base_rate = summary
+                    .base_rate
+                    .zip(rate)
+                    .map(|(total, rate)| total + rate);
+                summary.stats.merge(&snapshot);
+                summary.latency.merge(&latency);
+            }
+            None => self.groups.push(GroupSummary {
+                name: source.group.clone(),
+                kind: workload.kind(),
+                workers,
+                base_rate: rate,
+                stats: snapshot,
+                latency,
+            }),
+        }
+    }
+
+    /// Achieved rate of operations per second.
+    pub fn rate(&self, stats: &StatsSnapshot) -> f64 {
+        if self.elapsed > 0.0 {
+            stats.ops as f64 / self.elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Run time: {:.1}s", self.elapsed)?;
+        if let Some(seed) = self.seed {
+            writeln!(f, "Seed: {seed}")?;
+        }
+        writeln!(
+            f,
+            "{:<16} {:<10} {:>7} {:>12} {:>12} {:>12} {:>10}",
+            "Group", "Type", "Workers", "Base/s", "Achieved/s", "Ops", "Errors"
+        )?;
+
+        for group in self.groups.iter() {
+            let base = group
+                .base_rate
+                .map(|rate| format!("{rate:.2}"))
+                .unwrap_or(String::from("-"));
+
+            writeln!(
+                f,
+                "{:<16} {:<10} {:>7} {:>12} {:>12.2} {:>12} {:>10}",
+                group.name,
+                group.kind,
+                group.workers,
+                base,
+                self.rate(&group.stats),
+                group.stats.ops,
+                group.stats.errors
+            )?;
+
+            if group.stats.errors > 0 {
+                writeln!(f, "  errors: {}", group.stats.errors_breakdown())?;
+            }
+
+            if let Some(p) = group.latency.percentiles() {
+                writeln!(
+                    f,
+                    "  latency: p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+                    format_nanos(p.p50),
+                    format_nanos(p.p90),
+                    format_nanos(p.p99),
+                    format_nanos(p.p999),
+                    format_nanos(p.max)
+                )?;
+            }
+
+            if group.stats.missed > 0 {
+                writeln!(
+                    f,
+                    "  missed: {}, target rate is not sustained",
+                    group.stats.missed
+                )?;
+            }
+        }
+
+        if let Some(target) = &self.target {
+            write!(
+                f,
+                "Target pid {}: cpu mean {:.1}%, max {:.1}%, rss max {:.1} MiB",
+                target.pid,
+                target.cpu_mean,
+                target.cpu_max,
+                target.rss_max as f64 / (1024.0 * 1024.0)
+            )?;
+            if let Some(fds) = target.fds_max {
+                write!(f, ", fds max {fds}")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_stats() {
+        let stats = WorkerStats::shared();
+
+        stats.success();
+        stats.record::<usize>(&Ok(0));
+        stats.record::<usize>(&Err(Errno::ENOENT));
+        stats.failure(Errno::ENOENT.into_raw());
+        stats.failure(-1);
+        stats.miss(3);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.ops, 5);
+        assert_eq!(snapshot.errors, 3);
+        assert_eq!(snapshot.errors_breakdown(), "unknown 1, ENOENT 2");
+
+        let mut total = StatsSnapshot::default();
+        total.merge(&snapshot);
+        total.merge(&snapshot);
+        assert_eq!(total.ops, 10);
+        assert_eq!(total.missed, 6);
+        assert_eq!(total.errnos.get(&Errno::ENOENT.into_raw()), Some(&4));
+    }
+
+    #[test]
+    fn test_retired_summary() {
+        let workload = Workload::Syscalls {
+            arrival_rate: 100.0,
+            tight_loop: false,
+            pacing: Default::default(),
+            syscall_nr: 39,
+            syscall_args: Default::default(),
+        };
+
+        let registry = Registry::default();
+        for process in 0..3 {
+            let stats = WorkerStats::shared();
+            stats.success();
+
+            registry.add(Source {
+                group: String::from("syscalls"),
+                workload: "syscalls",
+                cpu: 0,
+                process,
+                stats,
+                retired: false,
+            });
+        }
+
+        // One worker was retired, its operations still count
+        registry.retire("syscalls", 2);
+        registry.retire("other", 0);
+
+        let mut summary = Summary::default();
+        for source in registry.sources().iter() {
+            summary.add(source, &workload);
+        }
+
+        let group = &summary.groups[0];
+        assert_eq!(group.workers, 2);
+        assert_eq!(group.base_rate, Some(200.0));
+        assert_eq!(group.stats.ops, 3);
+    }
+}
@@ -0,0 +1,755 @@
+//! Worker processes management. Every worker is forked with a pipe, which
+//! it uses to report an error or a panic message before exiting, so that the
+//! parent can tell which worker has failed and why. Dead workers could be
+//! respawned according to the restart policy, keeping the same CPU core and
+//! process number. Commands of the control socket, if any, are handled in
+//! between.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{iter, panic, process, thread};
+
+use core_affinity::CoreId;
+use fork::{Fork, fork};
+use log::{error, info, warn};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::signal::{Signal, kill};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::{Pid, pipe2};
+
+use crate::cgroup;
+use crate::control::{Controller, Reply, Request};
+use crate::events::{self, EventSink};
+use crate::namespaces::{self, Namespaces};
+use crate::scheduling::Scheduling;
+use crate::{RestartMode, RestartPolicy, Worker};
+
+/// Exit code of a worker process, which payload returned an error.
+const EXIT_WORKER_ERROR: i32 = 1;
+
+/// How often to check workers status.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A forked worker process.
+pub struct WorkerHandle {
+    pub pid: Pid,
+
+    /// Name of the workload group the worker belongs to.
+    pub group: String,
+    pub cpu: CoreId,
+    pub process: usize,
+
+    /// How the worker process is set up before running the payload.
+    setup: WorkerSetup,
+
+    /// How many times the worker was restarted.
+    pub restarts: u32,
+
+    /// The worker itself, kept to be able to respawn it.
+    worker: Box<dyn Worker>,
+
+    /// Where the worker logs generated events, if enabled.
+    events: Option<EventSink>,
+
+    /// Read end of the pipe the worker reports errors to.
+    errors: File,
+
+    /// Why the worker was asked to terminate while the run goes on.
+    retirement: Option<Retirement>,
+}
+
+/// How a worker process is set up right after it's forked, before running
+/// the payload.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerSetup {
+    /// Whether the worker is pinned to its CPU core.
+    pub pin: bool,
+
+    /// How the worker is scheduled, if different from the parent.
+    pub scheduling: Option<Scheduling>,
+
+    /// Cgroup the worker moves itself into, if any.
+    pub cgroup: Option<PathBuf>,
+
+    /// Namespaces the worker is started in, if any.
+    pub namespaces: Option<Namespaces>,
+}
+
+impl WorkerSetup {
+    fn apply(&self, cpu: CoreId) -> Result<(), String> {
+        if let Some(cgroup) = &self.cgroup {
+            cgroup::join(cgroup)
+                .map_err(|e| format!("Cannot join cgroup {cgroup:?}: {e}"))?;
+        }
+
+        if self.pin {
+            core_affinity::set_for_current(cpu);
+        }
+
+        if let Some(scheduling) = &self.scheduling {
+            scheduling.apply()?;
+        }
+
+        // The last one, as the root directory might change
+        if let Some(namespaces) = &self.namespaces {
+            namespaces.enter()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a worker is terminated before the end of the run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Retirement {
+    /// The worker is not needed anymore.
+    Removed,
+
+    /// The worker has to be forked again with a new payload.
+    Replaced,
+}
+
+/// How a worker process has finished.
+#[derive(Debug, PartialEq)]
+pub enum WorkerExit {
+    /// Payload has finished without errors.
+    Success,
+
+    /// Worker was terminated on purpose, e.g. when the run is over.
+    Stopped,
+
+    /// Worker has failed, with a reason why.
+    Failed(String),
+}
+
+impl Display for WorkerHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "worker {} of group {} on CPU {} (pid {})",
+            self.process, self.group, self.cpu.id, self.pid
+        )
+    }
+}
+
+impl WorkerHandle {
+    /// Fork a new worker process, which runs the payload in a loop until it
+    /// fails. The process is set up according to `setup` first, e.g. pinned
+    /// to the specified CPU core.
+    pub fn spawn(
+        worker: Box<dyn Worker>,
+        group: &str,
+        cpu: CoreId,
+        process: usize,
+        setup: WorkerSetup,
+        events: Option<EventSink>,
+    ) -> Result<Self, String> {
+        let (pid, errors) =
+            fork_worker(&*worker, cpu, &setup, events.as_ref())?;
+
+        Ok(WorkerHandle {
+            pid,
+            group: group.to_string(),
+            cpu,
+            process,
+            setup,
+            restarts: 0,
+            worker,
+            events,
+            errors,
+            retirement: None,
+        })
+    }
+
+    /// Fork the same worker once again, after the previous process is gone.
+    pub fn respawn(&mut self) -> Result<(), String> {
+        self.fork()?;
+        self.restarts += 1;
+        Ok(())
+    }
+
+    fn fork(&mut self) -> Result<(), String> {
+        let (pid, errors) = fork_worker(
+            &*self.worker,
+            self.cpu,
+            &self.setup,
+            self.events.as_ref(),
+        )?;
+
+        self.pid = pid;
+        self.errors = errors;
+        Ok(())
+    }
+
+    /// Interpret the wait status of the worker process, taking into account
+    /// whether it was asked to stop. The error reported by the worker, if
+    /// any, is used as a reason of the failure.
+    pub fn exit(&mut self, status: WaitStatus, stopping: bool) -> WorkerExit {
+        // The pipe is non blocking, read whatever was reported
+        let mut buffer = vec![];
+        let _ = self.errors.read_to_end(&mut buffer);
+        let message = String::from_utf8_lossy(&buffer);
+
+        let reason = |default: String| {
+            if message.is_empty() {
+                default
+            } else {
+                message.to_string()
+            }
+        };
+
+        match status {
+            WaitStatus::Exited(_, 0) => WorkerExit::Success,
+            WaitStatus::Exited(_, namespaces::EXIT_TERMINATED) if stopping => {
+                WorkerExit::Stopped
+            }
+            WaitStatus::Exited(_, code) => {
+                WorkerExit::Failed(reason(format!("exited with code {code}")))
+            }
+            WaitStatus::Signaled(_, Signal::SIGTERM | Signal::SIGKILL, _)
+                if stopping =>
+            {
+                WorkerExit::Stopped
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                WorkerExit::Failed(reason(format!("killed by {signal}")))
+            }
+            other => WorkerExit::Failed(format!("unexpected status {other:?}")),
+        }
+    }
+}
+
+fn fork_worker(
+    worker: &dyn Worker,
+    cpu: CoreId,
+    setup: &WorkerSetup,
+    events: Option<&EventSink>,
+) -> Result<(Pid, File), String> {
+    // Do not leak the pipe into executed processes, and never block reading
+    // from it: the worker writes everything it has before exiting.
+    let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)
+        .map_err(|e| format!("Cannot create a pipe: {e}"))?;
+    let (errors, report) =
+        unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+
+    if let Some(namespaces) = &setup.namespaces {
+        // Only the parent reads errors, but keeps the pipe end
+        let errors_fd = errors.as_raw_fd();
+        let child = namespaces.spawn(|| {
+            let _ = nix::unistd::close(errors_fd);
+            run(worker, report, cpu, setup, events)
+        })?;
+
+        info!("Child {}", child);
+        return Ok((child, errors));
+    }
+
+    match fork() {
+        Ok(Fork::Parent(child)) => {
+            info!("Child {}", child);
+            drop(report);
+            Ok((Pid::from_raw(child), errors))
+        }
+        Ok(Fork::Child) => {
+            drop(errors);
+            run(worker, report, cpu, setup, events)
+        }
+        Err(e) => Err(format!("Cannot fork a worker: {e}")),
+    }
+}
+
+/// Worker process main loop.
+fn run(
+    worker: &dyn Worker,
+    mut report: File,
+    cpu: CoreId,
+    setup: &WorkerSetup,
+    events: Option<&EventSink>,
+) -> ! {
+    if let Ok(hook_report) = report.try_clone() {
+        let default_hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |panic_info| {
+            let _ = write!(&hook_report, "{panic_info}");
+            default_hook(panic_info);
+        }));
+    }
+
+    // Open the log while the path is still there, the worker might change
+    // its root directory
+    if let Some(sink) = events
+        && let Err(e) = events::open(sink, namespaces::host_pid())
+    {
+        let _ = write!(report, "Cannot log events to {}: {e}", sink.path);
+        process::exit(EXIT_WORKER_ERROR);
+    }
+
+    if let Err(e) = setup.apply(cpu) {
+        let _ = write!(report, "{e}");
+        process::exit(EXIT_WORKER_ERROR);
+    }
+
+    loop {
+        if let Err(e) = worker.run_payload() {
+            events::flush();
+            let _ = write!(report, "{e}");
+            process::exit(EXIT_WORKER_ERROR);
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Whether a worker has to be restarted after it has finished.
+    fn applies(&self, handle: &WorkerHandle, exit: &WorkerExit) -> bool {
+        let restart = match exit {
+            WorkerExit::Stopped => false,
+            WorkerExit::Success => self.policy == RestartMode::Always,
+            WorkerExit::Failed(_) => self.policy != RestartMode::Never,
+        };
+
+        restart
+            && (self.max_restarts == 0 || handle.restarts < self.max_restarts)
+    }
+
+    /// How long to wait before the next restart. The delay is doubled with
+    /// every restart of the same worker, up to `max_backoff`.
+    fn delay(&self, restarts: u32) -> Duration {
+        let backoff = self
+            .backoff
+            .saturating_mul(1u64.checked_shl(restarts).unwrap_or(u64::MAX))
+            .min(self.max_backoff);
+
+        Duration::from_millis(backoff)
+    }
+}
+
+/// Ask a worker to terminate. A stopped worker handles the signal only when
+/// continued.
+fn terminate(pid: Pid, paused: bool) {
+    info!("Terminating: {}", pid);
+    let _ = kill(pid, Signal::SIGTERM);
+
+    if paused {
+        let _ = kill(pid, Signal::SIGCONT);
+    }
+}
+
+/// Keeps track of all the worker processes, stops them when the run is over
+/// and restarts them if needed.
+pub struct Supervisor {
+    policy: RestartPolicy,
+
+    /// Running workers.
+    workers: HashMap<Pid, WorkerHandle>,
+
+    /// Dead workers waiting to be restarted, with the restart time.
+    pending: Vec<(Instant, WorkerHandle)>,
+
+    /// Whether the workers were asked to stop.
+    stopping: bool,
+
+    /// Whether the workers are stopped with SIGSTOP.
+    paused: bool,
+
+    /// Number of worker failures so far.
+    failures: usize,
+
+    /// When supervising has started, the duration is counted from it.
+    started: Option<Instant>,
+
+    /// Commands from the control socket.
+    controller: Option<Controller>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy) -> Self {
+        Supervisor {
+            policy,
+            workers: HashMap::new(),
+            pending: vec![],
+            stopping: false,
+            paused: false,
+            failures: 0,
+            started: None,
+            controller: None,
+        }
+    }
+
+    /// Apply a new restart policy, e.g. after the configuration was
+    /// reloaded.
+    pub fn set_policy(&mut self, policy: RestartPolicy) {
+        self.policy = policy;
+    }
+
+    /// Handle commands of the control socket while supervising workers.
+    pub fn control(&mut self, controller: Controller) {
+        self.controller = Some(controller);
+    }
+
+    pub fn controller_mut(&mut self) -> Option<&mut Controller> {
+        self.controller.as_mut()
+    }
+
+    pub fn add(&mut self, handle: WorkerHandle) {
+        self.workers.insert(handle.pid, handle);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty() && self.pending.is_empty()
+    }
+
+    /// Supervise workers until all of them are finished, or until the
+    /// duration in seconds is over, if it's not zero. Returns how many
+    /// times workers have failed.
+    pub fn run(&mut self, duration: u64) -> usize {
+        self.run_until(duration, || false);
+        self.failures
+    }
+
+    /// Supervise workers the same way as `run`, but return `true` as soon
+    /// as `interrupted` does, so that workers could be changed before
+    /// supervising them further. The duration is counted from the first
+    /// call. Returns `false` once all workers are finished.
+    pub fn run_until(
+        &mut self,
+        duration: u64,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> bool {
+        let start = *self.started.get_or_insert_with(Instant::now);
+
+        while !self.is_empty() {
+            self.handle_requests();
+
+            if interrupted() {
+                return true;
+            }
+
+            if duration != 0
+                && !self.stopping
+                && start.elapsed().as_secs() >= duration
+            {
+                self.stop();
+            }
+
+            match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => {
+                    self.restart_pending();
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Ok(status) => self.finished(status),
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    error!("waitpid: {e}");
+                    break;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// How many times workers have failed so far.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Ask all workers to terminate, and forget about pending restarts.
+    pub fn stop(&mut self) {
+        self.stopping = true;
+        self.pending.clear();
+
+        for pid in self.workers.keys() {
+            terminate(*pid, self.paused);
+        }
+
+        self.paused = false;
+    }
+
+    /// Terminate workers of a group starting from the specified process
+    /// number, e.g. when the number of workers was reduced. They are not
+    /// restarted.
+    pub fn retire(&mut self, group: &str, from: usize) {
+        let retired = |handle: &WorkerHandle| {
+            handle.group == group && handle.process >= from
+        };
+
+        self.pending.retain(|(_, handle)| !retired(handle));
+
+        for handle in self.workers.values_mut().filter(|h| retired(h)) {
+            if handle.retirement.replace(Retirement::Removed).is_none() {
+                terminate(handle.pid, self.paused);
+            }
+        }
+    }
+
+    /// Terminate all workers of a group and fork them again with a new
+    /// payload made by `replace`. They keep their CPU core, process number
+    /// and statistics.
+    pub fn replace(
+        &mut self,
+        group: &str,
+        mut replace: impl FnMut(&WorkerHandle) -> Box<dyn Worker>,
+    ) {
+        // Those waiting for a restart get the new payload right away
+        let pending = self.pending.iter_mut().map(|(_, handle)| handle);
+
+        for handle in pending.filter(|h| h.group == group) {
+            handle.worker = replace(handle);
+        }
+
+        // Removed workers are gone for good
+        let replaced = self.workers.values_mut().filter(|h| {
+            h.group == group && h.retirement != Some(Retirement::Removed)
+        });
+
+        for handle in replaced {
+            handle.worker = replace(handle);
+
+            if handle.retirement.replace(Retirement::Replaced).is_none() {
+                terminate(handle.pid, self.paused);
+            }
+        }
+    }
+
+    /// Stop all workers with SIGSTOP, workers restarted in the meantime are
+    /// stopped as well.
+    pub fn pause(&mut self) -> Result<(), String> {
+        if self.stopping {
+            return Err(String::from("the run is stopping"));
+        }
+
+        self.paused = true;
+        self.signal_all(Signal::SIGSTOP);
+        info!("Paused {} workers", self.workers.len());
+        Ok(())
+    }
+
+    /// Continue stopped workers.
+    pub fn resume(&mut self) -> Result<(), String> {
+        if !self.paused {
+            return Err(String::from("not paused"));
+        }
+
+        // Workers have to know about the pause before they continue
+        if let Some(controller) = &self.controller {
+            controller.resumed();
+        }
+
+        self.paused = false;
+        self.signal_all(Signal::SIGCONT);
+        info!("Resumed {} workers", self.workers.len());
+        Ok(())
+    }
+
+    fn signal_all(&self, signal: Signal) {
+        for pid in self.workers.keys() {
+            if let Err(e) = kill(*pid, signal) {
+                warn!("Cannot send {signal} to {pid}: {e}");
+            }
+        }
+    }
+
+    fn handle_requests(&mut self) {
+        let requests: Vec<_> = match &self.controller {
+            Some(controller) => iter::from_fn(|| controller.next()).collect(),
+            None => return,
+        };
+
+        for pending in requests {
+            let reply = self.handle(&pending.request);
+            pending.reply(reply);
+        }
+    }
+
+    fn handle(&mut self, request: &Request) -> Reply {
+        let Some(controller) = &self.controller else {
+            return Err(String::from("control is disabled"));
+        };
+
+        match request {
+            Request::Pause => self.pause().map(|_| String::new()),
+            Request::Resume => self.resume().map(|_| String::new()),
+            Request::SetRate { group, rate } => {
+                controller.set_rate(group, *rate)
+            }
+            Request::Status => {
+                let state = match (self.stopping, self.paused) {
+                    (true, _) => "stopping",
+                    (false, true) => "paused",
+                    (false, false) => "running",
+                };
+                Ok(controller.status(state, self.workers.len()))
+            }
+            Request::Stop => {
+                info!("Stopping on request");
+                self.stop();
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn finished(&mut self, status: WaitStatus) {
+        let Some(mut handle) =
+            status.pid().and_then(|pid| self.workers.remove(&pid))
+        else {
+            return;
+        };
+
+        let retirement = handle.retirement.take();
+        let exit = handle.exit(status, self.stopping || retirement.is_some());
+
+        match &exit {
+            WorkerExit::Success | WorkerExit::Stopped => {
+                info!("Finished: {handle}");
+            }
+            WorkerExit::Failed(reason) => {
+                error!("Failed: {handle}: {reason}");
+                self.failures += 1;
+            }
+        }
+
+        if self.stopping || retirement == Some(Retirement::Removed) {
+            return;
+        }
+
+        if retirement == Some(Retirement::Replaced) {
+            match handle.fork() {
+                Ok(()) => {
+                    info!("Replaced: {handle}");
+                    self.add(handle);
+                }
+                Err(e) => {
+                    error!("Cannot replace {handle}: {e}");
+                    self.failures += 1;
+                }
+            }
+            return;
+        }
+
+        if self.policy.applies(&handle, &exit) {
+            let delay = self.policy.delay(handle.restarts);
+            info!("Restarting {handle} in {:?}", delay);
+            self.pending.push((Instant::now() + delay, handle));
+        } else if self.policy.policy != RestartMode::Never {
+            warn!("Not restarting {handle} anymore");
+        }
+    }
+
+    fn restart_pending(&mut self) {
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|(time, _)| *time <= now);
+        self.pending = pending;
+
+        for (_, mut handle) in due {
+            match handle.respawn() {
+                Ok(()) => {
+                    info!("Restarted: {handle}, {} restarts", handle.restarts);
+
+                    if self.paused {
+                        let _ = kill(handle.pid, Signal::SIGSTOP);
+                    }
+
+                    self.add(handle);
+                }
+                Err(e) => {
+                    error!("Cannot restart {handle}: {e}");
+                    self.failures += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkerError;
+
+    struct NoopWorker;
+
+    impl Worker for NoopWorker {
+        fn run_payload(&self) -> Result<(), WorkerError> {
+            Ok(())
+        }
+    }
+
+    fn handle(message: &str, restarts: u32) -> WorkerHandle {
+        let (read_fd, write_fd) =
+            pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).unwrap();
+        let (errors, mut report) = unsafe {
+            (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd))
+        };
+        write!(report, "{message}").unwrap();
+
+        WorkerHandle {
+            pid: Pid::from_raw(1),
+            group: String::from("syscalls"),
+            cpu: CoreId { id: 0 },
+            process: 0,
+            setup: WorkerSetup::default(),
+            restarts,
+            worker: Box::new(NoopWorker),
+            events: None,
+            errors,
+            retirement: None,
+        }
+    }
+
+    #[test]
+    fn test_worker_exit() {
+        let pid = Pid::from_raw(1);
+        let term = WaitStatus::Signaled(pid, Signal::SIGTERM, false);
+
+        assert_eq!(
+            handle("", 0).exit(WaitStatus::Exited(pid, 0), false),
+            WorkerExit::Success
+        );
+        assert_eq!(handle("", 0).exit(term, true), WorkerExit::Stopped);
+        assert_eq!(
+            handle("", 0).exit(term, false),
+            WorkerExit::Failed(String::from("killed by SIGTERM"))
+        );
+        assert_eq!(
+            handle("worker error: boom", 0)
+                .exit(WaitStatus::Exited(pid, 1), false),
+            WorkerExit::Failed(String::from("worker error: boom"))
+        );
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let failed = WorkerExit::Failed(String::from("boom"));
+        let policy = RestartPolicy {
+            policy: RestartMode::OnFailure,
+            max_restarts: 3,
+            backoff: 500,
+            max_backoff: 3000,
+        };
+
+        assert!(policy.applies(&handle("", 0), &failed));
+        assert!(policy.applies(&handle("", 2), &failed));
+        assert!(!policy.applies(&handle("", 3), &failed));
+        assert!(!policy.applies(&handle("", 0), &WorkerExit::Success));
+        assert!(!policy.applies(&handle("", 0), &WorkerExit::Stopped));
+
+        let always = RestartPolicy {
+            policy: RestartMode::Always,
+            max_restarts: 0,
+            ..policy
+        };
+        assert!(always.applies(&handle("", 100), &WorkerExit::Success));
+        assert!(!always.applies(&handle("", 0), &WorkerExit::Stopped));
+
+        assert_eq!(policy.delay(0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_millis(2000));
+        assert_eq!(policy.delay(3), Duration::from_millis(3000));
+        assert_eq!(policy.delay(100), Duration::from_millis(3000));
+    }
+}
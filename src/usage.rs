@@ -0,0 +1,350 @@
+//! Resource usage of a target process, e.g. the monitoring tool under test,
+//! sampled from procfs while the load is generated. Every sample carries the
+//! number of operations generated during the same interval, so that the
+//! overhead could be put side by side with the load, and the time since the
+//! start of the run, to match it with scenario phases.
+
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use nix::unistd::{Pid, SysconfVar, sysconf};
+use serde::Serialize;
+
+use crate::TargetConfig;
+use crate::stats::Registry;
+
+/// Cumulative counters of a process as reported by procfs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcUsage {
+    /// CPU time in user and kernel mode, in clock ticks.
+    pub utime: u64,
+    pub stime: u64,
+
+    /// Resident set size in bytes.
+    pub rss: u64,
+
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+
+    /// Number of open file descriptors, if permitted to look.
+    pub fds: Option<u64>,
+
+    /// Bytes read from and written to storage, if permitted to look.
+    pub read_bytes: Option<u64>,
+    pub write_bytes: Option<u64>,
+}
+
+impl ProcUsage {
+    /// Read current usage of the process from `/proc/<pid>/stat`, `status`,
+    /// `io` and `fd`.
+    pub fn read(pid: Pid) -> io::Result<ProcUsage> {
+        let dir = format!("/proc/{pid}");
+        let invalid = |file: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("cannot parse {dir}/{file}"),
+            )
+        };
+
+        let stat = fs::read_to_string(format!("{dir}/stat"))?;
+        let (utime, stime) =
+            parse_stat(&stat).ok_or_else(|| invalid("stat"))?;
+
+        let status = fs::read_to_string(format!("{dir}/status"))?;
+        let field = |name| status_field(&status, name);
+
+        // Kernel threads have no memory of their own
+        let rss = field("VmRSS").unwrap_or_default() * 1024;
+        let voluntary_switches = field("voluntary_ctxt_switches")
+            .ok_or_else(|| invalid("status"))?;
+        let involuntary_switches = field("nonvoluntary_ctxt_switches")
+            .ok_or_else(|| invalid("status"))?;
+
+        // Both need ptrace access to the process
+        let fds = fs::read_dir(format!("{dir}/fd"))
+            .ok()
+            .map(|entries| entries.count() as u64);
+        let io = fs::read_to_string(format!("{dir}/io")).unwrap_or_default();
+
+        Ok(ProcUsage {
+            utime,
+            stime,
+            rss,
+            voluntary_switches,
+            involuntary_switches,
+            fds,
+            read_bytes: status_field(&io, "read_bytes"),
+            write_bytes: status_field(&io, "write_bytes"),
+        })
+    }
+}
+
+/// User and system CPU time from `/proc/<pid>/stat`. The command name could
+/// contain spaces and parentheses, so fields are counted from the last
+/// closing parenthesis.
+fn parse_stat(content: &str) -> Option<(u64, u64)> {
+    let (_, rest) = content.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace();
+
+    // utime and stime are fields 14 and 15, the first one after the command
+    // name is the state, field 3
+    let utime = fields.nth(11)?.parse().ok()?;
+    let stime = fields.next()?.parse().ok()?;
+
+    Some((utime, stime))
+}
+
+/// Numeric value of a `Name: value [kB]` line, as found in `status` and `io`.
+fn status_field(content: &str, name: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key == name).then(|| value.split_whitespace().next()?.parse().ok())?
+    })
+}
+
+/// Find a process by its command name, as in `/proc/<pid>/comm`. If there
+/// are several, the one with the lowest pid is taken, as it's usually the
+/// main process, which has started the others.
+pub fn find_by_comm(comm: &str) -> Option<Pid> {
+    let own = std::process::id() as i32;
+
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{pid}/comm"))
+                .is_ok_and(|name| name.trim_end() == comm)
+        })
+        .min()
+        .map(Pid::from_raw)
+}
+
+/// Usage of the target process during one sampling interval.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSample {
+    /// End of the interval, in seconds since the start of the run.
+    pub time: f64,
+    pub pid: i32,
+
+    /// CPU utilization during the interval, 100% is one core.
+    pub cpu: f64,
+
+    /// Cumulative CPU time in seconds.
+    pub utime: f64,
+    pub stime: f64,
+
+    /// Resident set size in bytes.
+    pub rss: u64,
+
+    /// Cumulative number of context switches.
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+
+    pub fds: Option<u64>,
+    pub read_bytes: Option<u64>,
+    pub write_bytes: Option<u64>,
+
+    /// Operations generated by all workers during the interval.
+    pub ops: u64,
+}
+
+impl UsageSample {
+    /// Usage between two readings, with CPU time in `ticks` per second.
+    fn new(
+        time: f64,
+        pid: Pid,
+        ticks: f64,
+        previous: &Reading,
+        current: &Reading,
+    ) -> UsageSample {
+        let (before, usage) = (&previous.usage, &current.usage);
+        let elapsed = (current.at - previous.at).as_secs_f64();
+        let cpu_time = (usage.utime + usage.stime)
+            .saturating_sub(before.utime + before.stime);
+
+        UsageSample {
+            time,
+            pid: pid.as_raw(),
+            cpu: cpu_time as f64 / ticks / elapsed * 100.0,
+            utime: usage.utime as f64 / ticks,
+            stime: usage.stime as f64 / ticks,
+            rss: usage.rss,
+            voluntary_switches: usage.voluntary_switches,
+            involuntary_switches: usage.involuntary_switches,
+            fds: usage.fds,
+            read_bytes: usage.read_bytes,
+            write_bytes: usage.write_bytes,
+            ops: current.ops - previous.ops,
+        }
+    }
+}
+
+/// Usage of the target together with the total number of generated
+/// operations at the same moment.
+struct Reading {
+    at: Instant,
+    usage: ProcUsage,
+    ops: u64,
+}
+
+/// Aggregated usage of the target over the whole run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageSummary {
+    pub pid: i32,
+    pub cpu_mean: f64,
+    pub cpu_max: f64,
+    pub rss_max: u64,
+    pub fds_max: Option<u64>,
+}
+
+impl UsageSummary {
+    pub fn new(samples: &[UsageSample]) -> Option<UsageSummary> {
+        let last = samples.last()?;
+        let cpu = samples.iter().map(|s| s.cpu);
+
+        Some(UsageSummary {
+            pid: last.pid,
+            cpu_mean: cpu.clone().sum::<f64>() / samples.len() as f64,
+            cpu_max: cpu.fold(0.0, f64::max),
+            rss_max: samples.iter().map(|s| s.rss).max().unwrap_or_default(),
+            fds_max: samples.iter().filter_map(|s| s.fds).max(),
+        })
+    }
+}
+
+/// Background thread sampling usage of the target process.
+pub struct UsageSampler {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Vec<UsageSample>>,
+}
+
+impl UsageSampler {
+    pub fn start(target: &TargetConfig, registry: Registry) -> UsageSampler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let target = target.clone();
+
+        let handle = thread::spawn(move || {
+            let ticks =
+                sysconf(SysconfVar::CLK_TCK).ok().flatten().unwrap_or(100)
+                    as f64;
+            let interval = Duration::from_millis(target.interval);
+            let start = Instant::now();
+
+            let ops = || {
+                let sources = registry.sources();
+                sources.iter().map(|s| s.stats.snapshot().ops).sum()
+            };
+
+            let mut samples = vec![];
+            let mut sampled = None;
+            let mut previous: Option<Reading> = None;
+
+            for i in 1.. {
+                // A process looked up by name could be restarted, then the
+                // new one is picked up
+                let pid =
+                    target.pid.map(Pid::from_raw).or(sampled).or_else(|| {
+                        target.comm.as_deref().and_then(find_by_comm)
+                    });
+                let usage = pid.and_then(|pid| {
+                    ProcUsage::read(pid).ok().map(|usage| (pid, usage))
+                });
+
+                match usage {
+                    Some((pid, usage)) => {
+                        if sampled != Some(pid) {
+                            info!("Sampling usage of pid {pid}");
+                            previous = None;
+                        }
+
+                        let current = Reading {
+                            at: Instant::now(),
+                            usage,
+                            ops: ops(),
+                        };
+
+                        if let Some(previous) = &previous {
+                            samples.push(UsageSample::new(
+                                (current.at - start).as_secs_f64(),
+                                pid,
+                                ticks,
+                                previous,
+                                &current,
+                            ));
+                        }
+
+                        sampled = Some(pid);
+                        previous = Some(current);
+                    }
+                    None => {
+                        if sampled.is_some() || i == 1 {
+                            warn!("Target process {target} is not found");
+                        }
+
+                        sampled = None;
+                        previous = None;
+                    }
+                }
+
+                // Sleep until an absolute deadline to not accumulate drift
+                let deadline = start + interval * i;
+                while !stopped.load(Ordering::Relaxed) {
+                    match deadline.checked_duration_since(Instant::now()) {
+                        Some(left) => thread::park_timeout(left),
+                        None => break,
+                    }
+                }
+
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+
+            samples
+        });
+
+        UsageSampler { stop, handle }
+    }
+
+    /// Stop sampling and return all the samples taken.
+    pub fn stop(self) -> Vec<UsageSample> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proc_usage() {
+        let stat = "42 (a (b) c) S 1 42 42 0 -1 4194560 1000 0 0 0 \
+                    250 75 0 0 20 0 8 0 100 1000000 500";
+        assert_eq!(parse_stat(stat), Some((250, 75)));
+        assert_eq!(parse_stat("42 (x) S 1"), None);
+
+        let status = "Name:\tfalco\nVmRSS:\t  2048 kB\n\
+                      voluntary_ctxt_switches:\t10\n\
+                      nonvoluntary_ctxt_switches:\t3\n";
+        assert_eq!(status_field(status, "VmRSS"), Some(2048));
+        assert_eq!(status_field(status, "nonvoluntary_ctxt_switches"), Some(3));
+        assert_eq!(status_field(status, "VmSwap"), None);
+
+        let own = Pid::this();
+        let usage = ProcUsage::read(own).expect("cannot read own usage");
+        assert!(usage.rss > 0);
+        assert!(usage.fds.is_some_and(|fds| fds >= 3));
+
+        let comm = fs::read_to_string("/proc/1/comm").unwrap();
+        assert_eq!(find_by_comm(comm.trim_end()), Some(Pid::from_raw(1)));
+    }
+}
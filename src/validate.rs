@@ -0,0 +1,1628 @@
+//! Configuration validation. Every problem is reported together with its
+//! path in the configuration, e.g. `groups[1].workload.arrival_rate`, so that
+//! a misconfigured file could be fixed in one go instead of finding out about
+//! problems one by one when workers panic.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+
+use config::{Value, ValueKind};
+use syscalls::Sysno;
+
+use crate::cgroup::{self, CgroupConfig};
+use crate::distribution::Empirical;
+use crate::namespaces::{Namespace, NamespaceConfig};
+use crate::pacing::Pacing;
+use crate::placement::Placement;
+use crate::rate::Schedule;
+use crate::scheduling::{IoClass, Policy, Scheduling};
+use crate::{CONFIG_VERSION, Distribution, Phase, Workload, WorkloadConfig};
+
+/// A single problem found in the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// Where in the configuration the problem was found.
+    pub path: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub(crate) fn new(path: &str, message: impl Into<String>) -> Self {
+        ValidationError {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<config::ConfigError> for ValidationError {
+    fn from(e: config::ConfigError) -> Self {
+        ValidationError::new("", e.to_string())
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+const CONFIG_KEYS: &[&str] = &[
+    "version",
+    "restart_interval",
+    "per_core",
+    "workers",
+    "placement",
+    "scheduling",
+    "cgroup",
+    "namespaces",
+    "workload",
+    "duration",
+    "groups",
+    "scenario",
+    "restart",
+    "metrics",
+    "results",
+    "target",
+    "control",
+    "seed",
+    "schedule",
+];
+
+const GROUP_KEYS: &[&str] = &[
+    "name",
+    "per_core",
+    "workers",
+    "placement",
+    "scheduling",
+    "cgroup",
+    "namespaces",
+    "workload",
+    "schedule",
+];
+
+const PLACEMENT_KEYS: &[&str] = &["cpus", "reserved", "strategy"];
+
+const SCHEDULING_KEYS: &[&str] =
+    &["nice", "policy", "priority", "io_class", "io_priority"];
+
+const CGROUP_KEYS: &[&str] = &[
+    "parent",
+    "naming",
+    "count",
+    "cpu_max",
+    "memory_max",
+    "pids_max",
+];
+
+const NAMESPACES_KEYS: &[&str] = &["unshare", "hostname", "rootfs"];
+
+const RESTART_KEYS: &[&str] =
+    &["policy", "max_restarts", "backoff", "max_backoff"];
+
+const METRICS_KEYS: &[&str] = &["address"];
+
+const RESULTS_KEYS: &[&str] = &["json", "csv", "events", "tag_paths"];
+
+const TARGET_KEYS: &[&str] = &["pid", "comm", "interval"];
+
+const CONTROL_KEYS: &[&str] = &["socket"];
+
+const PHASE_KEYS: &[&str] = &[
+    "duration",
+    "scale",
+    "arrival_rate",
+    "departure_rate",
+    "connections_dyn_max",
+];
+
+const PACING_KEYS: &[&str] = &["wait", "max_batch", "max_lag"];
+
+const STEP_KEYS: &[&str] = &["duration", "scale"];
+
+const POINT_KEYS: &[&str] = &["at", "scale"];
+
+/// Known keys for every schedule type, including the `type` tag itself.
+fn schedule_keys(kind: &str) -> Option<&'static [&'static str]> {
+    let keys: &[&str] = match kind {
+        "step" => &["type", "steps", "repeat"],
+        "sine" => &["type", "period", "offset", "amplitude"],
+        "bursts" => &["type", "on_scale", "off_scale", "mean_on", "mean_off"],
+        "ramp" => &["type", "points"],
+        _ => return None,
+    };
+
+    Some(keys)
+}
+
+/// Known keys for every distribution family, including the `distribution`
+/// tag itself.
+fn distribution_keys(kind: &str) -> Option<&'static [&'static str]> {
+    let keys: &[&str] = match kind {
+        "constant" => &["distribution", "value"],
+        "uniform" | "Uniform" => &["distribution", "lower", "upper"],
+        "exp" | "exponential" => &["distribution", "rate"],
+        "normal" => &["distribution", "mean", "std_dev"],
+        "lognormal" => &["distribution", "mu", "sigma"],
+        "pareto" => &["distribution", "scale", "shape"],
+        "zipf" | "Zipfian" => &["distribution", "n", "n_ports", "exponent"],
+        "empirical" => &["distribution", "path"],
+        _ => return None,
+    };
+
+    Some(keys)
+}
+
+/// Workload options holding a distribution in a nested table.
+const DISTRIBUTION_OPTIONS: &[&str] = &["lifetime", "payload_size"];
+
+/// Known keys for every workload type, including the `type` tag itself.
+fn workload_keys(kind: &str) -> Option<&'static [&'static str]> {
+    let keys: &[&str] = match kind {
+        // Plus keys of the port count distribution
+        "endpoints" => &["type"],
+        "processes" => &[
+            "type",
+            "arrival_rate",
+            "departure_rate",
+            "lifetime",
+            "random_process",
+        ],
+        "syscalls" => &[
+            "type",
+            "arrival_rate",
+            "tight_loop",
+            "pacing",
+            "syscall_nr",
+            "syscall_args",
+        ],
+        "iouring" => &[
+            "type",
+            "arrival_rate",
+            "pacing",
+            "iouring_nr",
+            "iouring_args",
+        ],
+        "network" => &[
+            "type",
+            "server",
+            "address",
+            "target_port",
+            "arrival_rate",
+            "departure_rate",
+            "lifetime",
+            "connections_static",
+            "connections_dyn_max",
+            "conns_per_addr",
+            "send_interval",
+            "payload_size",
+            "preempt",
+        ],
+        "bpf" => &["type", "tracepoint", "nprogs"],
+        _ => return None,
+    };
+
+    Some(keys)
+}
+
+/// Find keys in the raw configuration, that do not correspond to any option.
+/// Such keys are ignored during deserialization, which makes typos pass
+/// silently.
+pub fn unknown_keys(raw: &HashMap<String, Value>) -> Vec<ValidationError> {
+    let mut errors = vec![];
+
+    check_keys("", raw, CONFIG_KEYS, &mut errors);
+
+    if let Some(workload) = raw.get("workload") {
+        check_workload("workload", workload, &mut errors);
+    }
+
+    if let Some(schedule) = raw.get("schedule") {
+        check_schedule("schedule", schedule, &mut errors);
+    }
+
+    for (i, group) in array(raw.get("groups")).iter().enumerate() {
+        let path = format!("groups[{i}]");
+
+        if let ValueKind::Table(table) = &group.kind {
+            check_keys(&path, table, GROUP_KEYS, &mut errors);
+
+            if let Some(workload) = table.get("workload") {
+                check_workload(
+                    &format!("{path}.workload"),
+                    workload,
+                    &mut errors,
+                );
+            }
+
+            if let Some(schedule) = table.get("schedule") {
+                check_schedule(
+                    &format!("{path}.schedule"),
+                    schedule,
+                    &mut errors,
+                );
+            }
+
+            for (key, known) in [
+                ("placement", PLACEMENT_KEYS),
+                ("scheduling", SCHEDULING_KEYS),
+                ("cgroup", CGROUP_KEYS),
+                ("namespaces", NAMESPACES_KEYS),
+            ] {
+                if let Some(Value {
+                    kind: ValueKind::Table(table),
+                    ..
+                }) = table.get(key)
+                {
+                    check_keys(
+                        &format!("{path}.{key}"),
+                        table,
+                        known,
+                        &mut errors,
+                    );
+                }
+            }
+        }
+    }
+
+    for (key, known) in [
+        ("placement", PLACEMENT_KEYS),
+        ("scheduling", SCHEDULING_KEYS),
+        ("cgroup", CGROUP_KEYS),
+        ("namespaces", NAMESPACES_KEYS),
+        ("restart", RESTART_KEYS),
+        ("metrics", METRICS_KEYS),
+        ("results", RESULTS_KEYS),
+        ("target", TARGET_KEYS),
+        ("control", CONTROL_KEYS),
+    ] {
+        if let Some(Value {
+            kind: ValueKind::Table(table),
+            ..
+        }) = raw.get(key)
+        {
+            check_keys(key, table, known, &mut errors);
+        }
+    }
+
+    for (i, phase) in array(raw.get("scenario")).iter().enumerate() {
+        if let ValueKind::Table(table) = &phase.kind {
+            check_keys(
+                &format!("scenario[{i}]"),
+                table,
+                PHASE_KEYS,
+                &mut errors,
+            );
+        }
+    }
+
+    errors
+}
+
+fn array(value: Option<&Value>) -> &[Value] {
+    match value.map(|v| &v.kind) {
+        Some(ValueKind::Array(values)) => values,
+        _ => &[],
+    }
+}
+
+fn check_keys(
+    path: &str,
+    table: &HashMap<String, Value>,
+    known: &[&str],
+    errors: &mut Vec<ValidationError>,
+) {
+    let mut keys: Vec<_> = table
+        .keys()
+        .filter(|key| !known.contains(&key.as_str()))
+        .collect();
+    keys.sort();
+
+    for key in keys {
+        errors.push(ValidationError::new(
+            &join(path, key),
+            "unknown configuration key",
+        ));
+    }
+}
+
+fn check_workload(
+    path: &str,
+    workload: &Value,
+    errors: &mut Vec<ValidationError>,
+) {
+    let ValueKind::Table(table) = &workload.kind else {
+        return;
+    };
+
+    // Unknown workload types are reported during deserialization
+    let kind = table
+        .get("type")
+        .and_then(|t| t.clone().into_string().ok())
+        .unwrap_or_default();
+
+    let Some(known) = workload_keys(&kind) else {
+        return;
+    };
+
+    if kind == "endpoints" {
+        // Unknown distribution families are reported during deserialization
+        let Some(distribution) =
+            distribution_kind(table).and_then(|k| distribution_keys(&k))
+        else {
+            return;
+        };
+
+        let known: Vec<_> = known.iter().chain(distribution).copied().collect();
+        check_keys(path, table, &known, errors);
+    } else {
+        check_keys(path, table, known, errors);
+    }
+
+    for option in DISTRIBUTION_OPTIONS {
+        if let Some(Value {
+            kind: ValueKind::Table(distribution),
+            ..
+        }) = table.get(*option)
+            && let Some(known) = distribution_kind(distribution)
+                .and_then(|k| distribution_keys(&k))
+        {
+            check_keys(&join(path, option), distribution, known, errors);
+        }
+    }
+
+    if let Some(Value {
+        kind: ValueKind::Table(pacing),
+        ..
+    }) = table.get("pacing")
+    {
+        check_keys(&join(path, "pacing"), pacing, PACING_KEYS, errors);
+    }
+}
+
+fn distribution_kind(table: &HashMap<String, Value>) -> Option<String> {
+    table
+        .get("distribution")
+        .and_then(|d| d.clone().into_string().ok())
+}
+
+fn check_schedule(
+    path: &str,
+    schedule: &Value,
+    errors: &mut Vec<ValidationError>,
+) {
+    let ValueKind::Table(table) = &schedule.kind else {
+        return;
+    };
+
+    // Unknown schedule types are reported during deserialization
+    let kind = table
+        .get("type")
+        .and_then(|t| t.clone().into_string().ok())
+        .unwrap_or_default();
+
+    if let Some(known) = schedule_keys(&kind) {
+        check_keys(path, table, known, errors);
+    }
+
+    for (list, known) in [("steps", STEP_KEYS), ("points", POINT_KEYS)] {
+        for (i, item) in array(table.get(list)).iter().enumerate() {
+            if let ValueKind::Table(item) = &item.kind {
+                check_keys(&format!("{path}.{list}[{i}]"), item, known, errors);
+            }
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// Check values of the deserialized configuration for problems, that will
+/// make workers fail, e.g. zero rates or invalid ranges.
+pub fn validate(config: &WorkloadConfig) -> Vec<ValidationError> {
+    let mut errors = vec![];
+
+    match config.version {
+        None | Some(CONFIG_VERSION) => {}
+        Some(1) => errors.push(ValidationError::new(
+            "version",
+            "version 1 rates of syscalls and io_uring are per millisecond, \
+             multiply them by 1000 and set version 2",
+        )),
+        Some(version) => errors.push(ValidationError::new(
+            "version",
+            format!("unsupported version {version}, expected {CONFIG_VERSION}"),
+        )),
+    }
+
+    if config.workload.is_none() && config.groups.is_empty() {
+        errors.push(ValidationError::new(
+            "workload",
+            "no workload or workload groups specified",
+        ));
+    }
+
+    if config.workload.is_some() {
+        check_workers("workers", config.workers, &mut errors);
+    }
+
+    if let Some(placement) = &config.placement {
+        check_placement("placement", placement, &mut errors);
+    }
+
+    if let Some(scheduling) = &config.scheduling {
+        check_scheduling("scheduling", scheduling, &mut errors);
+    }
+
+    if let Some(cgroup) = &config.cgroup {
+        check_cgroup("cgroup", cgroup, &mut errors);
+    }
+
+    if let Some(namespaces) = &config.namespaces {
+        check_namespaces("namespaces", namespaces, &mut errors);
+    }
+
+    if let (Some(scheduling), Some(namespaces)) =
+        (&config.scheduling, &config.namespaces)
+    {
+        check_privileges("scheduling", scheduling, namespaces, &mut errors);
+    }
+
+    if let Some(workload) = &config.workload {
+        check_workload_values("workload", workload, &mut errors);
+    }
+
+    if let Some(schedule) = &config.schedule {
+        check_schedule_values("schedule", schedule, &mut errors);
+    }
+
+    // The top level workload is a group on its own, named by the workload
+    // type
+    let mut names: Vec<(String, &str)> = config
+        .workload
+        .iter()
+        .map(|workload| (String::from("workload"), workload.kind()))
+        .collect();
+
+    for (i, group) in config.groups.iter().enumerate() {
+        let path = format!("groups[{i}]");
+
+        check_workers(&format!("{path}.workers"), group.workers, &mut errors);
+
+        if let Some(placement) = &group.placement {
+            check_placement(
+                &format!("{path}.placement"),
+                placement,
+                &mut errors,
+            );
+        }
+
+        if let Some(scheduling) = &group.scheduling {
+            check_scheduling(
+                &format!("{path}.scheduling"),
+                scheduling,
+                &mut errors,
+            );
+        }
+
+        if let Some(cgroup) = &group.cgroup {
+            check_cgroup(&format!("{path}.cgroup"), cgroup, &mut errors);
+        }
+
+        if let Some(namespaces) = &group.namespaces {
+            check_namespaces(
+                &format!("{path}.namespaces"),
+                namespaces,
+                &mut errors,
+            );
+        }
+
+        // Either of them could be inherited, but the combination is reported
+        // only once if both are
+        let scheduling =
+            group.scheduling.as_ref().or(config.scheduling.as_ref());
+        let namespaces =
+            group.namespaces.as_ref().or(config.namespaces.as_ref());
+
+        if let (Some(scheduling), Some(namespaces)) = (scheduling, namespaces)
+            && (group.scheduling.is_some() || group.namespaces.is_some())
+        {
+            check_privileges(
+                &format!("{path}.scheduling"),
+                scheduling,
+                namespaces,
+                &mut errors,
+            );
+        }
+
+        check_workload_values(
+            &format!("{path}.workload"),
+            &group.workload,
+            &mut errors,
+        );
+
+        if let Some(schedule) = &group.schedule {
+            check_schedule_values(
+                &format!("{path}.schedule"),
+                schedule,
+                &mut errors,
+            );
+        }
+
+        names.push((path, group.name()));
+    }
+
+    for (i, (path, name)) in names.iter().enumerate() {
+        if names[..i].iter().any(|(_, other)| other == name) {
+            errors.push(ValidationError::new(
+                &format!("{path}.name"),
+                format!("duplicated group name '{name}'"),
+            ));
+        }
+    }
+
+    for (i, phase) in config.scenario.iter().enumerate() {
+        check_phase(&format!("scenario[{i}]"), phase, &mut errors);
+    }
+
+    if config.restart.backoff > config.restart.max_backoff {
+        errors.push(ValidationError::new(
+            "restart.backoff",
+            format!(
+                "backoff {} is larger than max_backoff {}",
+                config.restart.backoff, config.restart.max_backoff
+            ),
+        ));
+    }
+
+    if let Some(metrics) = &config.metrics
+        && metrics.address.parse::<SocketAddr>().is_err()
+    {
+        errors.push(ValidationError::new(
+            "metrics.address",
+            format!("invalid listen address '{}'", metrics.address),
+        ));
+    }
+
+    if config.results.tag_paths && config.results.events.is_none() {
+        errors.push(ValidationError::new(
+            "results.tag_paths",
+            "tags are embedded only into logged events, events log needed",
+        ));
+    }
+
+    if let Some(target) = &config.target {
+        if target.pid.is_some() == target.comm.is_some() {
+            errors.push(ValidationError::new(
+                "target",
+                "exactly one of pid or comm has to be specified",
+            ));
+        }
+
+        if target.interval == 0 {
+            errors.push(ValidationError::new(
+                "target.interval",
+                "sampling interval must be positive",
+            ));
+        }
+    }
+
+    errors
+}
+
+/// Find options, that are valid but likely mean something else than
+/// intended. Without a version, rates of syscalls and io_uring workloads might
+/// still be written per millisecond as the format version 1 had it.
+pub fn warnings(config: &WorkloadConfig) -> Vec<ValidationError> {
+    if config.version.is_some() {
+        return vec![];
+    }
+
+    let workloads = config
+        .workload
+        .iter()
+        .map(|workload| (String::from("workload"), workload))
+        .chain(config.groups.iter().enumerate().map(|(i, group)| {
+            (format!("groups[{i}].workload"), &group.workload)
+        }));
+
+    workloads
+        .filter(|(_, workload)| {
+            matches!(
+                workload,
+                Workload::Syscalls { .. } | Workload::IOUring { .. }
+            )
+        })
+        .map(|(path, _)| {
+            ValidationError::new(
+                &join(&path, "arrival_rate"),
+                format!(
+                    "rate is per second since version 2, set version = \
+                     {CONFIG_VERSION} if it's already converted"
+                ),
+            )
+        })
+        .collect()
+}
+
+fn check_workers(
+    path: &str,
+    workers: usize,
+    errors: &mut Vec<ValidationError>,
+) {
+    if workers == 0 {
+        errors.push(ValidationError::new(path, "at least one worker needed"));
+    }
+}
+
+fn check_placement(
+    path: &str,
+    placement: &Placement,
+    errors: &mut Vec<ValidationError>,
+) {
+    if let Some(cpus) = &placement.cpus {
+        if cpus.is_empty() {
+            errors.push(ValidationError::new(
+                &join(path, "cpus"),
+                "at least one CPU needed",
+            ));
+        } else if cpus.iter().all(|cpu| placement.reserved.contains(cpu)) {
+            errors.push(ValidationError::new(
+                &join(path, "reserved"),
+                format!("all CPUs {cpus} are reserved"),
+            ));
+        }
+    }
+}
+
+fn check_scheduling(
+    path: &str,
+    scheduling: &Scheduling,
+    errors: &mut Vec<ValidationError>,
+) {
+    let mut check =
+        |key: &str, value: Option<i32>, range: RangeInclusive<i32>| {
+            if let Some(value) = value
+                && !range.contains(&value)
+            {
+                errors.push(ValidationError::new(
+                    &join(path, key),
+                    format!(
+                        "must be from {} to {}, got {value}",
+                        range.start(),
+                        range.end()
+                    ),
+                ));
+            }
+        };
+
+    check("nice", scheduling.nice, -20..=19);
+    check("io_priority", scheduling.io_priority, 0..=7);
+
+    let realtime = scheduling.policy.is_some_and(|p| p.realtime());
+    if realtime {
+        check("priority", scheduling.priority, 1..=99);
+    }
+
+    match (realtime, scheduling.priority) {
+        (true, None) => errors.push(ValidationError::new(
+            &join(path, "priority"),
+            "real-time policy needs a priority",
+        )),
+        (false, Some(_)) => errors.push(ValidationError::new(
+            &join(path, "priority"),
+            "priority applies only to a real-time policy",
+        )),
+        _ => (),
+    }
+
+    if scheduling.io_priority.is_some() && scheduling.io_class.is_none() {
+        errors.push(ValidationError::new(
+            &join(path, "io_priority"),
+            "I/O priority needs an io_class",
+        ));
+    }
+}
+
+fn check_cgroup(
+    path: &str,
+    config: &CgroupConfig,
+    errors: &mut Vec<ValidationError>,
+) {
+    if !config.parent.starts_with('/') {
+        errors.push(ValidationError::new(
+            &join(path, "parent"),
+            format!("must be an absolute path, got '{}'", config.parent),
+        ));
+    }
+
+    if config.count == 0 {
+        errors.push(ValidationError::new(
+            &join(path, "count"),
+            "at least one cgroup needed",
+        ));
+    }
+
+    if let Some(cpu_max) = &config.cpu_max
+        && !cgroup::valid_cpu_max(cpu_max)
+    {
+        errors.push(ValidationError::new(
+            &join(path, "cpu_max"),
+            format!("expected '<quota|max> [period]', got '{cpu_max}'"),
+        ));
+    }
+
+    if let Some(memory_max) = &config.memory_max
+        && !cgroup::valid_memory_max(memory_max)
+    {
+        errors.push(ValidationError::new(
+            &join(path, "memory_max"),
+            format!("expected 'max' or bytes, got '{memory_max}'"),
+        ));
+    }
+}
+
+fn check_namespaces(
+    path: &str,
+    config: &NamespaceConfig,
+    errors: &mut Vec<ValidationError>,
+) {
+    if config.unshare.is_empty() {
+        errors.push(ValidationError::new(
+            &join(path, "unshare"),
+            "at least one namespace needed",
+        ));
+    }
+
+    if config.hostname.is_empty() {
+        errors.push(ValidationError::new(
+            &join(path, "hostname"),
+            "must not be empty",
+        ));
+    }
+
+    let Some(rootfs) = &config.rootfs else {
+        return;
+    };
+
+    if !rootfs.starts_with('/') {
+        errors.push(ValidationError::new(
+            &join(path, "rootfs"),
+            format!("must be an absolute path, got '{rootfs}'"),
+        ));
+    }
+
+    if !config.contains(Namespace::Mnt) {
+        errors.push(ValidationError::new(
+            &join(path, "rootfs"),
+            "needs a mnt namespace",
+        ));
+    }
+}
+
+/// Workers apply the scheduling settings once they are in their namespaces.
+/// Raising priorities needs capabilities in the initial user namespace, which
+/// workers in a user namespace of their own do not have.
+fn check_privileges(
+    path: &str,
+    scheduling: &Scheduling,
+    namespaces: &NamespaceConfig,
+    errors: &mut Vec<ValidationError>,
+) {
+    if !namespaces.contains(Namespace::User) {
+        return;
+    }
+
+    if scheduling.policy == Some(Policy::Fifo) {
+        errors.push(ValidationError::new(
+            &join(path, "policy"),
+            "fifo is not permitted in a user namespace",
+        ));
+    }
+
+    if scheduling.nice.is_some_and(|nice| nice < 0) {
+        errors.push(ValidationError::new(
+            &join(path, "nice"),
+            "negative nice is not permitted in a user namespace",
+        ));
+    }
+
+    if scheduling.io_class == Some(IoClass::Realtime) {
+        errors.push(ValidationError::new(
+            &join(path, "io_class"),
+            "realtime is not permitted in a user namespace",
+        ));
+    }
+}
+
+fn check_rate(path: &str, rate: f64, errors: &mut Vec<ValidationError>) {
+    if !(rate.is_finite() && rate > 0.0) {
+        errors.push(ValidationError::new(
+            path,
+            format!("rate must be a positive number, got {rate}"),
+        ));
+    }
+}
+
+fn check_workload_values(
+    path: &str,
+    workload: &Workload,
+    errors: &mut Vec<ValidationError>,
+) {
+    let key = |name: &str| join(path, name);
+
+    match workload {
+        Workload::Endpoints { distribution } => {
+            check_distribution(path, distribution, errors);
+        }
+        Workload::Processes {
+            arrival_rate,
+            departure_rate,
+            lifetime,
+            ..
+        } => {
+            check_rate(&key("arrival_rate"), *arrival_rate, errors);
+
+            match lifetime {
+                Some(lifetime) => {
+                    check_distribution(&key("lifetime"), lifetime, errors)
+                }
+                None => {
+                    check_rate(&key("departure_rate"), *departure_rate, errors)
+                }
+            }
+        }
+        Workload::Syscalls {
+            arrival_rate,
+            tight_loop,
+            pacing,
+            syscall_nr,
+            ..
+        } => {
+            if !tight_loop {
+                check_rate(&key("arrival_rate"), *arrival_rate, errors);
+                check_pacing(&key("pacing"), pacing, errors);
+            }
+
+            if Sysno::new(*syscall_nr as usize).is_none() {
+                errors.push(ValidationError::new(
+                    &key("syscall_nr"),
+                    format!("unknown syscall {syscall_nr}"),
+                ));
+            }
+        }
+        Workload::IOUring {
+            arrival_rate,
+            pacing,
+            iouring_nr,
+            ..
+        } => {
+            check_rate(&key("arrival_rate"), *arrival_rate, errors);
+            check_pacing(&key("pacing"), pacing, errors);
+
+            if !crate::worker::io_uring::SUPPORTED
+                .iter()
+                .any(|(code, _)| code == iouring_nr)
+            {
+                errors.push(ValidationError::new(
+                    &key("iouring_nr"),
+                    format!("unsupported io_uring operation {iouring_nr}"),
+                ));
+            }
+        }
+        Workload::Network {
+            server,
+            arrival_rate,
+            departure_rate,
+            lifetime,
+            conns_per_addr,
+            payload_size,
+            ..
+        } => {
+            // Rates and lifetimes are used only by the client
+            if !server {
+                check_rate(&key("arrival_rate"), *arrival_rate, errors);
+
+                match lifetime {
+                    Some(lifetime) => {
+                        check_distribution(&key("lifetime"), lifetime, errors)
+                    }
+                    None => check_rate(
+                        &key("departure_rate"),
+                        *departure_rate,
+                        errors,
+                    ),
+                }
+            }
+
+            if let Some(payload_size) = payload_size {
+                check_distribution(&key("payload_size"), payload_size, errors);
+            }
+
+            if *conns_per_addr == 0 {
+                errors.push(ValidationError::new(
+                    &key("conns_per_addr"),
+                    "at least one connection per address needed",
+                ));
+            }
+        }
+        Workload::Bpf { .. } => {}
+    }
+}
+
+fn check_pacing(
+    path: &str,
+    pacing: &Pacing,
+    errors: &mut Vec<ValidationError>,
+) {
+    if pacing.max_batch == 0 {
+        errors.push(ValidationError::new(
+            &join(path, "max_batch"),
+            "at least one event per batch needed",
+        ));
+    }
+
+    check_positive(&join(path, "max_lag"), pacing.max_lag, errors);
+}
+
+fn check_distribution(
+    path: &str,
+    distribution: &Distribution,
+    errors: &mut Vec<ValidationError>,
+) {
+    let key = |name: &str| join(path, name);
+
+    match distribution {
+        Distribution::Constant { value } => {
+            check_finite(&key("value"), *value, errors);
+        }
+        Distribution::Uniform { lower, upper } => {
+            if !(lower < upper && lower.is_finite() && upper.is_finite()) {
+                errors.push(ValidationError::new(
+                    &key("lower"),
+                    format!(
+                        "lower bound {lower} must be less than upper bound {upper}"
+                    ),
+                ));
+            }
+        }
+        Distribution::Exponential { rate } => {
+            check_rate(&key("rate"), *rate, errors);
+        }
+        Distribution::Normal { mean, std_dev } => {
+            check_finite(&key("mean"), *mean, errors);
+            check_non_negative(&key("std_dev"), *std_dev, errors);
+        }
+        Distribution::LogNormal { mu, sigma } => {
+            check_finite(&key("mu"), *mu, errors);
+            check_non_negative(&key("sigma"), *sigma, errors);
+        }
+        Distribution::Pareto { scale, shape } => {
+            check_positive(&key("scale"), *scale, errors);
+            check_positive(&key("shape"), *shape, errors);
+        }
+        Distribution::Zipfian { n, exponent } => {
+            if *n == 0 {
+                errors.push(ValidationError::new(
+                    &key("n"),
+                    "at least one value needed",
+                ));
+            }
+
+            check_positive(&key("exponent"), *exponent, errors);
+        }
+        Distribution::Empirical { path: file } => {
+            if let Err(e) = Empirical::load(file) {
+                errors.push(ValidationError::new(&key("path"), e.to_string()));
+            }
+        }
+    }
+}
+
+fn check_finite(path: &str, value: f64, errors: &mut Vec<ValidationError>) {
+    if !value.is_finite() {
+        errors.push(ValidationError::new(
+            path,
+            format!("must be a finite number, got {value}"),
+        ));
+    }
+}
+
+fn check_positive(path: &str, value: f64, errors: &mut Vec<ValidationError>) {
+    if !(value.is_finite() && value > 0.0) {
+        errors.push(ValidationError::new(
+            path,
+            format!("must be a positive number, got {value}"),
+        ));
+    }
+}
+
+fn check_non_negative(
+    path: &str,
+    value: f64,
+    errors: &mut Vec<ValidationError>,
+) {
+    if !(value.is_finite() && value >= 0.0) {
+        errors.push(ValidationError::new(
+            path,
+            format!("must be a non-negative number, got {value}"),
+        ));
+    }
+}
+
+fn check_phase(path: &str, phase: &Phase, errors: &mut Vec<ValidationError>) {
+    let key = |name: &str| join(path, name);
+
+    if phase.duration == 0 {
+        errors.push(ValidationError::new(
+            &key("duration"),
+            "phase duration must be positive",
+        ));
+    }
+
+    check_rate(&key("scale"), phase.scale, errors);
+
+    if let Some(rate) = phase.arrival_rate {
+        check_rate(&key("arrival_rate"), rate, errors);
+    }
+
+    if let Some(rate) = phase.departure_rate {
+        check_rate(&key("departure_rate"), rate, errors);
+    }
+
+    if phase.connections_dyn_max == Some(0) {
+        errors.push(ValidationError::new(
+            &key("connections_dyn_max"),
+            "at least one dynamic connection needed",
+        ));
+    }
+}
+
+fn check_scale(path: &str, scale: f64, errors: &mut Vec<ValidationError>) {
+    if !(scale.is_finite() && scale >= 0.0) {
+        errors.push(ValidationError::new(
+            path,
+            format!("scale must be a non-negative number, got {scale}"),
+        ));
+    }
+}
+
+fn check_duration(path: &str, value: f64, errors: &mut Vec<ValidationError>) {
+    if !(value.is_finite() && value > 0.0) {
+        errors.push(ValidationError::new(
+            path,
+            format!("duration must be a positive number, got {value}"),
+        ));
+    }
+}
+
+fn check_schedule_values(
+    path: &str,
+    schedule: &Schedule,
+    errors: &mut Vec<ValidationError>,
+) {
+    let key = |name: &str| join(path, name);
+    let found = errors.len();
+
+    match schedule {
+        Schedule::Step { steps, .. } => {
+            if steps.is_empty() {
+                errors.push(ValidationError::new(
+                    &key("steps"),
+                    "at least one step needed",
+                ));
+            }
+
+            for (i, step) in steps.iter().enumerate() {
+                let path = format!("{path}.steps[{i}]");
+                check_duration(&join(&path, "duration"), step.duration, errors);
+                check_scale(&join(&path, "scale"), step.scale, errors);
+            }
+        }
+        Schedule::Sine {
+            period,
+            offset,
+            amplitude,
+        } => {
+            check_duration(&key("period"), *period, errors);
+
+            if !(offset.is_finite() && amplitude.is_finite()) {
+                errors.push(ValidationError::new(
+                    &key("amplitude"),
+                    "offset and amplitude must be finite numbers",
+                ));
+            }
+        }
+        Schedule::Bursts {
+            on_scale,
+            off_scale,
+            mean_on,
+            mean_off,
+        } => {
+            check_scale(&key("on_scale"), *on_scale, errors);
+            check_scale(&key("off_scale"), *off_scale, errors);
+            check_duration(&key("mean_on"), *mean_on, errors);
+            check_duration(&key("mean_off"), *mean_off, errors);
+        }
+        Schedule::Ramp { points } => {
+            if points.is_empty() {
+                errors.push(ValidationError::new(
+                    &key("points"),
+                    "at least one point needed",
+                ));
+            }
+
+            for (i, point) in points.iter().enumerate() {
+                let path = format!("{path}.points[{i}]");
+                check_scale(&join(&path, "scale"), point.scale, errors);
+
+                if i > 0 && point.at <= points[i - 1].at {
+                    errors.push(ValidationError::new(
+                        &join(&path, "at"),
+                        "points must be ordered by time",
+                    ));
+                }
+            }
+        }
+    }
+
+    // Otherwise nothing is ever going to happen
+    if errors.len() == found && schedule.max_scale() <= 0.0 {
+        errors.push(ValidationError::new(
+            path,
+            "schedule scale is never positive",
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// All problems found in the configuration.
+    fn errors(input: &str) -> Vec<String> {
+        match WorkloadConfig::from_config(testing::source(input)) {
+            Ok(_) => vec![],
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "bpf"
+            nporgs = 100
+
+            [[groups]]
+            name = "processes"
+            speed = 1
+
+            [groups.workload]
+            type = "processes"
+            arrival_rate = 10
+            departure_rate = 1
+            random_process = false
+
+            [groups.workload.lifetime]
+            distribution = "pareto"
+            scale = 1
+            shape = 2
+            shift = 1
+
+            [groups.schedule]
+            type = "ramp"
+            points = [{ at = 5, scale = 2, rate = 1 }]
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "workload.nporgs: unknown configuration key",
+                "groups[0].speed: unknown configuration key",
+                "groups[0].workload.lifetime.shift: unknown configuration key",
+                "groups[0].schedule.points[0].rate: unknown configuration key",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scheduling() {
+        let input = r#"
+            restart_interval = 10
+
+            [scheduling]
+            nice = 30
+            policy = "fifo"
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [[groups]]
+            name = "batch"
+            scheduling = { priority = 10 }
+            workload = { type = "syscalls", arrival_rate = 100 }
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "scheduling.nice: must be from -20 to 19, got 30",
+                "scheduling.priority: real-time policy needs a priority",
+                "groups[0].scheduling.priority: priority applies only to a real-time policy",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workers() {
+        let input = r#"
+            restart_interval = 10
+
+            [[groups]]
+            workers = 0
+            workload = { type = "syscalls", arrival_rate = 100 }
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec!["groups[0].workers: at least one worker needed"]
+        );
+    }
+
+    #[test]
+    fn test_placement() {
+        let input = r#"
+            restart_interval = 10
+
+            [placement]
+            cpus = "2-3"
+            reserved = "0-3"
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec!["placement.reserved: all CPUs 2-3 are reserved"]
+        );
+    }
+
+    #[test]
+    fn test_cgroup() {
+        let input = r#"
+            restart_interval = 10
+
+            [cgroup]
+            parent = "berserker"
+            cpu_max = "half"
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "cgroup.parent: must be an absolute path, got 'berserker'",
+                "cgroup.cpu_max: expected '<quota|max> [period]', got 'half'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let input = r#"
+            restart_interval = 10
+
+            [namespaces]
+            unshare = ["uts", "net"]
+            rootfs = "rootfs"
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "namespaces.rootfs: must be an absolute path, got 'rootfs'",
+                "namespaces.rootfs: needs a mnt namespace",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_namespace_privileges() {
+        let input = r#"
+            restart_interval = 10
+
+            [scheduling]
+            nice = -5
+
+            [namespaces]
+            unshare = ["pid", "user"]
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [[groups]]
+            name = "realtime"
+            scheduling = { policy = "fifo", priority = 10 }
+            workload = { type = "syscalls", arrival_rate = 100 }
+
+            [[groups]]
+            name = "inherited"
+            workload = { type = "syscalls", arrival_rate = 100 }
+
+            [[groups]]
+            name = "host"
+            namespaces = { unshare = ["pid"] }
+            workload = { type = "syscalls", arrival_rate = 100 }
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "scheduling.nice: negative nice is not permitted in a user \
+                 namespace",
+                "groups[0].scheduling.policy: fifo is not permitted in a user \
+                 namespace",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workload_values() {
+        let input = r#"
+            restart_interval = 10
+
+            [[groups]]
+            workload = { type = "endpoints", distribution = "uniform", lower = 100, upper = 10 }
+
+            [[groups]]
+            [groups.workload]
+            type = "processes"
+            arrival_rate = 0.0
+            random_process = false
+
+            [groups.workload.lifetime]
+            distribution = "pareto"
+            scale = 0
+            shape = 2
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "groups[0].workload.lower: lower bound 100 must be less than upper bound 10",
+                "groups[1].workload.arrival_rate: rate must be a positive number, got 0",
+                "groups[1].workload.lifetime.scale: must be a positive number, got 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [schedule]
+            type = "ramp"
+            points = [{ at = 10, scale = 1 }, { at = 5, scale = 2 }]
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec!["schedule.points[1].at: points must be ordered by time"]
+        );
+    }
+
+    #[test]
+    fn test_duplicated_names() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "bpf"
+
+            [[groups]]
+            name = "bpf"
+            workload = { type = "syscalls", arrival_rate = 100 }
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec!["groups[0].name: duplicated group name 'bpf'"]
+        );
+    }
+
+    #[test]
+    fn test_scenario() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [[scenario]]
+            duration = 10
+            scale = -1.0
+
+            [[scenario]]
+            duration = 10
+            connections_dyn_max = 0
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "scenario[0].scale: rate must be a positive number, got -1",
+                "scenario[1].connections_dyn_max: at least one dynamic \
+                 connection needed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [target]
+            interval = 0
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "target: exactly one of pid or comm has to be specified",
+                "target.interval: sampling interval must be positive",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_results() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [results]
+            tag_paths = true
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "results.tag_paths: tags are embedded only into logged \
+                 events, events log needed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        let input = r#"
+            version = 1
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+        "#;
+
+        assert_eq!(
+            errors(input),
+            vec![
+                "version: version 1 rates of syscalls and io_uring are per \
+                 millisecond, multiply them by 1000 and set version 2",
+            ]
+        );
+
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            arrival_rate = 100
+
+            [[groups]]
+            name = "endpoints"
+            workload = { type = "endpoints", distribution = "uniform", lower = 1, upper = 10 }
+
+            [[groups]]
+            name = "uring"
+            workload = { type = "iouring", arrival_rate = 100 }
+        "#;
+
+        let found: Vec<String> = warnings(&testing::config(input))
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                "workload.arrival_rate: rate is per second since version 2, \
+                 set version = 2 if it's already converted",
+                "groups[1].workload.arrival_rate: rate is per second since \
+                 version 2, set version = 2 if it's already converted",
+            ]
+        );
+
+        let input = format!("version = 2\n{input}");
+        assert!(warnings(&testing::config(&input)).is_empty());
+    }
+
+    #[test]
+    fn test_valid() {
+        let input = r#"
+            restart_interval = 10
+
+            [placement]
+            cpus = "0-7,12"
+            reserved = "0"
+            strategy = "avoid-core-0"
+
+            [scheduling]
+            nice = 10
+            policy = "idle"
+            io_class = "idle"
+
+            [cgroup]
+            naming = "kubepods-systemd"
+            count = 10
+            memory_max = "512M"
+            pids_max = 100
+
+            [namespaces]
+            unshare = ["pid", "mnt", "net", "uts", "ipc", "user"]
+            hostname = "pod-{process}"
+            rootfs = "/var/lib/berserker/rootfs"
+
+            [workload]
+            type = "network"
+            server = true
+            address = "10.0.0.1"
+            target_port = 8081
+            connections_static = 10
+            connections_dyn_max = 100
+            preempt = false
+        "#;
+
+        assert!(errors(input).is_empty());
+    }
+}
@@ -0,0 +1,515 @@
+//! Detection accuracy of a monitor. The ground truth event log written by a
+//! run is matched against a JSON lines export of events the monitor has
+//! reported. Every generated event seen by the monitor is matched, others
+//! are missed, and reported events without a generated counterpart are
+//! spurious. How to read the export is described by a field mapping, since
+//! every monitor has its own format, e.g. for Falco JSON output:
+//!
+//! ```toml
+//! timestamp = "output_fields.evt.rawtime"
+//!
+//! [[kinds]]
+//! kind = "process"
+//! filter = { "output_fields.evt.type" = "execve" }
+//! fields = { tag = "output_fields.proc.cmdline" }
+//! ```
+//!
+//! Everything works on files only, so that the same export could be checked
+//! again with a different mapping.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::events::Event;
+use crate::histogram::{HistogramSnapshot, format_nanos};
+
+/// Event kinds of the ground truth log.
+const KINDS: &[&str] = &[
+    "syscall",
+    "io_uring",
+    "process",
+    "connection",
+    "listen",
+    "bpf_prog",
+];
+
+/// Unit of the timestamps reported by a monitor, all of them are counted
+/// since Unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl TimeUnit {
+    /// Nanoseconds since Unix epoch from a number or a numeric string.
+    /// Integers are converted exactly, nanosecond timestamps do not fit
+    /// into a float.
+    fn nanos(&self, value: &Value) -> Option<u64> {
+        let scale: u64 = match self {
+            TimeUnit::Ns => 1,
+            TimeUnit::Us => 1_000,
+            TimeUnit::Ms => 1_000_000,
+            TimeUnit::S => 1_000_000_000,
+        };
+
+        let text = text(value);
+        match text.parse::<u64>() {
+            Ok(value) => value.checked_mul(scale),
+            Err(_) => text
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .map(|value| (value * scale as f64) as u64),
+        }
+    }
+}
+
+/// How to read events of one kind from the monitor export.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KindMapping {
+    /// Event kind of the ground truth, e.g. `process`.
+    pub kind: String,
+
+    /// Values the monitor record must have to be of this kind, by field.
+    #[serde(default)]
+    pub filter: BTreeMap<String, Value>,
+
+    /// Fields of the monitor record by ground truth field, values of which
+    /// must be equal for the events to match. The `tag` is looked up among
+    /// the words of the field separated by whitespace or slashes instead,
+    /// e.g. in the command line of a process or in a file path.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// Field of the monitor record with the event time. Without it the
+    /// detection latency is not known, and events are matched in order.
+    #[serde(default)]
+    pub timestamp: Option<String>,
+
+    #[serde(default)]
+    pub timestamp_unit: TimeUnit,
+
+    /// How late an event could be reported in milliseconds, or how early
+    /// because of clock differences.
+    #[serde(default = "default_window")]
+    pub window: u64,
+
+    pub kinds: Vec<KindMapping>,
+}
+
+fn default_window() -> u64 {
+    5000
+}
+
+impl Default for Mapping {
+    /// Mapping for an export in the format of the ground truth itself.
+    fn default() -> Self {
+        Mapping {
+            timestamp: Some(String::from("timestamp")),
+            timestamp_unit: TimeUnit::Ns,
+            window: default_window(),
+            kinds: KINDS
+                .iter()
+                .map(|kind| KindMapping {
+                    kind: kind.to_string(),
+                    filter: BTreeMap::from([(
+                        String::from("kind"),
+                        Value::from(*kind),
+                    )]),
+                    fields: BTreeMap::from([(
+                        String::from("tag"),
+                        String::from("tag"),
+                    )]),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> io::Result<Mapping> {
+        let content = fs::read_to_string(path)?;
+        let mapping: Mapping = toml::from_str(&content).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })?;
+
+        if let Some(kind) = mapping
+            .kinds
+            .iter()
+            .find(|k| !KINDS.contains(&k.kind.as_str()))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown event kind '{}'", kind.kind),
+            ));
+        }
+
+        Ok(mapping)
+    }
+}
+
+/// Read a JSON lines export of a monitor.
+pub fn load_observed(path: &str) -> io::Result<Vec<Value>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+
+    for (i, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        records.push(serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path}:{}: {e}", i + 1),
+            )
+        })?);
+    }
+
+    Ok(records)
+}
+
+/// Value of a field by its dotted path. Keys could contain dots on their
+/// own, e.g. `output_fields.proc.pid` is found in
+/// `{"output_fields": {"proc.pid": 1}}`.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let object = value.as_object()?;
+
+    if let Some(value) = object.get(path) {
+        return Some(value);
+    }
+
+    path.match_indices('.')
+        .find_map(|(i, _)| lookup(object.get(&path[..i])?, &path[i + 1..]))
+}
+
+/// Comparable text of a value: numbers and strings are compared the same,
+/// arrays are joined by spaces.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(values) => {
+            values.iter().map(text).collect::<Vec<_>>().join(" ")
+        }
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Results for one event kind.
+#[derive(Debug, Clone, Default)]
+pub struct KindResult {
+    pub kind: String,
+    pub generated: u64,
+    pub matched: u64,
+    pub missed: u64,
+    pub spurious: u64,
+
+    /// Time from generating an event until it was reported.
+    pub latency: HistogramSnapshot,
+}
+
+impl KindResult {
+    /// Share of generated events the monitor has missed.
+    pub fn loss(&self) -> f64 {
+        if self.generated > 0 {
+            self.missed as f64 / self.generated as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    pub kinds: Vec<KindResult>,
+}
+
+/// A monitor record of a known kind.
+#[derive(Clone)]
+struct Observed<'a> {
+    record: &'a Value,
+    timestamp: Option<u64>,
+}
+
+/// Match generated events of every mapped kind with the monitor records.
+/// Records are taken in order of time, and every one is matched with the
+/// earliest generated event having the same mapped fields within the time
+/// window. Records outside of the run time range are ignored.
+pub fn verify(
+    truth: &[Event],
+    observed: &[Value],
+    mapping: &Mapping,
+) -> Verification {
+    let window = mapping.window * 1_000_000;
+    let first = truth.iter().map(|e| e.timestamp).min().unwrap_or_default();
+    let last = truth.iter().map(|e| e.timestamp).max().unwrap_or_default();
+
+    let timestamp = |record: &Value| {
+        let path = mapping.timestamp.as_ref()?;
+        mapping.timestamp_unit.nanos(lookup(record, path)?)
+    };
+
+    // Every record belongs to the first kind with a matching filter
+    let mut by_kind: Vec<Vec<Observed>> = vec![vec![]; mapping.kinds.len()];
+
+    for record in observed {
+        let Some(index) = mapping.kinds.iter().position(|kind| {
+            kind.filter.iter().all(|(path, expected)| {
+                lookup(record, path).is_some_and(|v| text(v) == text(expected))
+            })
+        }) else {
+            continue;
+        };
+
+        let timestamp = timestamp(record);
+        if timestamp.is_some_and(|t| {
+            t + window < first || t > last.saturating_add(window)
+        }) {
+            continue;
+        }
+
+        by_kind[index].push(Observed { record, timestamp });
+    }
+
+    let kinds = mapping
+        .kinds
+        .iter()
+        .zip(by_kind)
+        .map(|(kind, mut observed)| {
+            // Without timestamps the export order is kept
+            observed.sort_by_key(|o| o.timestamp);
+            verify_kind(truth, observed, kind, window)
+        })
+        .collect();
+
+    Verification { kinds }
+}
+
+fn verify_kind(
+    truth: &[Event],
+    observed: Vec<Observed>,
+    mapping: &KindMapping,
+    window: u64,
+) -> KindResult {
+    let events: Vec<(&Event, Value)> = truth
+        .iter()
+        .map(|e| (e, serde_json::to_value(e).unwrap_or_default()))
+        .filter(|(_, value)| {
+            value.get("kind").and_then(Value::as_str)
+                == Some(mapping.kind.as_str())
+        })
+        .collect();
+
+    let mut result = KindResult {
+        kind: mapping.kind.clone(),
+        generated: events.len() as u64,
+        ..KindResult::default()
+    };
+
+    // Fields compared for equality, and where to find them in the record
+    let (fields, paths): (Vec<&str>, Vec<&str>) = mapping
+        .fields
+        .iter()
+        .filter(|(field, _)| *field != "tag")
+        .map(|(field, path)| (field.as_str(), path.as_str()))
+        .unzip();
+    let tag_path = mapping.fields.get("tag");
+
+    let keys: Vec<Vec<String>> = events
+        .iter()
+        .map(|(_, value)| key(value, &fields))
+        .collect();
+
+    // Unmatched generated events in order of time by the mapped fields
+    let mut pending: HashMap<&[String], VecDeque<usize>> = HashMap::new();
+    // Tags like the 5-tuple of a connection could be reused
+    let mut tags: HashMap<&str, Vec<usize>> = HashMap::new();
+
+    for (i, (event, _)) in events.iter().enumerate() {
+        pending.entry(&keys[i]).or_default().push_back(i);
+        tags.entry(event.tag.as_str()).or_default().push(i);
+    }
+
+    let mut matched = vec![false; events.len()];
+    let within = |i: usize, timestamp: Option<u64>| {
+        timestamp.is_none_or(|t| t.abs_diff(events[i].0.timestamp) <= window)
+    };
+
+    for Observed { record, timestamp } in observed {
+        let key = key(record, &paths);
+
+        let found = match tag_path {
+            Some(path) => lookup(record, path)
+                .map(text)
+                .unwrap_or_default()
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .filter_map(|word| tags.get(word))
+                .flatten()
+                .copied()
+                .find(|i| {
+                    !matched[*i] && keys[*i] == key && within(*i, timestamp)
+                }),
+            None => pending.get_mut(key.as_slice()).and_then(|queue| {
+                // Events too old for this record are too old for all the
+                // following ones as well
+                while let Some(&i) = queue.front()
+                    && timestamp.is_some_and(|t| {
+                        events[i].0.timestamp.saturating_add(window) < t
+                    })
+                {
+                    queue.pop_front();
+                }
+
+                let i = queue.front().copied()?;
+                within(i, timestamp).then(|| queue.pop_front())?
+            }),
+        };
+
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                result.matched += 1;
+
+                // Clocks could differ, an early report counts as instant
+                if let Some(t) = timestamp {
+                    result
+                        .latency
+                        .record(t.saturating_sub(events[i].0.timestamp));
+                }
+            }
+            None => result.spurious += 1,
+        }
+    }
+
+    result.missed = result.generated - result.matched;
+    result
+}
+
+/// Values of the fields to compare.
+fn key(value: &Value, paths: &[&str]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| lookup(value, path).map(text).unwrap_or_default())
+        .collect()
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>10} {:>10} {:>10} {:>8}",
+            "Kind", "Generated", "Matched", "Missed", "Spurious", "Loss"
+        )?;
+
+        for kind in self.kinds.iter() {
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>10} {:>10} {:>10} {:>7.2}%",
+                kind.kind,
+                kind.generated,
+                kind.matched,
+                kind.missed,
+                kind.spurious,
+                kind.loss() * 100.0
+            )?;
+
+            if let Some(p) = kind.latency.percentiles() {
+                writeln!(
+                    f,
+                    "  latency: p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+                    format_nanos(p.p50),
+                    format_nanos(p.p90),
+                    format_nanos(p.p99),
+                    format_nanos(p.p999),
+                    format_nanos(p.max)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events;
+
+    fn fixture(name: &str) -> String {
+        format!(
+            "{}/tests/fixtures/verify/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        )
+    }
+
+    #[test]
+    fn test_verify() {
+        let truth = events::load(&fixture("events.jsonl")).unwrap();
+        let observed = load_observed(&fixture("falco.jsonl")).unwrap();
+        let mapping = Mapping::load(&fixture("falco.toml")).unwrap();
+
+        let verification = verify(&truth, &observed, &mapping);
+        let results: Vec<_> = verification
+            .kinds
+            .iter()
+            .map(|k| {
+                (
+                    k.kind.as_str(),
+                    k.generated,
+                    k.matched,
+                    k.missed,
+                    k.spurious,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                ("process", 3, 2, 1, 1),
+                ("connection", 2, 1, 1, 0),
+                ("syscall", 4, 3, 1, 1),
+                ("io_uring", 1, 1, 0, 0),
+            ]
+        );
+
+        // Reported 2ms and 4ms after the processes were spawned
+        let process = &verification.kinds[0];
+        assert_eq!(process.latency.count(), 2);
+        assert_eq!(process.latency.percentiles().unwrap().max, 4_000_000);
+
+        // The export in the ground truth format matches completely
+        let export: Vec<_> = truth
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect();
+        let verification = verify(&truth, &export, &Mapping::default());
+        assert!(
+            verification
+                .kinds
+                .iter()
+                .all(|k| k.matched == k.generated && k.spurious == 0)
+        );
+
+        let nested = serde_json::json!({"a": {"b.c": {"d": 1}}});
+        assert_eq!(lookup(&nested, "a.b.c.d"), Some(&Value::from(1)));
+        assert_eq!(lookup(&nested, "a.b"), None);
+    }
+}
@@ -15,7 +15,12 @@ use aya_obj::generated::{
     perf_event_sample_format, perf_type_id,
 };
 
-use crate::{BaseConfig, Worker, WorkerError, Workload, WorkloadConfig};
+use crate::{
+    BaseConfig, Worker, WorkerError, Workload, WorkloadConfig,
+    events::{self, EventKind},
+    probes,
+    stats::WorkerStats,
+};
 
 #[derive(Debug, Clone)]
 pub struct BpfWorker {
@@ -24,9 +29,18 @@ pub struct BpfWorker {
 }
 
 impl BpfWorker {
-    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        stats: &'static WorkerStats,
+    ) -> Self {
         BpfWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload,
         }
     }
@@ -36,7 +50,7 @@ impl Worker for BpfWorker {
     fn run_payload(&self) -> Result<(), WorkerError> {
         info!("{self}");
 
-        let Workload::Bpf { nprogs, tracepoint } = self.workload.workload
+        let Some(Workload::Bpf { nprogs, tracepoint }) = self.workload.workload
         else {
             unreachable!()
         };
@@ -98,6 +112,25 @@ impl Worker for BpfWorker {
                 );
             }
 
+            let ret = if prog_fd >= 0 {
+                self.config.stats.success();
+                prog_fd
+            } else {
+                let errno = nix::errno::errno();
+                self.config.stats.failure(errno);
+                -(errno as i64)
+            };
+
+            probes::bpf_prog_loaded(
+                self.config.cpu.id,
+                self.config.process,
+                ret,
+            );
+            events::record(EventKind::BpfProg {
+                name: cstring.to_string_lossy().into_owned(),
+                ret,
+            });
+
             // Now prepare a tracepoint event the bpf program
             // will be attached to
             unsafe {
@@ -3,7 +3,11 @@ use std::{fmt::Display, net::TcpListener, thread, time};
 use core_affinity::CoreId;
 use log::info;
 
-use crate::{BaseConfig, Worker, WorkerError, WorkloadConfig};
+use crate::{
+    BaseConfig, Worker, WorkerError, WorkloadConfig,
+    events::{self, EventKind},
+    stats::WorkerStats,
+};
 
 struct EndpointWorkload {
     restart_interval: u64,
@@ -21,19 +25,40 @@ impl EndpointWorker {
         workload: WorkloadConfig,
         cpu: CoreId,
         process: usize,
+        stats: &'static WorkerStats,
         lower: usize,
         upper: usize,
     ) -> Self {
         let WorkloadConfig {
+            version: _,
             restart_interval,
             workload: _,
             per_core: _,
             workers: _,
+            placement: _,
+            scheduling: _,
+            cgroup: _,
+            namespaces: _,
             duration: _,
+            groups: _,
+            scenario: _,
+            restart: _,
+            metrics: _,
+            results: _,
+            target: _,
+            control: _,
+            seed: _,
+            schedule: _,
+            start: _,
+            runtime: _,
         } = workload;
 
         EndpointWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload: EndpointWorkload {
                 restart_interval,
                 lower,
@@ -47,6 +72,7 @@ impl Worker for EndpointWorker {
     fn run_payload(&self) -> Result<(), WorkerError> {
         info!("{self}");
 
+        let stats = self.config.stats;
         let EndpointWorkload {
             restart_interval,
             lower,
@@ -54,7 +80,9 @@ impl Worker for EndpointWorker {
         } = self.workload;
 
         let listeners: Vec<_> = (lower..upper)
-            .map(|port| thread::spawn(move || listen(port, restart_interval)))
+            .map(|port| {
+                thread::spawn(move || listen(port, restart_interval, stats))
+            })
             .collect();
 
         for listener in listeners {
@@ -71,12 +99,29 @@ impl Display for EndpointWorker {
     }
 }
 
-fn listen(port: usize, sleep: u64) -> std::io::Result<()> {
+fn listen(
+    port: usize,
+    sleep: u64,
+    stats: &'static WorkerStats,
+) -> std::io::Result<()> {
     let addr = format!("0.0.0.0:{port}");
-    let listener = TcpListener::bind(addr)?;
+    let listener = TcpListener::bind(addr).inspect_err(|e| {
+        let errno = e.raw_os_error().unwrap_or(0);
+        stats.failure(errno);
+        events::record(EventKind::Listen {
+            port: port as u16,
+            ret: -(errno as i64),
+        });
+    })?;
+    stats.success();
+    events::record(EventKind::Listen {
+        port: port as u16,
+        ret: 0,
+    });
 
     let _res = listener.incoming();
 
     thread::sleep(time::Duration::from_secs(sleep));
+    stats.close();
     Ok(())
 }
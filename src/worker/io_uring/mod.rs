@@ -3,17 +3,21 @@ mod openat2;
 mod statx;
 mod unlinkat;
 
-use std::{collections::HashMap, fmt::Display, str::FromStr, time::Instant};
+use std::{
+    borrow::Cow, collections::HashMap, fmt::Display, str::FromStr,
+    time::Instant,
+};
 
 use core_affinity::CoreId;
 use enum_dispatch::enum_dispatch;
 use log::{debug, info, trace};
-use rand::{Rng, thread_rng};
-use rand_distr::Exp;
 use syscalls::Errno;
 
 use crate::{
     BaseConfig, Worker, WorkerError, Workload, WorkloadConfig,
+    events::{self, EventKind},
+    pacing::Pacer,
+    stats::WorkerStats,
     worker::io_uring::{
         openat::OpenatIOUringCall, openat2::Openat2IOUringCall,
         statx::StatxIOUringCall, unlinkat::UnlinkatIOUringCall,
@@ -27,9 +31,18 @@ pub struct IOUringWorker {
 }
 
 impl IOUringWorker {
-    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        stats: &'static WorkerStats,
+    ) -> Self {
         IOUringWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload,
         }
     }
@@ -42,11 +55,12 @@ impl Worker for IOUringWorker {
         let mut counter = 0;
         let mut start = Instant::now();
 
-        let Workload::IOUring {
+        let Some(Workload::IOUring {
             arrival_rate,
+            pacing,
             iouring_nr,
             iouring_args,
-        } = &self.workload.workload
+        }) = &self.workload.workload
         else {
             unreachable!()
         };
@@ -60,12 +74,32 @@ impl Worker for IOUringWorker {
         };
         let mut ring = io_uring::IoUring::new(1).unwrap();
 
-        let exp = Exp::new(*arrival_rate).unwrap();
-        let rng = thread_rng();
-        let mut rng_iter = rng.sample_iter(exp);
+        let rng = self.workload.rng(self.config.cpu.id, self.config.process);
+        let mut pacer = Pacer::new(
+            &self.workload,
+            *arrival_rate,
+            pacing,
+            rng,
+            self.config.stats,
+        )
+        .map_err(|e| {
+            WorkerError::InternalWithMessage(format!(
+                "Cannot set up pacing: {e}"
+            ))
+        })?;
+
+        // Name of the operation for the event log
+        let op = SUPPORTED
+            .iter()
+            .find(|(code, _)| code == iouring_nr)
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown");
 
         info!("Running iouring {iouring_nr}");
 
+        // Intended start times of requests due
+        let mut due = vec![];
+
         loop {
             if start.elapsed().as_secs() > 10 {
                 info!(
@@ -77,37 +111,61 @@ impl Worker for IOUringWorker {
                 counter = 0;
             }
 
-            counter += 1;
-            // Do the iouring directly, without spawning a thread (it would
-            // introduce too much overhead for a quick iouring).
-            match caller.submit(&mut ring) {
-                Ok(_) => trace!(
-                    "{}-{}: Success",
-                    self.config.cpu.id, self.config.process
-                ),
-                Err(e) => debug!(
-                    "{}-{}: Error: {:?}",
-                    self.config.cpu.id, self.config.process, e
-                ),
+            // Wait until the next request is due, the rate might be changed
+            // by the current scenario phase or schedule. If the worker is
+            // late, few requests are due at once.
+            pacer.wait(&mut due).map_err(|e| {
+                WorkerError::InternalWithMessage(format!("Pacing failed: {e}"))
+            })?;
+
+            for start in due.iter() {
+                counter += 1;
+                let tag = next_tag(&mut caller);
+
+                // Do the iouring directly, without spawning a thread (it
+                // would introduce too much overhead for a quick iouring).
+                let result = caller.submit(&mut ring);
+                self.config.stats.record(&result);
+                self.config.stats.latency(start.elapsed());
+                if let Some(tag) = tag {
+                    events::record_tagged(
+                        tag,
+                        EventKind::IOUring {
+                            op: Cow::Borrowed(op),
+                            ret: match result {
+                                Ok(ret) => ret as i64,
+                                Err(e) => -(e.into_raw() as i64),
+                            },
+                        },
+                    );
+                }
+                match result {
+                    Ok(_) => trace!(
+                        "{}-{}: Success",
+                        self.config.cpu.id, self.config.process
+                    ),
+                    Err(e) => debug!(
+                        "{}-{}: Error: {:?}",
+                        self.config.cpu.id, self.config.process, e
+                    ),
+                }
             }
-
-            // Otherwise calculate waiting time
-            let interval: f64 = rng_iter.next().unwrap();
-            trace!(
-                "{}-{}: Interval {}, rounded {}",
-                self.config.cpu.id,
-                self.config.process,
-                interval,
-                (interval * 1000000.0).round() as u64
-            );
-            std::thread::sleep(std::time::Duration::from_nanos(
-                (interval * 1000000.0).round() as u64,
-            ));
-            trace!("{}-{}: Continue", self.config.cpu.id, self.config.process);
         }
     }
 }
 
+/// Correlation tag for the next request, if events are logged. Like for
+/// syscalls, the request gets it only if file names have to be tagged.
+fn next_tag(caller: &mut IOUringCallerEnum) -> Option<String> {
+    let tag = events::tag();
+    if let Some(tag) = &tag
+        && events::tag_paths()
+    {
+        caller.tag(tag);
+    }
+    tag
+}
+
 impl Display for IOUringWorker {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.config)
@@ -129,9 +187,21 @@ trait IOUringCaller {
     fn init(&mut self) -> std::io::Result<usize> {
         Ok(0)
     }
+
+    /// Embed the correlation tag into the next request, into the file name.
+    fn tag(&mut self, tag: &str);
+
     fn submit(&self, ring: &mut io_uring::IoUring) -> Result<usize, Errno>;
 }
 
+/// Supported io_uring operations, with their names.
+pub const SUPPORTED: &[(u8, &str)] = &[
+    (io_uring::opcode::OpenAt::CODE, "openat"),
+    (io_uring::opcode::OpenAt2::CODE, "openat2"),
+    (io_uring::opcode::Statx::CODE, "statx"),
+    (io_uring::opcode::UnlinkAt::CODE, "unlinkat"),
+];
+
 fn new_iouring_generator(
     iouring_nr: u8,
     iouring_args: &HashMap<String, String>,
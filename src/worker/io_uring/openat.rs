@@ -8,6 +8,7 @@ use io_uring::squeue::Entry;
 use io_uring::{IoUring, types};
 use syscalls::Errno;
 
+use crate::events;
 use crate::worker::io_uring::IOUringCaller;
 use crate::worker::io_uring::get_argument;
 
@@ -17,6 +18,9 @@ pub(super) struct OpenatIOUringCall {
     openat: Entry,
 
     pathname: CString, // used a raw pointer from string
+    base: CString,     // pathname from the arguments, to embed the tag into
+    flags: i32,
+    mode: u32,
 }
 
 impl OpenatIOUringCall {
@@ -28,15 +32,30 @@ impl OpenatIOUringCall {
         );
         let flags = get_argument(openat_args, "flags", 0);
         let mode = get_argument(openat_args, "mode", 0);
-        let openat = OpenAt::new(types::Fd(-1), pathname.as_ptr())
+        let openat = Self::entry(&pathname, flags, mode);
+        Self {
+            openat,
+            base: pathname.clone(),
+            pathname,
+            flags,
+            mode,
+        }
+    }
+
+    fn entry(pathname: &CString, flags: i32, mode: u32) -> Entry {
+        OpenAt::new(types::Fd(-1), pathname.as_ptr())
             .flags(flags)
             .mode(mode)
-            .build();
-        Self { openat, pathname }
+            .build()
     }
 }
 
 impl IOUringCaller for OpenatIOUringCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+        self.openat = Self::entry(&self.pathname, self.flags, self.mode);
+    }
+
     fn submit(&self, ring: &mut IoUring) -> Result<usize, Errno> {
         unsafe {
             ring.submission()
@@ -49,6 +68,10 @@ impl IOUringCaller for OpenatIOUringCall {
         if cqe.result() > -1 {
             // Close file descriptor
             unsafe { File::from_raw_fd(cqe.result()) };
+
+            if self.flags & libc::O_CREAT != 0 {
+                events::remove_tagged(&self.base, &self.pathname);
+            }
             return Ok(cqe.result() as usize);
         }
         Err(Errno::new(-cqe.result()))
@@ -8,6 +8,7 @@ use io_uring::squeue::Entry;
 use io_uring::{IoUring, types};
 use syscalls::Errno;
 
+use crate::events;
 use crate::worker::io_uring::IOUringCaller;
 use crate::worker::io_uring::get_argument;
 
@@ -17,6 +18,8 @@ pub(super) struct Openat2IOUringCall {
     openat: Entry,
 
     pathname: CString, // used a raw pointer from string
+    base: CString,     // pathname from the arguments, to embed the tag into
+    flags: u64,
     openhow: Box<types::OpenHow>,
 }
 
@@ -37,18 +40,27 @@ impl Openat2IOUringCall {
                 .resolve(resolve),
         );
 
-        let openat =
-            OpenAt2::new(types::Fd(-1), pathname.as_ptr(), openhow.as_ref())
-                .build();
+        let openat = Self::entry(&pathname, &openhow);
         Self {
             openat,
+            base: pathname.clone(),
             pathname,
+            flags,
             openhow,
         }
     }
+
+    fn entry(pathname: &CString, openhow: &types::OpenHow) -> Entry {
+        OpenAt2::new(types::Fd(-1), pathname.as_ptr(), openhow).build()
+    }
 }
 
 impl IOUringCaller for Openat2IOUringCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+        self.openat = Self::entry(&self.pathname, &self.openhow);
+    }
+
     fn submit(&self, ring: &mut IoUring) -> Result<usize, Errno> {
         unsafe {
             ring.submission()
@@ -61,6 +73,10 @@ impl IOUringCaller for Openat2IOUringCall {
         if cqe.result() > -1 {
             // Close file descriptor
             unsafe { File::from_raw_fd(cqe.result()) };
+
+            if self.flags & libc::O_CREAT as u64 != 0 {
+                events::remove_tagged(&self.base, &self.pathname);
+            }
             return Ok(cqe.result() as usize);
         }
         Err(Errno::new(-cqe.result()))
@@ -6,6 +6,7 @@ use io_uring::squeue::Entry;
 use io_uring::{IoUring, types};
 use syscalls::Errno;
 
+use crate::events;
 use crate::worker::io_uring::IOUringCaller;
 use crate::worker::io_uring::get_argument;
 
@@ -15,6 +16,9 @@ pub(super) struct StatxIOUringCall {
     statx: Entry,
 
     pathname: CString, // used a raw pointer from string
+    base: CString,     // pathname from the arguments, to embed the tag into
+    flags: i32,
+    mask: u32,
     statx_struct: Box<libc::statx>, // used as a mutable raw pointer
 }
 
@@ -30,23 +34,45 @@ impl StatxIOUringCall {
         let mut statx_struct: Box<libc::statx> =
             Box::new(unsafe { std::mem::zeroed() });
 
-        let statx = Statx::new(
-            types::Fd(-1),
-            pathname.as_ptr(),
-            statx_struct.as_mut() as *mut libc::statx as *mut _,
-        )
-        .flags(flags)
-        .mask(mask)
-        .build();
+        let statx = Self::entry(&pathname, flags, mask, &mut statx_struct);
         Self {
             statx,
+            base: pathname.clone(),
             pathname,
+            flags,
+            mask,
             statx_struct,
         }
     }
+
+    fn entry(
+        pathname: &CString,
+        flags: i32,
+        mask: u32,
+        statx_struct: &mut libc::statx,
+    ) -> Entry {
+        Statx::new(
+            types::Fd(-1),
+            pathname.as_ptr(),
+            statx_struct as *mut libc::statx as *mut _,
+        )
+        .flags(flags)
+        .mask(mask)
+        .build()
+    }
 }
 
 impl IOUringCaller for StatxIOUringCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+        self.statx = Self::entry(
+            &self.pathname,
+            self.flags,
+            self.mask,
+            &mut self.statx_struct,
+        );
+    }
+
     fn submit(&self, ring: &mut IoUring) -> Result<usize, Errno> {
         unsafe {
             ring.submission()
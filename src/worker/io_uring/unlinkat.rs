@@ -6,6 +6,7 @@ use io_uring::squeue::Entry;
 use io_uring::{IoUring, types};
 use syscalls::Errno;
 
+use crate::events;
 use crate::worker::io_uring::IOUringCaller;
 use crate::worker::io_uring::get_argument;
 
@@ -15,6 +16,8 @@ pub(super) struct UnlinkatIOUringCall {
     unlinkat: Entry,
 
     pathname: CString, // used a raw pointer from string
+    base: CString,     // pathname from the arguments, to embed the tag into
+    flags: i32,
 }
 
 impl UnlinkatIOUringCall {
@@ -26,14 +29,28 @@ impl UnlinkatIOUringCall {
         );
         let flags = get_argument(unlinkat_args, "flags", 0);
 
-        let unlinkat = UnlinkAt::new(types::Fd(-1), pathname.as_ptr())
+        let unlinkat = Self::entry(&pathname, flags);
+        Self {
+            unlinkat,
+            base: pathname.clone(),
+            pathname,
+            flags,
+        }
+    }
+
+    fn entry(pathname: &CString, flags: i32) -> Entry {
+        UnlinkAt::new(types::Fd(-1), pathname.as_ptr())
             .flags(flags)
-            .build();
-        Self { unlinkat, pathname }
+            .build()
     }
 }
 
 impl IOUringCaller for UnlinkatIOUringCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+        self.unlinkat = Self::entry(&self.pathname, self.flags);
+    }
+
     fn submit(&self, ring: &mut IoUring) -> Result<usize, Errno> {
         unsafe {
             ring.submission()
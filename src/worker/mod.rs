@@ -1,9 +1,8 @@
 use core_affinity::CoreId;
-use rand::{Rng, thread_rng};
-use rand_distr::{Uniform, Zipf};
+use rand::Rng;
 
 use crate::{
-    Distribution, Worker, Workload, WorkloadConfig,
+    Worker, Workload, WorkloadConfig, stats::WorkerStats,
     worker::io_uring::IOUringWorker,
 };
 
@@ -23,50 +22,50 @@ pub fn new_worker(
     workload: WorkloadConfig,
     cpu: CoreId,
     process: usize,
+    stats: &'static WorkerStats,
     lower_bound: &mut usize,
     upper_bound: &mut usize,
 ) -> Box<dyn Worker> {
-    match workload.workload {
+    let Some(kind) = &workload.workload else {
+        unreachable!()
+    };
+
+    match *kind {
         Workload::Processes { .. } => {
-            Box::new(ProcessesWorker::new(workload, cpu, process))
+            Box::new(ProcessesWorker::new(workload, cpu, process, stats))
         }
-        Workload::Endpoints { distribution } => {
-            match distribution {
-                Distribution::Zipfian { n_ports, exponent } => {
-                    let n_ports: f64 = thread_rng()
-                        .sample(Zipf::new(n_ports, exponent).unwrap());
+        Workload::Endpoints { ref distribution } => {
+            // The configuration is validated at this point, including
+            // availability of the empirical histogram
+            let sampler =
+                distribution.sampler().expect("invalid ports distribution");
+
+            // Negative samples are saturated to zero ports
+            let n_ports = workload.rng(cpu.id, process).sample(sampler);
 
-                    *lower_bound = *upper_bound;
-                    *upper_bound += n_ports as usize;
-                }
-                Distribution::Uniform { lower, upper } => {
-                    // TODO: Double check this branch
-                    let n_ports =
-                        thread_rng().sample(Uniform::new(lower, upper));
+            *lower_bound = *upper_bound;
+            *upper_bound += n_ports as usize;
 
-                    *lower_bound = *upper_bound;
-                    *upper_bound += n_ports as usize;
-                }
-            }
             Box::new(EndpointWorker::new(
                 workload,
                 cpu,
                 process,
+                stats,
                 *lower_bound,
                 *upper_bound,
             ))
         }
         Workload::Syscalls { .. } => {
-            Box::new(SyscallsWorker::new(workload, cpu, process))
+            Box::new(SyscallsWorker::new(workload, cpu, process, stats))
         }
         Workload::Network { .. } => {
-            Box::new(NetworkWorker::new(workload, cpu, process))
+            Box::new(NetworkWorker::new(workload, cpu, process, stats))
         }
         Workload::Bpf { .. } => {
-            Box::new(BpfWorker::new(workload, cpu, process))
+            Box::new(BpfWorker::new(workload, cpu, process, stats))
         }
         Workload::IOUring { .. } => {
-            Box::new(IOUringWorker::new(workload, cpu, process))
+            Box::new(IOUringWorker::new(workload, cpu, process, stats))
         }
     }
 }
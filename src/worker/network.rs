@@ -1,11 +1,12 @@
 use core_affinity::CoreId;
 use log::{debug, info, trace};
-use rand::{Rng, thread_rng};
+use rand::{Rng, rngs::StdRng};
 use rand_distr::Exp;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::os::unix::io::AsRawFd;
 use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime};
 use std::{
     fmt::Display,
     io::{BufReader, prelude::*},
@@ -13,7 +14,13 @@ use std::{
     thread,
 };
 
-use crate::{BaseConfig, Worker, WorkerError, Workload, WorkloadConfig};
+use crate::{
+    BaseConfig, Distribution, Worker, WorkerError, Workload, WorkloadConfig,
+    events::{self, EventKind},
+    probes,
+    rate::Arrivals,
+    stats::WorkerStats,
+};
 
 use smoltcp::iface::{Config, Interface, SocketSet};
 use smoltcp::phy::{
@@ -30,9 +37,18 @@ pub struct NetworkWorker {
 }
 
 impl NetworkWorker {
-    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        stats: &'static WorkerStats,
+    ) -> Self {
         NetworkWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload,
         }
     }
@@ -47,8 +63,12 @@ impl NetworkWorker {
     ) -> Result<(), WorkerError> {
         debug!("Starting server at {:?}:{:?}", addr, target_port);
 
-        let listener =
-            TcpListener::bind((addr.to_string(), target_port)).unwrap();
+        let listener = TcpListener::bind((addr.to_string(), target_port))
+            .map_err(|e| {
+                WorkerError::InternalWithMessage(format!(
+                    "cannot listen on {addr}:{target_port}: {e}"
+                ))
+            })?;
 
         for stream in listener.incoming() {
             let mut stream = stream.unwrap();
@@ -96,30 +116,82 @@ impl NetworkWorker {
         Ok(())
     }
 
+    /// Count a newly opened connection and fire the corresponding probe.
+    fn connection_opened(
+        &self,
+        local: (IpAddress, u16),
+        remote: (Ipv4Address, u16),
+    ) {
+        self.config.stats.success();
+
+        #[allow(irrefutable_let_patterns)]
+        let IpAddress::Ipv4(local_addr) = local.0 else {
+            return;
+        };
+
+        probes::connection_opened(
+            self.config.cpu.id,
+            self.config.process,
+            (local_addr.to_bits(), local.1),
+            (remote.0.to_bits(), remote.1),
+        );
+
+        // Monitors tell connections apart by the 5-tuple, it's the tag in
+        // the form most of them report
+        if events::enabled() {
+            let tag =
+                format!("{local_addr}:{}->{}:{}", local.1, remote.0, remote.1);
+            events::record_tagged(
+                tag,
+                EventKind::Connection {
+                    protocol: Cow::Borrowed("tcp"),
+                    local_addr: local_addr.to_string(),
+                    local_port: local.1,
+                    remote_addr: remote.0.to_string(),
+                    remote_port: remote.1,
+                },
+            );
+        }
+    }
+
     fn start_client(
         &self,
         addr: Ipv4Address,
         target_port: u16,
     ) -> Result<(), WorkerError> {
-        let Workload::Network {
+        let Some(Workload::Network {
             server: _,
             address: _,
             target_port: _,
             arrival_rate,
             departure_rate,
+            ref lifetime,
             connections_static,
             connections_dyn_max,
             conns_per_addr,
             send_interval,
+            ref payload_size,
             preempt,
-        } = self.workload.workload
+        }) = self.workload.workload
         else {
             unreachable!()
         };
 
+        let sampler = |distribution: &Option<Distribution>| {
+            distribution
+                .as_ref()
+                .map(Distribution::sampler)
+                .transpose()
+                .map_err(|e| WorkerError::InternalWithMessage(e.to_string()))
+        };
+        let lifetime = sampler(lifetime)?;
+        let payload_size = sampler(payload_size)?;
+
         debug!("Starting client, target {:?}:{:?}", addr, target_port);
 
-        let (mut iface, mut device, fd) = self.setup_tuntap(addr);
+        let mut rng =
+            self.workload.rng(self.config.cpu.id, self.config.process);
+        let (mut iface, mut device, fd) = self.setup_tuntap(addr, &mut rng);
         let cx = iface.context();
 
         // Dynamic sockets are going to be responsible for connections that
@@ -129,6 +201,10 @@ impl NetworkWorker {
         // * connection lifetime
         let mut dynamic_sockets = HashMap::new();
 
+        // Sockets waiting for the connection to be established, with the
+        // time when the connection was supposed to be opened
+        let mut connecting = HashMap::new();
+
         // Open static set of connections, that are going to live throughout
         // the whole run
         let mut sockets = SocketSet::new(vec![]);
@@ -141,9 +217,9 @@ impl NetworkWorker {
             sockets.add(tcp_socket);
         }
 
-        for (i, socket) in sockets
+        for (i, (handle, socket)) in sockets
             .iter_mut()
-            .filter_map(|(_h, s)| tcp::Socket::downcast_mut(s))
+            .filter_map(|(h, s)| tcp::Socket::downcast_mut(s).map(|s| (h, s)))
             .enumerate()
         {
             let index = i as u32;
@@ -153,6 +229,11 @@ impl NetworkWorker {
             socket
                 .connect(cx, (addr, target_port), (local_addr, local_port))
                 .unwrap();
+            self.connection_opened(
+                (local_addr, local_port),
+                (addr, target_port),
+            );
+            connecting.insert(handle, SystemTime::now());
         }
 
         // Use global timer to throttle sending the data. It means there will
@@ -164,8 +245,8 @@ impl NetworkWorker {
 
         // Timer and waiting interval for the next new dynamic connection
         let mut arrivals = SystemTime::now();
-        let mut interval: f64 =
-            thread_rng().sample(Exp::new(arrival_rate).unwrap());
+        let mut schedule = Arrivals::new(&self.workload, arrival_rate);
+        let mut interval = schedule.next(&mut rng);
 
         // Current number of opened connections, both dynamic and static
         let mut total_conns = connections_static;
@@ -193,16 +274,31 @@ impl NetworkWorker {
                 let (local_addr, local_port) =
                     get_local_addr_port(addr, conns_per_addr, total_conns);
 
-                let lifetime: f64 =
-                    thread_rng().sample(Exp::new(departure_rate).unwrap());
-
-                // If we've reached the connections limit
-                if dynamic_sockets.len() == connections_dyn_max as usize
-                    && preempt
+                let lifetime: f64 = match &lifetime {
+                    Some(lifetime) => rng.sample(lifetime).max(0.0),
+                    None => rng.sample(
+                        Exp::new(self.workload.departure_rate(departure_rate))
+                            .unwrap(),
+                    ),
+                };
+
+                // The limit might be changed by the current scenario phase
+                let connections_dyn_max =
+                    self.workload.connections_dyn_max(connections_dyn_max);
+
+                // If we've reached the connections limit, preempt one to
+                // make room. If the limit was lowered by a scenario phase,
+                // connections above it are preempted as well.
+                while preempt
+                    && connections_dyn_max > 0
+                    && dynamic_sockets.len() >= connections_dyn_max as usize
                 {
-                    let idx =
-                        thread_rng().gen_range(0..connections_dyn_max as usize);
-                    let (key, _) = sockets.iter().nth(idx).unwrap();
+                    let idx = rng.gen_range(0..dynamic_sockets.len());
+                    let (key, _) = sockets
+                        .iter()
+                        .filter(|(h, _)| dynamic_sockets.contains_key(h))
+                        .nth(idx)
+                        .unwrap();
                     dynamic_sockets.remove(&key);
                     close_sockets.push(key);
                 }
@@ -217,10 +313,18 @@ impl NetworkWorker {
                             (local_addr, local_port),
                         )
                         .unwrap();
+                    self.connection_opened(
+                        (local_addr, local_port),
+                        (addr, target_port),
+                    );
 
                     let handle = sockets.add(socket);
                     dynamic_sockets
                         .insert(handle, (SystemTime::now(), lifetime));
+                    connecting.insert(
+                        handle,
+                        arrivals + Duration::from_secs_f64(interval),
+                    );
                 }
 
                 info!(
@@ -231,9 +335,10 @@ impl NetworkWorker {
                     index - 1
                 );
 
-                // set new interval for the next new connection
-                interval = thread_rng().sample(Exp::new(arrival_rate).unwrap());
-                arrivals = SystemTime::now();
+                // set new interval for the next new connection, counted from
+                // the intended arrival to not fall behind the schedule
+                arrivals += Duration::from_secs_f64(interval);
+                interval = schedule.next(&mut rng);
             }
 
             // Iterate through all sockets, update the state for each one
@@ -262,6 +367,14 @@ impl NetworkWorker {
                     }
                 }
 
+                if socket.state() == tcp::State::Established
+                    && let Some(intended) = connecting.remove(&h)
+                {
+                    self.config
+                        .stats
+                        .latency(intended.elapsed().unwrap_or_default());
+                }
+
                 if socket.can_recv() {
                     socket
                         .recv(|data| {
@@ -287,8 +400,17 @@ impl NetworkWorker {
                         // reset the timer
                         send_timer = SystemTime::now();
 
-                        let response = format!("hello {}\n", i);
-                        let binary = response.as_bytes();
+                        let response = match &payload_size {
+                            // Whatever doesn't fit into the socket buffer
+                            // is not sent
+                            Some(size) => {
+                                let size = (rng.sample(size).max(0.0) as usize)
+                                    .min(socket.send_capacity());
+                                vec![b'x'; size]
+                            }
+                            None => format!("hello {}\n", i).into_bytes(),
+                        };
+                        let binary = response.as_slice();
                         trace!(
                             "sending request from idx {} addr {}, data {:?}",
                             i,
@@ -304,6 +426,8 @@ impl NetworkWorker {
                 info!("Close handle {}", h);
                 // TODO: reuse sockets
                 sockets.remove(h);
+                connecting.remove(&h);
+                self.config.stats.close();
                 total_conns -= 1;
             }
 
@@ -330,15 +454,13 @@ impl NetworkWorker {
     fn setup_tuntap(
         &self,
         addr: Ipv4Address,
+        rng: &mut StdRng,
     ) -> (Interface, FaultInjector<Tracer<TunTapInterface>>, i32) {
         let device_name = "berserker0";
         let device = TunTapInterface::new(device_name, Medium::Ip).unwrap();
         let fd = device.as_raw_fd();
 
-        let seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .subsec_nanos();
+        let seed = rng.r#gen();
 
         let device = Tracer::new(device, |_timestamp, printer| {
             trace!("{}", printer);
@@ -354,7 +476,7 @@ impl NetworkWorker {
             Medium::Ip => Config::new(smoltcp::wire::HardwareAddress::Ip),
             Medium::Ieee802154 => todo!(),
         };
-        config.random_seed = rand::random();
+        config.random_seed = rng.r#gen();
 
         let mut iface = Interface::new(config, &mut device, Instant::now());
         iface.set_any_ip(true);
@@ -409,23 +531,21 @@ impl Worker for NetworkWorker {
     fn run_payload(&self) -> Result<(), WorkerError> {
         info!("{self}");
 
-        let Workload::Network {
+        let Some(Workload::Network {
             server,
             address,
             target_port,
             ..
-        } = self.workload.workload
+        }) = self.workload.workload
         else {
             unreachable!()
         };
 
         if server {
-            let _ = self.start_server(address, target_port);
+            self.start_server(address, target_port)
         } else {
-            let _ = self.start_client(address, target_port);
+            self.start_client(address, target_port)
         }
-
-        Ok(())
     }
 }
 
@@ -1,13 +1,24 @@
-use std::{fmt::Display, process::Command, thread, time};
+use std::{
+    fmt::Display,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
 
 use core_affinity::CoreId;
 use fork::{Fork, fork};
 use log::{info, warn};
 use nix::{sys::wait::waitpid, unistd::Pid};
-use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use rand::{Rng, distributions::Alphanumeric};
 use rand_distr::Exp;
 
-use crate::{BaseConfig, Worker, WorkerError, Workload, WorkloadConfig};
+use crate::{
+    BaseConfig, Distribution, Worker, WorkerError, Workload, WorkloadConfig,
+    events::{self, EventKind},
+    probes,
+    rate::Arrivals,
+    stats::WorkerStats,
+};
 
 #[derive(Debug, Clone)]
 pub struct ProcessesWorker {
@@ -16,46 +27,103 @@ pub struct ProcessesWorker {
 }
 
 impl ProcessesWorker {
-    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        stats: &'static WorkerStats,
+    ) -> Self {
         ProcessesWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload,
         }
     }
 
-    fn spawn_process(&self, lifetime: u64) -> Result<(), WorkerError> {
-        let Workload::Processes {
-            arrival_rate: _,
-            departure_rate: _,
-            random_process,
-        } = self.workload.workload
-        else {
-            unreachable!()
-        };
-        let BaseConfig { cpu, process } = self.config;
+    /// Spawn a process living for `lifetime` milliseconds. If `uniq_arg` is
+    /// specified, the stub binary is executed with it as an argument.
+    ///
+    /// Latency is measured from the `intended` start until the process has
+    /// exited. A forked process sleeps for its lifetime, which is not
+    /// counted, while the stub binary exits right away.
+    fn spawn_process(
+        &self,
+        lifetime: u64,
+        uniq_arg: Option<String>,
+        intended: Instant,
+    ) -> Result<(), WorkerError> {
+        let BaseConfig {
+            cpu,
+            process,
+            stats,
+        } = self.config;
 
-        if random_process {
-            let uniq_arg: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(7)
-                .map(char::from)
+        if let Some(uniq_arg) = uniq_arg {
+            // The correlation tag goes into arguments to be seen by monitors
+            let tag = events::tag();
+            let args: Vec<String> = [Some(uniq_arg), tag.clone()]
+                .into_iter()
+                .flatten()
                 .collect();
-            let _res = Command::new("stub").arg(uniq_arg).output().unwrap();
+
+            let child = Command::new("stub")
+                .args(&args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn();
+
+            match child {
+                Ok(child) => {
+                    stats.success();
+                    probes::process_spawned(cpu.id, process, child.id() as i32);
+                    if let Some(tag) = tag {
+                        let argv = [String::from("stub")].into_iter();
+                        events::record_tagged(
+                            tag,
+                            EventKind::Process {
+                                child: child.id() as i32,
+                                argv: argv.chain(args).collect(),
+                            },
+                        );
+                    }
+                    let _res = child.wait_with_output();
+                    stats.latency(intended.elapsed());
+                    stats.close();
+                }
+                Err(e) => stats.failure(e.raw_os_error().unwrap_or(0)),
+            }
             Ok(())
         } else {
             match fork() {
                 Ok(Fork::Parent(child)) => {
+                    stats.success();
+                    probes::process_spawned(cpu.id, process, child);
+                    events::record(EventKind::Process {
+                        child,
+                        argv: vec![],
+                    });
                     info!("Parent: child {}", child);
                     waitpid(Pid::from_raw(child), None).unwrap();
+                    stats.latency(
+                        intended
+                            .elapsed()
+                            .saturating_sub(Duration::from_millis(lifetime)),
+                    );
+                    stats.close();
                     Ok(())
                 }
                 Ok(Fork::Child) => {
                     info!("{}-{}: Child start, {}", cpu.id, process, lifetime);
-                    thread::sleep(time::Duration::from_millis(lifetime));
+                    thread::sleep(Duration::from_millis(lifetime));
                     info!("{}-{}: Child stop", cpu.id, process);
                     Ok(())
                 }
                 Err(_) => {
+                    stats.failure(nix::errno::errno());
                     warn!("Failed");
                     Ok(())
                 }
@@ -68,28 +136,68 @@ impl Worker for ProcessesWorker {
     fn run_payload(&self) -> Result<(), WorkerError> {
         info!("{self}");
 
-        let Workload::Processes {
+        let Some(Workload::Processes {
             arrival_rate,
             departure_rate,
-            random_process: _,
-        } = self.workload.workload
+            ref lifetime,
+            random_process,
+        }) = self.workload.workload
         else {
             unreachable!()
         };
 
+        let lifetime = lifetime
+            .as_ref()
+            .map(Distribution::sampler)
+            .transpose()
+            .map_err(|e| WorkerError::InternalWithMessage(e.to_string()))?;
+
+        let mut rng =
+            self.workload.rng(self.config.cpu.id, self.config.process);
+        let mut arrivals = Arrivals::new(&self.workload, arrival_rate);
+
+        // Intended start of the next process, processes are spawned at
+        // absolute deadlines to not accumulate drift
+        let mut intended = Instant::now();
+
         thread::scope(|s| {
             loop {
-                let lifetime: f64 =
-                    thread_rng().sample(Exp::new(departure_rate).unwrap());
+                let lifetime: f64 = match &lifetime {
+                    Some(lifetime) => rng.sample(lifetime).max(0.0),
+                    None => {
+                        let departure_rate =
+                            self.workload.departure_rate(departure_rate);
+                        rng.sample(Exp::new(departure_rate).unwrap())
+                    }
+                };
+
+                let uniq_arg: Option<String> = random_process.then(|| {
+                    (&mut rng)
+                        .sample_iter(&Alphanumeric)
+                        .take(7)
+                        .map(char::from)
+                        .collect()
+                });
 
                 let worker = self;
 
                 s.spawn(move || {
-                    worker.spawn_process((lifetime * 1000.0).round() as u64)
+                    worker.spawn_process(
+                        (lifetime * 1000.0).round() as u64,
+                        uniq_arg,
+                        intended,
+                    )
                 });
 
-                let interval: f64 =
-                    thread_rng().sample(Exp::new(arrival_rate).unwrap());
+                let interval = arrivals.next(&mut rng);
+
+                // The schedule has ended, nothing to spawn until the run ends
+                if interval.is_infinite() {
+                    loop {
+                        thread::sleep(Duration::MAX);
+                    }
+                }
+
                 info!(
                     "{}-{}: Interval {}, rounded {}, lifetime {}, rounded {}",
                     self.config.cpu.id,
@@ -99,9 +207,10 @@ impl Worker for ProcessesWorker {
                     lifetime,
                     (lifetime * 1000.0).round() as u64
                 );
-                thread::sleep(time::Duration::from_millis(
-                    (interval * 1000.0).round() as u64,
-                ));
+                intended += Duration::from_secs_f64(interval);
+                thread::sleep(
+                    intended.saturating_duration_since(Instant::now()),
+                );
                 info!(
                     "{}-{}: Continue",
                     self.config.cpu.id, self.config.process
@@ -3,10 +3,13 @@ use std::collections::HashMap;
 use std::ffi::CString;
 
 use super::{SysCaller, get_argument};
+use crate::events;
 use syscalls::{self, Sysno};
 
 #[derive(Debug)]
 pub struct ChmodCall {
+    /// Path from the arguments, the tag is embedded into it.
+    pub base: CString,
     pub pathname: CString,
     pub mode: usize,
 }
@@ -21,11 +24,19 @@ impl ChmodCall {
             (S_ISVTX | S_IRWXU | S_IRWXG | S_IRWXO) as usize,
         );
 
-        Self { pathname, mode }
+        Self {
+            base: pathname.clone(),
+            pathname,
+            mode,
+        }
     }
 }
 
 impl SysCaller for ChmodCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+    }
+
     fn call(&self) -> Result<usize, syscalls::Errno> {
         unsafe {
             syscalls::syscall!(Sysno::chmod, self.pathname.as_ptr(), self.mode)
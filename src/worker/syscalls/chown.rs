@@ -2,10 +2,13 @@ use std::collections::HashMap;
 use std::ffi::CString;
 
 use super::{SysCaller, get_argument};
+use crate::events;
 use syscalls::{self, Sysno, syscall};
 
 #[derive(Debug)]
 pub struct ChownCall {
+    /// Path from the arguments, the tag is embedded into it.
+    pub base: CString,
     pub pathname: CString,
     pub owner: usize,
     pub group: usize,
@@ -19,6 +22,7 @@ impl ChownCall {
         let group = get_argument(chown_args, "group", 0);
 
         Self {
+            base: pathname.clone(),
             pathname,
             owner,
             group,
@@ -27,6 +31,10 @@ impl ChownCall {
 }
 
 impl SysCaller for ChownCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+    }
+
     fn call(&self) -> Result<usize, syscalls::Errno> {
         unsafe {
             syscall!(
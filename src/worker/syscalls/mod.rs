@@ -18,16 +18,15 @@ mod socket;
 mod unlink;
 mod unshare;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::str::FromStr;
 use std::time::Instant;
-use std::{fmt::Display, thread, time};
 
 use core_affinity::CoreId;
 use enum_dispatch::enum_dispatch;
 use log::{debug, error, info, trace};
-use rand::{Rng, thread_rng};
-use rand_distr::Exp;
 use syscalls::{Errno, Sysno};
 
 use crate::worker::syscalls::accept::AcceptCall;
@@ -49,7 +48,13 @@ use crate::worker::syscalls::setuid::SetuidCall;
 use crate::worker::syscalls::socket::SocketCall;
 use crate::worker::syscalls::unlink::UnlinkCall;
 use crate::worker::syscalls::unshare::UnshareCall;
-use crate::{BaseConfig, Worker, WorkerError, Workload, WorkloadConfig};
+use crate::{
+    BaseConfig, Worker, WorkerError, Workload, WorkloadConfig,
+    events::{self, EventKind},
+    pacing::Pacer,
+    probes,
+    stats::WorkerStats,
+};
 
 #[derive(Debug, Clone)]
 pub struct SyscallsWorker {
@@ -58,9 +63,18 @@ pub struct SyscallsWorker {
 }
 
 impl SyscallsWorker {
-    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        stats: &'static WorkerStats,
+    ) -> Self {
         SyscallsWorker {
-            config: BaseConfig { cpu, process },
+            config: BaseConfig {
+                cpu,
+                process,
+                stats,
+            },
             workload,
         }
     }
@@ -73,12 +87,13 @@ impl Worker for SyscallsWorker {
         let mut counter = 0;
         let mut start = Instant::now();
 
-        let Workload::Syscalls {
+        let Some(Workload::Syscalls {
             arrival_rate,
             tight_loop,
+            pacing,
             syscall_nr,
             syscall_args,
-        } = &self.workload.workload
+        }) = &self.workload.workload
         else {
             unreachable!()
         };
@@ -90,13 +105,33 @@ impl Worker for SyscallsWorker {
             return Err(WorkerError::Internal);
         };
 
-        let exp = Exp::new(*arrival_rate).unwrap();
-        let rng = thread_rng();
-        let mut rng_iter = rng.sample_iter(exp);
+        let rng = self.workload.rng(self.config.cpu.id, self.config.process);
+
+        // A tight loop is not paced at all
+        let mut pacer = match tight_loop {
+            true => None,
+            false => Some(
+                Pacer::new(
+                    &self.workload,
+                    *arrival_rate,
+                    pacing,
+                    rng,
+                    self.config.stats,
+                )
+                .map_err(|e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "Cannot set up pacing: {e}"
+                    ))
+                })?,
+            ),
+        };
 
         let syscall = Sysno::from(*syscall_nr);
         info!("Running syscall {syscall}");
 
+        // Intended start times of syscalls due
+        let mut due = vec![];
+
         loop {
             if start.elapsed().as_secs() > 10 {
                 info!(
@@ -108,41 +143,78 @@ impl Worker for SyscallsWorker {
                 counter = 0;
             }
 
-            counter += 1;
-            // Do the syscall directly, without spawning a thread (it would
-            // introduce too much overhead for a quick syscall).
-            match caller.call() {
-                Ok(_) => trace!(
-                    "{}-{}: Success",
-                    self.config.cpu.id, self.config.process
-                ),
-                Err(e) => debug!(
-                    "{}-{}: Error: {:?}",
-                    self.config.cpu.id, self.config.process, e
-                ),
-            }
-            // If running in a tight loop, go to the next iteration
-            if *tight_loop {
-                continue;
-            }
+            // Wait until the next syscall is due, the rate might be changed
+            // by the current scenario phase or schedule. If the worker is
+            // late, few syscalls are due at once. In a tight loop syscalls
+            // are due right away.
+            match &mut pacer {
+                Some(pacer) => pacer.wait(&mut due).map_err(|e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "Pacing failed: {e}"
+                    ))
+                })?,
+                None => {
+                    due.clear();
+                    due.push(Instant::now());
+                }
+            };
+
+            for start in due.iter() {
+                counter += 1;
+                let tag = next_tag(&mut caller);
 
-            // Otherwise calculate waiting time
-            let interval: f64 = rng_iter.next().unwrap();
-            trace!(
-                "{}-{}: Interval {}, rounded {}",
-                self.config.cpu.id,
-                self.config.process,
-                interval,
-                (interval * 1000000.0).round() as u64
-            );
-            thread::sleep(time::Duration::from_nanos(
-                (interval * 1000000.0).round() as u64,
-            ));
-            trace!("{}-{}: Continue", self.config.cpu.id, self.config.process);
+                // Do the syscall directly, without spawning a thread (it
+                // would introduce too much overhead for a quick syscall).
+                let result = caller.call();
+                self.config.stats.record(&result);
+                self.config.stats.latency(start.elapsed());
+                let ret = match result {
+                    Ok(ret) => ret as i64,
+                    Err(e) => -(e.into_raw() as i64),
+                };
+                probes::syscall_issued(
+                    self.config.cpu.id,
+                    self.config.process,
+                    *syscall_nr,
+                    ret,
+                );
+                if let Some(tag) = tag {
+                    events::record_tagged(
+                        tag,
+                        EventKind::Syscall {
+                            sysno: *syscall_nr,
+                            name: Cow::Borrowed(syscall.name()),
+                            ret,
+                        },
+                    );
+                }
+                match result {
+                    Ok(_) => trace!(
+                        "{}-{}: Success",
+                        self.config.cpu.id, self.config.process
+                    ),
+                    Err(e) => debug!(
+                        "{}-{}: Error: {:?}",
+                        self.config.cpu.id, self.config.process, e
+                    ),
+                }
+            }
         }
     }
 }
 
+/// Correlation tag for the next call, if events are logged. It's embedded
+/// into the call only if asked for, otherwise the call stays as configured.
+fn next_tag(caller: &mut SysCallerEnum) -> Option<String> {
+    let tag = events::tag();
+    if let Some(tag) = &tag
+        && events::tag_paths()
+    {
+        caller.tag(tag);
+    }
+    tag
+}
+
 impl Display for SyscallsWorker {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.config)
@@ -179,9 +251,38 @@ trait SysCaller {
     fn init(&mut self) -> Result<usize, Errno> {
         Ok(0)
     }
+
+    /// Embed the correlation tag into the next call where possible, e.g.
+    /// into the file name.
+    fn tag(&mut self, _tag: &str) {}
+
     fn call(&self) -> Result<usize, Errno>;
 }
 
+/// Syscalls with a dedicated implementation, any other syscall is invoked
+/// without arguments.
+pub const SUPPORTED: &[Sysno] = &[
+    Sysno::open,
+    Sysno::openat,
+    Sysno::socket,
+    Sysno::connect,
+    Sysno::listen,
+    Sysno::accept,
+    Sysno::accept4,
+    Sysno::setuid,
+    Sysno::setreuid,
+    Sysno::setresuid,
+    Sysno::mmap,
+    Sysno::mount,
+    Sysno::unlink,
+    Sysno::unshare,
+    Sysno::chown,
+    Sysno::chmod,
+    Sysno::prctl,
+    Sysno::ioctl,
+    Sysno::capset,
+];
+
 impl SysCallerEnum {
     fn new(syscall: Sysno, syscall_args: &HashMap<String, String>) -> Self {
         match syscall {
@@ -229,3 +330,69 @@ fn get_argument<T: FromStr>(
         default
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventSink;
+    use nix::unistd::getpid;
+    use std::{env, fs};
+
+    fn sink(tag_paths: bool) -> EventSink {
+        let path = env::temp_dir()
+            .join(format!("berserker-syscalls-{}.jsonl", std::process::id()));
+
+        EventSink {
+            path: path.to_string_lossy().into_owned(),
+            group: String::from("syscalls"),
+            cpu: 0,
+            process: 0,
+            tag_paths,
+        }
+    }
+
+    #[test]
+    fn test_tag_paths() {
+        // Logging events alone doesn't change the call
+        let events = sink(false);
+        events::open(&events, getpid()).unwrap();
+
+        let mut caller = SysCallerEnum::new(Sysno::open, &HashMap::new());
+        assert!(next_tag(&mut caller).is_some());
+        let SysCallerEnum::OpenCall(call) = &caller else {
+            panic!("wrong caller found");
+        };
+        assert_eq!(call.pathname.to_str(), Ok("/tmp"));
+        assert!(caller.call().is_ok());
+
+        // Tagged files are created in the configured directory and removed
+        // right away
+        let dir = env::temp_dir()
+            .join(format!("berserker-tags-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        events::open(&sink(true), getpid()).unwrap();
+
+        let args = HashMap::from([
+            (String::from("pathname"), dir.to_string_lossy().into_owned()),
+            (
+                String::from("flags"),
+                (libc::O_CREAT | libc::O_WRONLY).to_string(),
+            ),
+            (String::from("mode"), String::from("420")),
+        ]);
+        let mut caller = SysCallerEnum::new(Sysno::open, &args);
+        let tag = next_tag(&mut caller).unwrap();
+        let SysCallerEnum::OpenCall(call) = &caller else {
+            panic!("wrong caller found");
+        };
+        assert_eq!(
+            call.pathname.to_str(),
+            Ok(dir.join(&tag).to_str().unwrap())
+        );
+        assert!(caller.call().is_ok());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir(&dir).unwrap();
+        fs::remove_file(&events.path).unwrap();
+    }
+}
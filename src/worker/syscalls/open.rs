@@ -5,9 +5,12 @@ use syscalls::{Errno, Sysno, syscall};
 
 use super::SysCaller;
 use super::get_argument;
+use crate::events;
 
 #[derive(Debug)]
 pub struct OpenCall {
+    /// Path from the arguments, the tag is embedded into it.
+    pub base: CString,
     pub pathname: CString,
     pub flags: usize,
     pub mode: usize,
@@ -21,6 +24,7 @@ impl OpenCall {
         let mode = get_argument(open_args, "mode", 0);
 
         Self {
+            base: pathname.clone(),
             pathname,
             flags,
             mode,
@@ -29,6 +33,10 @@ impl OpenCall {
 }
 
 impl SysCaller for OpenCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+    }
+
     fn call(&self) -> Result<usize, Errno> {
         let res = unsafe {
             syscall!(Sysno::open, self.pathname.as_ptr(), self.flags, self.mode)
@@ -37,6 +45,10 @@ impl SysCaller for OpenCall {
         if let Ok(fd) = res {
             // Close file descriptor
             unsafe { File::from_raw_fd(fd as i32) };
+
+            if self.flags & libc::O_CREAT as usize != 0 {
+                events::remove_tagged(&self.base, &self.pathname);
+            }
         }
 
         res
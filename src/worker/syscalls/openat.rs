@@ -3,11 +3,15 @@ use std::{ffi::CString, fs::File, os::fd::FromRawFd};
 
 use super::SysCaller;
 use super::get_argument;
+use crate::events;
 use syscalls::{Errno, Sysno, syscall};
 
 #[derive(Debug)]
 pub struct OpenatCall {
     pub dirfd: usize,
+
+    /// Path from the arguments, the tag is embedded into it.
+    pub base: CString,
     pub pathname: CString,
     pub flags: usize,
     pub mode: usize,
@@ -26,6 +30,7 @@ impl OpenatCall {
 
         Self {
             dirfd,
+            base: pathname.clone(),
             pathname,
             flags,
             mode,
@@ -34,6 +39,10 @@ impl OpenatCall {
 }
 
 impl SysCaller for OpenatCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+    }
+
     fn call(&self) -> Result<usize, Errno> {
         let res = unsafe {
             syscall!(
@@ -48,6 +57,10 @@ impl SysCaller for OpenatCall {
         if let Ok(fd) = res {
             // Close file descriptor
             unsafe { File::from_raw_fd(fd as i32) };
+
+            if self.flags & libc::O_CREAT as usize != 0 {
+                events::remove_tagged(&self.base, &self.pathname);
+            }
         }
 
         res
@@ -3,7 +3,6 @@ use std::collections::HashMap;
 use std::{fs::File, os::fd::FromRawFd};
 
 use super::{SysCaller, get_argument};
-use log::info;
 use syscalls::{Errno, Sysno, syscall};
 
 #[derive(Debug)]
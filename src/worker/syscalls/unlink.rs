@@ -2,10 +2,13 @@ use std::collections::HashMap;
 use std::ffi::CString;
 
 use super::{SysCaller, get_argument};
+use crate::events;
 use syscalls::{Errno, Sysno, syscall};
 
 #[derive(Debug)]
 pub struct UnlinkCall {
+    /// Path from the arguments, the tag is embedded into it.
+    pub base: CString,
     pub pathname: CString,
 }
 
@@ -17,11 +20,18 @@ impl UnlinkCall {
             CString::new("/privileged_dir/file").unwrap(),
         );
 
-        Self { pathname }
+        Self {
+            base: pathname.clone(),
+            pathname,
+        }
     }
 }
 
 impl SysCaller for UnlinkCall {
+    fn tag(&mut self, tag: &str) {
+        self.pathname = events::tagged_path(&self.base, tag);
+    }
+
     fn call(&self) -> Result<usize, Errno> {
         unsafe { syscall!(Sysno::unlink, self.pathname.as_ptr()) }
     }